@@ -0,0 +1,125 @@
+use crate::{
+    error::Error,
+    expand::is_name,
+    grammar::Token,
+    lex::{at_command_start, Lexer},
+    session::Session,
+};
+
+/// Replaces the aliases among the words that start commands with their
+/// values, lexed as if they had been typed. The first word of a value is
+/// itself expanded, unless it's an alias already being expanded, and a value
+/// ending in a blank lets the word after it be expanded too. Quoted words,
+/// such as `\ls` or `'ls'`, aren't aliases.
+pub fn expand(tokens: Vec<Token>, session: &Session) -> Result<Vec<Token>, Error> {
+    if session.aliases.is_empty() {
+        return Ok(tokens);
+    }
+    Ok(expand_tokens(tokens, session, &mut vec![])?.0)
+}
+
+/// Whether `name` can be an alias.
+pub fn is_valid(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains(|c: char| c.is_whitespace() || "/$`=\\'\"|&;()<>".contains(c))
+}
+
+/// Expands `tokens`, skipping the aliases in `active`. Also returns whether
+/// they ended with an alias whose value ends in a blank.
+fn expand_tokens(
+    tokens: Vec<Token>,
+    session: &Session,
+    active: &mut Vec<String>,
+) -> Result<(Vec<Token>, bool), Error> {
+    let mut expanded = vec![];
+    let mut blank = false;
+
+    for token in tokens {
+        let alias = match &token {
+            Token::Input(word) if blank || in_command_position(&expanded) => session
+                .aliases
+                .get(word)
+                .filter(|_| !active.contains(word))
+                .map(|value| (word.clone(), value)),
+            _ => None,
+        };
+        let Some((name, value)) = alias else {
+            blank = false;
+            expanded.push(token);
+            continue;
+        };
+
+        active.push(name);
+        let tokens = Lexer::lex_with(value, &session.options)?;
+        let (tokens, ends_with_alias) = expand_tokens(tokens, session, active)?;
+        active.pop();
+        expanded.extend(tokens);
+        blank = ends_with_alias || value.ends_with([' ', '\t']);
+    }
+
+    Ok((expanded, blank))
+}
+
+/// Whether the next word would be a command name. Assignments, `time` and
+/// `!` may come before it.
+fn in_command_position(tokens: &[Token]) -> bool {
+    let prefix = tokens
+        .iter()
+        .rev()
+        .take_while(|token| match token {
+            Token::Input(word) => {
+                word == "time"
+                    || word == "!"
+                    || word.split_once('=').is_some_and(|(name, _)| is_name(name))
+            }
+            _ => false,
+        })
+        .count();
+    at_command_start(&tokens[..tokens.len() - prefix])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let mut session = Session::new();
+        for (name, value) in [
+            ("ll", "ls -l"),
+            ("ls", "ls --color"),
+            ("sudo", "sudo "),
+            ("a", "b"),
+            ("b", "a"),
+            ("up", "cd ..; ll"),
+        ] {
+            session.aliases.insert(name.into(), value.into());
+        }
+        let expand = |line: &str| {
+            let tokens = expand(Lexer::lex(line).unwrap(), &session).unwrap();
+            tokens
+                .iter()
+                .map(|t| t.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+
+        assert_eq!(expand("ll /tmp"), "ls --color -l /tmp");
+        assert_eq!(expand("echo ll"), "echo ll");
+        assert_eq!(expand("\\ll; 'll'"), "\\ll ; 'll'");
+        assert_eq!(
+            expand("x | ll && X=1 ll"),
+            "x | ls --color -l && X=1 ls --color -l"
+        );
+        assert_eq!(expand("sudo ll ll"), "sudo ls --color -l ll");
+        assert_eq!(expand("a"), "a");
+        assert_eq!(expand("up"), "cd .. ; ls --color -l");
+        assert_eq!(expand("time ll"), "time ls --color -l");
+    }
+
+    #[test]
+    fn test_is_valid() {
+        assert!(is_valid("ll") && is_valid("g.s") && is_valid("-x"));
+        assert!(!is_valid("") && !is_valid("a/b") && !is_valid("a b") && !is_valid("$x"));
+    }
+}
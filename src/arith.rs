@@ -0,0 +1,527 @@
+use crate::{error::Error, session::Session};
+
+/// How deeply variables whose values are themselves expressions are followed.
+const MAX_DEPTH: usize = 32;
+
+/// Evaluates an arithmetic expression with 64-bit signed integers, C operator
+/// precedence and shell variables, as in `$(( ))` and `(( ))`. Assignments
+/// are made to the session.
+pub fn evaluate(expression: &str, session: &mut Session) -> Result<i64, Error> {
+    evaluate_at_depth(expression, session, 0)
+}
+
+fn evaluate_at_depth(expression: &str, session: &mut Session, depth: usize) -> Result<i64, Error> {
+    if depth > MAX_DEPTH {
+        return Err(Error::Arithmetic(format!(
+            "{}: expression recursion level exceeded",
+            expression
+        )));
+    }
+
+    let tokens = tokenize(expression)?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+
+    let mut parser = ExprParser { tokens, i: 0 };
+    let expr = parser.comma()?;
+    if let Some(token) = parser.tokens.get(parser.i) {
+        return Err(Error::Arithmetic(format!(
+            "{}: syntax error (error token is \"{}\")",
+            expression, token
+        )));
+    }
+
+    Evaluator { session, depth }.eval(&expr)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ArithToken {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+impl std::fmt::Display for ArithToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ArithToken::Number(n) => write!(f, "{}", n),
+            ArithToken::Name(name) => write!(f, "{}", name),
+            ArithToken::Op(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+/// Operators, longest first so that they're matched greedily.
+const OPERATORS: &[&str] = &[
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=",
+    "*=", "/=", "%=", "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "&", "^", "|", "!", "~",
+    "?", ":", "=", ",", "(", ")",
+];
+
+fn tokenize(expression: &str) -> Result<Vec<ArithToken>, Error> {
+    let chars = expression.chars().collect::<Vec<_>>();
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let len = chars[i..]
+                .iter()
+                .take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '#' | '@'))
+                .count();
+            let word = chars[i..i + len].iter().collect::<String>();
+            i += len;
+            if c.is_ascii_digit() {
+                tokens.push(ArithToken::Number(parse_number(&word)?));
+            } else {
+                tokens.push(ArithToken::Name(word));
+            }
+        } else {
+            let rest = chars[i..].iter().collect::<String>();
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(*op))
+                .ok_or_else(|| {
+                    Error::Arithmetic(format!(
+                        "{}: syntax error: invalid arithmetic operator (error token is \"{}\")",
+                        expression, rest
+                    ))
+                })?;
+            tokens.push(ArithToken::Op(op));
+            i += op.len();
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Parses a decimal, `0x` hexadecimal, `0` octal or `base#digits` literal.
+fn parse_number(word: &str) -> Result<i64, Error> {
+    let invalid = || Error::Arithmetic(format!("{}: value too great for base", word));
+
+    let (base, digits) = if let Some((base, digits)) = word.split_once('#') {
+        let base = base.parse::<u32>().map_err(|_| invalid())?;
+        if !(2..=64).contains(&base) {
+            return Err(Error::Arithmetic(format!(
+                "{}: invalid arithmetic base",
+                word
+            )));
+        }
+        (base, digits)
+    } else if let Some(digits) = word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        (16, digits)
+    } else if word.len() > 1 && word.starts_with('0') {
+        (8, &word[1..])
+    } else {
+        (10, word)
+    };
+
+    if digits.is_empty() {
+        return Err(invalid());
+    }
+
+    digits.chars().try_fold(0i64, |n, c| {
+        // Digits beyond 9 are a-z, then A-Z, `@` and `_`. Up to base 36,
+        // letters of either case mean the same.
+        let digit = match c {
+            '0'..='9' => c as u32 - '0' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 10,
+            'A'..='Z' if base <= 36 => c as u32 - 'A' as u32 + 10,
+            'A'..='Z' => c as u32 - 'A' as u32 + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => return Err(invalid()),
+        };
+        if digit >= base {
+            return Err(invalid());
+        }
+        Ok(n.wrapping_mul(base as i64).wrapping_add(digit as i64))
+    })
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Number(i64),
+    Variable(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    /// `++x` and `--x`, or `x++` and `x--` when `postfix` is set.
+    Increment {
+        name: String,
+        delta: i64,
+        postfix: bool,
+    },
+    Assign {
+        name: String,
+        op: &'static str,
+        value: Box<Expr>,
+    },
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+struct ExprParser {
+    tokens: Vec<ArithToken>,
+    i: usize,
+}
+
+/// Binary operators from lowest to highest precedence, above the ternary.
+const BINARY: &[&[&str]] = &[
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+impl ExprParser {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.i) {
+            Some(ArithToken::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn error(&self) -> Error {
+        match self.tokens.get(self.i) {
+            Some(token) => Error::Arithmetic(format!(
+                "syntax error: operand expected (error token is \"{}\")",
+                token
+            )),
+            None => Error::Arithmetic("syntax error: operand expected".to_string()),
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), Error> {
+        if self.peek_op() == Some(op) {
+            self.i += 1;
+            Ok(())
+        } else {
+            Err(self.error())
+        }
+    }
+
+    fn comma(&mut self) -> Result<Expr, Error> {
+        let mut left = self.assignment()?;
+        while self.peek_op() == Some(",") {
+            self.i += 1;
+            let right = self.assignment()?;
+            left = Expr::Binary(",", Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn assignment(&mut self) -> Result<Expr, Error> {
+        if let (Some(ArithToken::Name(name)), Some(ArithToken::Op(op))) =
+            (self.tokens.get(self.i), self.tokens.get(self.i + 1))
+        {
+            if op.ends_with('=') && !matches!(*op, "==" | "!=" | "<=" | ">=") {
+                let (name, op) = (name.clone(), *op);
+                self.i += 2;
+                let value = self.assignment()?;
+                return Ok(Expr::Assign {
+                    name,
+                    op,
+                    value: Box::new(value),
+                });
+            }
+        }
+        self.conditional()
+    }
+
+    fn conditional(&mut self) -> Result<Expr, Error> {
+        let condition = self.binary(0)?;
+        if self.peek_op() != Some("?") {
+            return Ok(condition);
+        }
+        self.i += 1;
+        let then = self.comma()?;
+        self.expect(":")?;
+        let otherwise = self.conditional()?;
+        Ok(Expr::Conditional(
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, Error> {
+        let Some(ops) = BINARY.get(level) else {
+            return self.power();
+        };
+        let mut left = self.binary(level + 1)?;
+        while let Some(op) = self.peek_op().filter(|op| ops.contains(op)) {
+            self.i += 1;
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn power(&mut self) -> Result<Expr, Error> {
+        let base = self.unary()?;
+        if self.peek_op() == Some("**") {
+            self.i += 1;
+            let exponent = self.power()?;
+            return Ok(Expr::Binary("**", Box::new(base), Box::new(exponent)));
+        }
+        Ok(base)
+    }
+
+    fn unary(&mut self) -> Result<Expr, Error> {
+        match self.peek_op() {
+            Some(op @ ("++" | "--")) => {
+                self.i += 1;
+                match self.tokens.get(self.i) {
+                    Some(ArithToken::Name(name)) => {
+                        let name = name.clone();
+                        self.i += 1;
+                        Ok(Expr::Increment {
+                            name,
+                            delta: if op == "++" { 1 } else { -1 },
+                            postfix: false,
+                        })
+                    }
+                    // `--5` is just two negations.
+                    _ => {
+                        let operand = self.unary()?;
+                        let sign = &op[..1];
+                        let sign = if sign == "+" { "+" } else { "-" };
+                        Ok(Expr::Unary(
+                            sign,
+                            Box::new(Expr::Unary(sign, Box::new(operand))),
+                        ))
+                    }
+                }
+            }
+            Some(op @ ("+" | "-" | "!" | "~")) => {
+                self.i += 1;
+                let operand = self.unary()?;
+                Ok(Expr::Unary(op, Box::new(operand)))
+            }
+            _ => self.postfix(),
+        }
+    }
+
+    fn postfix(&mut self) -> Result<Expr, Error> {
+        match self.tokens.get(self.i).cloned() {
+            Some(ArithToken::Number(n)) => {
+                self.i += 1;
+                Ok(Expr::Number(n))
+            }
+            Some(ArithToken::Name(name)) => {
+                self.i += 1;
+                match self.peek_op() {
+                    Some(op @ ("++" | "--")) => {
+                        self.i += 1;
+                        Ok(Expr::Increment {
+                            name,
+                            delta: if op == "++" { 1 } else { -1 },
+                            postfix: true,
+                        })
+                    }
+                    _ => Ok(Expr::Variable(name)),
+                }
+            }
+            Some(ArithToken::Op("(")) => {
+                self.i += 1;
+                let expr = self.comma()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            _ => Err(self.error()),
+        }
+    }
+}
+
+struct Evaluator<'a> {
+    session: &'a mut Session,
+    depth: usize,
+}
+
+impl Evaluator<'_> {
+    fn variable(&mut self, name: &str) -> Result<i64, Error> {
+        let value = self.session.var(name).unwrap_or_default().to_string();
+        evaluate_at_depth(&value, self.session, self.depth + 1)
+    }
+
+    fn assign(&mut self, name: &str, value: i64) -> Result<i64, Error> {
+        self.session.set_var(name, value.to_string())?;
+        Ok(value)
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<i64, Error> {
+        Ok(match expr {
+            Expr::Number(n) => *n,
+            Expr::Variable(name) => self.variable(name)?,
+            Expr::Unary(op, operand) => {
+                let n = self.eval(operand)?;
+                match *op {
+                    "+" => n,
+                    "-" => n.wrapping_neg(),
+                    "!" => (n == 0) as i64,
+                    "~" => !n,
+                    _ => unreachable!(),
+                }
+            }
+            Expr::Increment {
+                name,
+                delta,
+                postfix,
+            } => {
+                let old = self.variable(name)?;
+                let new = self.assign(name, old.wrapping_add(*delta))?;
+                if *postfix {
+                    old
+                } else {
+                    new
+                }
+            }
+            Expr::Assign { name, op, value } => {
+                let value = self.eval(value)?;
+                let value = match op.strip_suffix('=').unwrap() {
+                    "" => value,
+                    op => {
+                        let old = self.variable(name)?;
+                        binary(op, old, value)?
+                    }
+                };
+                self.assign(name, value)?
+            }
+            Expr::Conditional(condition, then, otherwise) => {
+                if self.eval(condition)? != 0 {
+                    self.eval(then)?
+                } else {
+                    self.eval(otherwise)?
+                }
+            }
+            Expr::Binary("&&", left, right) => {
+                (self.eval(left)? != 0 && self.eval(right)? != 0) as i64
+            }
+            Expr::Binary("||", left, right) => {
+                (self.eval(left)? != 0 || self.eval(right)? != 0) as i64
+            }
+            Expr::Binary(op, left, right) => {
+                let left = self.eval(left)?;
+                let right = self.eval(right)?;
+                binary(op, left, right)?
+            }
+        })
+    }
+}
+
+fn binary(op: &str, left: i64, right: i64) -> Result<i64, Error> {
+    Ok(match op {
+        "," => right,
+        "|" => left | right,
+        "^" => left ^ right,
+        "&" => left & right,
+        "==" => (left == right) as i64,
+        "!=" => (left != right) as i64,
+        "<" => (left < right) as i64,
+        "<=" => (left <= right) as i64,
+        ">" => (left > right) as i64,
+        ">=" => (left >= right) as i64,
+        "<<" => left.wrapping_shl(right as u32),
+        ">>" => left.wrapping_shr(right as u32),
+        "+" => left.wrapping_add(right),
+        "-" => left.wrapping_sub(right),
+        "*" => left.wrapping_mul(right),
+        "/" | "%" if right == 0 => {
+            return Err(Error::Arithmetic("division by 0".to_string()));
+        }
+        "/" => left.wrapping_div(right),
+        "%" => left.wrapping_rem(right),
+        "**" if right < 0 => {
+            return Err(Error::Arithmetic("exponent less than 0".to_string()));
+        }
+        "**" => left.wrapping_pow(right.min(u32::MAX as i64) as u32),
+        _ => unreachable!(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expression: &str) -> Result<i64, Error> {
+        let mut session = Session::new();
+        session.vars.insert("x".to_string(), "5".to_string());
+        session.vars.insert("y".to_string(), "x * 2".to_string());
+        evaluate(expression, &mut session)
+    }
+
+    #[test]
+    fn test_evaluate() {
+        assert_eq!(eval(""), Ok(0));
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("7 / 2 + 7 % 2"), Ok(4));
+        assert_eq!(eval("2 ** 3 ** 2"), Ok(512));
+        assert_eq!(eval("-2 ** 2"), Ok(4));
+        assert_eq!(eval("1 << 4 | 1"), Ok(17));
+        assert_eq!(eval("6 & 3 ^ 1"), Ok(3));
+        assert_eq!(eval("~0"), Ok(-1));
+        assert_eq!(eval("!0 && 2 > 1 || 0"), Ok(1));
+        assert_eq!(eval("1 == 2 ? 3 : 4"), Ok(4));
+        assert_eq!(eval("0x1f + 010 + 2#101 + 64#_"), Ok(31 + 8 + 5 + 63));
+        assert_eq!(eval("x + y"), Ok(15));
+        assert_eq!(eval("nope + 1"), Ok(1));
+        assert_eq!(eval("1, 2"), Ok(2));
+        assert_eq!(eval("--5"), Ok(5));
+        assert_eq!(eval("9223372036854775807 + 1"), Ok(i64::MIN));
+    }
+
+    #[test]
+    fn test_evaluate_assignment() {
+        let mut session = Session::new();
+        assert_eq!(evaluate("i = 2", &mut session), Ok(2));
+        assert_eq!(evaluate("i += 3", &mut session), Ok(5));
+        assert_eq!(evaluate("i <<= 1", &mut session), Ok(10));
+        assert_eq!(evaluate("i++", &mut session), Ok(10));
+        assert_eq!(evaluate("++i", &mut session), Ok(12));
+        assert_eq!(evaluate("i--, i", &mut session), Ok(11));
+        assert_eq!(evaluate("a = b = 4", &mut session), Ok(4));
+        assert_eq!(session.var("i"), Some("11"));
+        assert_eq!(session.var("a"), Some("4"));
+        assert_eq!(session.var("b"), Some("4"));
+
+        // Only the branch taken is evaluated.
+        assert_eq!(
+            evaluate("0 && (c = 1), 1 ? 2 : (d = 1)", &mut session),
+            Ok(2)
+        );
+        assert_eq!(session.var("c"), None);
+        assert_eq!(session.var("d"), None);
+    }
+
+    #[test]
+    fn test_evaluate_errors() {
+        assert_eq!(
+            eval("1 / 0"),
+            Err(Error::Arithmetic("division by 0".to_string()))
+        );
+        assert_eq!(
+            eval("1 % (x - 5)"),
+            Err(Error::Arithmetic("division by 0".to_string()))
+        );
+        assert!(eval("1 +").is_err());
+        assert!(eval("(1").is_err());
+        assert!(eval("1 2").is_err());
+        assert!(eval("08").is_err());
+        assert!(eval("1 $ 2").is_err());
+
+        let mut session = Session::new();
+        session.vars.insert("r".to_string(), "r + 1".to_string());
+        assert!(evaluate("r", &mut session).is_err());
+    }
+}
@@ -2,13 +2,75 @@ use crate::grammar::Token;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Ast {
-    Command { command: Token, args: Vec<Token> },
-    Pipe { left: Box<Ast>, right: Box<Ast> },
-    RedirectOut { left: Box<Ast>, right: Token },
-    RedirectAppend { left: Box<Ast>, right: Token },
-    And { left: Box<Ast>, right: Box<Ast> },
-    Or { left: Box<Ast>, right: Box<Ast> },
-    Sequence { left: Box<Ast>, right: Box<Ast> },
-    Subshell { inner: Box<Ast> },
-    Background { inner: Box<Ast> },
+    Command {
+        /// Leading `NAME=value` words, for the command's environment.
+        assignments: Vec<Token>,
+        command: Token,
+        args: Vec<Token>,
+    },
+    /// A command made only of assignments, which apply to the shell.
+    Assignment {
+        assignments: Vec<Token>,
+    },
+    Pipe {
+        left: Box<Ast>,
+        right: Box<Ast>,
+    },
+    PipeAll {
+        left: Box<Ast>,
+        right: Box<Ast>,
+    },
+    RedirectOut {
+        left: Box<Ast>,
+        right: Token,
+    },
+    RedirectAppend {
+        left: Box<Ast>,
+        right: Token,
+    },
+    HereDoc {
+        left: Box<Ast>,
+        body: String,
+        expand: bool,
+    },
+    HereString {
+        left: Box<Ast>,
+        right: Token,
+    },
+    And {
+        left: Box<Ast>,
+        right: Box<Ast>,
+    },
+    Or {
+        left: Box<Ast>,
+        right: Box<Ast>,
+    },
+    Sequence {
+        left: Box<Ast>,
+        right: Box<Ast>,
+    },
+    Subshell {
+        inner: Box<Ast>,
+    },
+    Group {
+        inner: Box<Ast>,
+    },
+    Background {
+        inner: Box<Ast>,
+    },
+    Not {
+        inner: Box<Ast>,
+    },
+    Time {
+        inner: Box<Ast>,
+        posix: bool,
+    },
+    Arithmetic {
+        expression: String,
+    },
+    /// `name() body`, which defines a function.
+    Function {
+        name: String,
+        body: Box<Ast>,
+    },
 }
@@ -1,23 +1,162 @@
-use std::io::{self, BufRead, Write};
+use std::{io, path::Path};
 
-use shell::{exec::RunningProcess, pipeline::Pipeline};
+use shell::{
+    builtin,
+    editor::Editor,
+    exec::{self, run_trap, status_code, Io, RunningProcess},
+    expand::expand_string,
+    grammar::Token,
+    history,
+    lex::Lexer,
+    parse::Parser,
+    pipeline::Pipeline,
+    prompt,
+    session::Session,
+    signal,
+};
 
 fn main() {
     let mut line = String::new();
+    let mut editor = Editor::new();
+    let mut session = Session::new();
+    session.interactive = true;
+    session.options.emacs = true;
 
+    // A login shell is started with `-l`, or by a name starting with `-`.
+    let mut argv = std::env::args();
+    let mut startup = Startup {
+        login: argv.next().is_some_and(|name| name.starts_with('-')),
+        ..Startup::default()
+    };
+
+    // Other than the startup options, the command line takes the same
+    // options as `set`.
+    let mut args = vec![];
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "-l" | "--login" => startup.login = true,
+            "--norc" => startup.norc = true,
+            "--noprofile" => startup.noprofile = true,
+            "--rcfile" => match argv.next() {
+                Some(file) => startup.rcfile = Some(file),
+                None => {
+                    eprintln!("shell: --rcfile: option requires an argument");
+                    std::process::exit(2);
+                }
+            },
+            _ => args.push(arg),
+        }
+    }
+    if !args.is_empty() {
+        let set = builtin::lookup("set").unwrap();
+        let code = set(&mut session, &args, &mut Io::default()).unwrap_or(2);
+        if code != 0 {
+            std::process::exit(code);
+        }
+    }
+
+    if let Err(e) = signal::init_interactive() {
+        eprintln!("Error: {}", e);
+    }
+
+    for (name, value) in [("PS1", "\\n> "), ("PS2", "> "), ("PS4", "+ ")] {
+        if session.var(name).is_none() {
+            session.vars.insert(name.to_string(), value.to_string());
+        }
+    }
+
+    startup.run(&mut session);
+    if let Some(code) = session.exit {
+        exit(&mut session, code);
+    }
+
+    if session.var("HISTFILE").is_none() {
+        if let Some(home) = session.var("HOME") {
+            let file = format!("{}/.shell_history", home);
+            session.vars.insert("HISTFILE".to_string(), file);
+        }
+    }
+    if let Err(e) = history::load(&mut session) {
+        eprintln!("Error: {}", e);
+    }
+
+    let mut save_history = true;
     loop {
-        print!("\n> ");
-        let _ = io::stdout().flush();
+        line.clear();
+        signal::clear_interrupted();
+
+        // `PROMPT_COMMAND` runs before each prompt, leaving `$?` alone.
+        if let Some(command) = session.var("PROMPT_COMMAND").map(String::from) {
+            let status = session.status;
+            if let Err(e) = Pipeline::run(&command, &mut session) {
+                eprintln!("Error: {}", e);
+            }
+            session.status = status;
+        }
+
+        let ps1 = prompt(&mut session, "PS1");
+        match editor.read_line(&ps1, &mut line, &session) {
+            Ok(true) => {}
+            Ok(false) => {
+                let code = session.status;
+                exit(&mut session, code)
+            }
+            // Ctrl-C abandons the line.
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                exit(&mut session, 1);
+            }
+        }
+
+        // Keep reading until the command is complete, including any
+        // here-documents.
+        let mut interrupted = false;
+        while incomplete(&line) {
+            let ps2 = prompt(&mut session, "PS2");
+            match editor.read_line(&ps2, &mut line, &session) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(_) => {
+                    interrupted = true;
+                    break;
+                }
+            }
+        }
+        if interrupted {
+            continue;
+        }
 
-        io::stdin().lock().read_line(&mut line).unwrap();
+        // History references are expanded before the line is lexed, and the
+        // result is echoed.
+        let line = match session.history.expand(line.trim_end()) {
+            Ok(Some(expanded)) => {
+                println!("{}", expanded);
+                expanded
+            }
+            Ok(None) => line.trim_end().to_string(),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                continue;
+            }
+        };
+        // If the history can't be saved, it's only kept in memory from then
+        // on, rather than failing again on every line.
+        if let Err(e) = history::add(&mut session, &line, save_history) {
+            eprintln!("Error: {}", e);
+            save_history = false;
+        }
+
+        // There's nothing to run on a blank line or one with only a comment.
+        if Lexer::lex(&line).is_ok_and(|tokens| tokens.is_empty()) {
+            continue;
+        }
 
-        match Pipeline::run(line.trim()) {
+        match Pipeline::run(line.trim(), &mut session) {
             Ok(p) => match p {
                 RunningProcess::Foreground(status) => {
                     if !status.success() {
-                        if let Some(code) = status.code() {
-                            eprintln!("Error: {}", code);
-                        }
+                        eprintln!("Error: {}", status_code(status));
                     }
                 }
                 RunningProcess::Background => {
@@ -27,6 +166,96 @@ fn main() {
             Err(e) => eprintln!("Error: {}", e),
         }
 
-        line.clear();
+        if let Some(code) = session.exit {
+            exit(&mut session, code);
+        }
+    }
+}
+
+/// Which startup files to read, from the command line.
+#[derive(Default)]
+struct Startup {
+    login: bool,
+    norc: bool,
+    noprofile: bool,
+    rcfile: Option<String>,
+}
+
+impl Startup {
+    /// Reads the startup files as bash does. A login shell reads
+    /// `/etc/shell_profile` and `~/.shell_profile`, and any other shell
+    /// reads `/etc/shellrc` and `~/.shellrc`, or the file given with
+    /// `--rcfile`. Then the file named by `$ENV` is read, as POSIX specifies.
+    ///
+    /// `/etc/profile` and `~/.profile` aren't read, as they're written for
+    /// `sh` and use syntax that this shell doesn't have.
+    fn run(&self, session: &mut Session) {
+        let home = session.var("HOME").map(String::from);
+        let home = |name: &str| home.as_ref().map(|home| Path::new(home).join(name));
+
+        if self.login && !self.noprofile {
+            source(session, Path::new("/etc/shell_profile"));
+            if let Some(profile) = home(".shell_profile") {
+                source(session, &profile);
+            }
+        } else if !self.login && !self.norc {
+            match &self.rcfile {
+                Some(rcfile) => source(session, Path::new(rcfile)),
+                None => {
+                    source(session, Path::new("/etc/shellrc"));
+                    if let Some(rc) = home(".shellrc") {
+                        source(session, &rc);
+                    }
+                }
+            }
+        }
+
+        if let Some(env) = session.var("ENV").map(String::from) {
+            let env = expand_string(&Token::Input(env.clone()), session).unwrap_or(env);
+            if !env.is_empty() {
+                source(session, Path::new(&env));
+            }
+        }
+    }
+}
+
+/// Runs a startup file in the session, if it exists. Errors in it are
+/// reported with the line they're on.
+fn source(session: &mut Session, path: &Path) {
+    let script = match std::fs::read_to_string(path) {
+        Ok(script) => script,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+        Err(e) => {
+            eprintln!("shell: {}: {}", path.display(), e);
+            return;
+        }
+    };
+    let name = path.display().to_string();
+    if let Err(e) = exec::source(&name, &script, &[], session, &mut Io::default()) {
+        eprintln!("shell: {}: {}", name, e);
+    }
+}
+
+/// Whether the command read so far needs more lines.
+fn incomplete(line: &str) -> bool {
+    let line = line.trim_start();
+    match Lexer::lex(line) {
+        Ok(tokens) if tokens.is_empty() => false,
+        Ok(tokens) => Parser::parse(&tokens).is_err_and(|e| e.is_incomplete()),
+        Err(e) => e.is_incomplete(),
+    }
+}
+
+/// Expands the prompt in the variable `name`.
+fn prompt(session: &mut Session, name: &str) -> String {
+    let ps = session.var(name).unwrap_or_default().to_string();
+    prompt::expand(&ps, session)
+}
+
+/// Runs the `EXIT` trap and exits the shell.
+fn exit(session: &mut Session, code: i32) -> ! {
+    if let Err(e) = run_trap("EXIT", session) {
+        eprintln!("Error: {}", e);
     }
+    std::process::exit(code)
 }
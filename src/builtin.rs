@@ -0,0 +1,1189 @@
+use std::{fs, io, io::Write, path::Path};
+
+use crate::{
+    alias,
+    complete::{self, Spec, ACTIONS, OPTIONS},
+    exec::{self, Io},
+    expand::{is_name, quote},
+    history::format_time,
+    session::{Options, Session},
+    signal,
+};
+
+pub type Builtin = fn(&mut Session, &[String], &mut Io) -> io::Result<i32>;
+
+/// The names of the builtins, for completion.
+pub const NAMES: &[&str] = &[
+    ".", "alias", "cd", "compgen", "complete", "exit", "export", "history", "readonly", "return",
+    "set", "shopt", "source", "trap", "unalias", "unset",
+];
+
+pub fn lookup(name: &str) -> Option<Builtin> {
+    match name {
+        "." | "source" => Some(source),
+        "alias" => Some(alias),
+        "cd" => Some(cd),
+        "compgen" => Some(compgen),
+        "complete" => Some(complete),
+        "exit" => Some(exit),
+        "export" => Some(export),
+        "history" => Some(history),
+        "readonly" => Some(readonly),
+        "return" => Some(r#return),
+        "set" => Some(set),
+        "shopt" => Some(shopt),
+        "trap" => Some(trap),
+        "unalias" => Some(unalias),
+        "unset" => Some(unset),
+        _ => None,
+    }
+}
+
+/// Whether `name` is a POSIX special builtin, whose prefix assignments
+/// persist in the shell.
+pub fn is_special(name: &str) -> bool {
+    matches!(
+        name,
+        "." | ":"
+            | "break"
+            | "continue"
+            | "eval"
+            | "exec"
+            | "exit"
+            | "export"
+            | "readonly"
+            | "return"
+            | "set"
+            | "shift"
+            | "times"
+            | "trap"
+            | "unset"
+    )
+}
+
+fn cd(session: &mut Session, args: &[String], io: &mut Io) -> io::Result<i32> {
+    let (dir, print) = match args.first().map(String::as_str) {
+        None => match session.var("HOME") {
+            Some(home) => (home.to_string(), false),
+            None => {
+                writeln!(io.err(), "cd: HOME not set")?;
+                return Ok(1);
+            }
+        },
+        Some("-") => match session.var("OLDPWD") {
+            Some(old) => (old.to_string(), true),
+            None => {
+                writeln!(io.err(), "cd: OLDPWD not set")?;
+                return Ok(1);
+            }
+        },
+        Some(dir) => (dir.to_string(), false),
+    };
+
+    let path = match fs::canonicalize(session.cwd.join(Path::new(&dir))) {
+        Ok(path) if path.is_dir() => path,
+        Ok(_) => {
+            writeln!(io.err(), "cd: {}: Not a directory", dir)?;
+            return Ok(1);
+        }
+        Err(e) => {
+            writeln!(io.err(), "cd: {}: {}", dir, e)?;
+            return Ok(1);
+        }
+    };
+
+    // As in bash, the directory still changes if `PWD` or `OLDPWD` is
+    // readonly, but `cd` fails.
+    let old = std::mem::replace(&mut session.cwd, path);
+    let cwd = session.cwd.to_string_lossy().into_owned();
+    let mut status = 0;
+    for (name, value) in [("OLDPWD", old.to_string_lossy().into_owned()), ("PWD", cwd)] {
+        if let Err(e) = session.set_var(name, value) {
+            writeln!(io.err(), "cd: {}", e)?;
+            status = 1;
+        }
+    }
+
+    if print {
+        writeln!(io.out(), "{}", session.cwd.display())?;
+    }
+
+    Ok(status)
+}
+
+fn exit(session: &mut Session, args: &[String], io: &mut Io) -> io::Result<i32> {
+    let code = match args.first().map(|arg| arg.parse::<i32>()) {
+        None => session.status,
+        Some(Ok(code)) => code & 0xff,
+        Some(Err(_)) => {
+            writeln!(io.err(), "exit: {}: numeric argument required", args[0])?;
+            2
+        }
+    };
+    session.exit = Some(code);
+    Ok(code)
+}
+
+/// `export [-n] [-p] [name[=value] ...]`
+fn export(session: &mut Session, args: &[String], io: &mut Io) -> io::Result<i32> {
+    let (unexport, args) = match args.first().map(String::as_str) {
+        Some("-n") => (true, &args[1..]),
+        Some("-p") => (false, &args[1..]),
+        _ => (false, args),
+    };
+
+    if args.is_empty() {
+        let names = session.exported.iter().cloned().collect();
+        return declare(session, "-x", names, io);
+    }
+
+    let mut code = 0;
+    for arg in args {
+        let Some(name) = assign(session, "export", arg, io)? else {
+            code = 1;
+            continue;
+        };
+        if unexport {
+            session.exported.remove(&name);
+        } else {
+            session.exported.insert(name);
+        }
+    }
+
+    Ok(code)
+}
+
+/// `history [-c] [-d offset] [-w [file]] [n]`
+///
+/// Lists the last `n` lines of the history, or all of it, with the time each
+/// was entered if `HISTTIMEFORMAT` is set.
+fn history(session: &mut Session, args: &[String], io: &mut Io) -> io::Result<i32> {
+    match args.first().map(String::as_str) {
+        Some("-c") => {
+            session.history.clear();
+            return Ok(0);
+        }
+        Some("-d") => {
+            let Some(offset) = args.get(1) else {
+                writeln!(io.err(), "history: -d: option requires an argument")?;
+                return Ok(2);
+            };
+            // A negative offset counts back from the end of the history.
+            let end = session.history.first() + session.history.len();
+            let number = match offset.parse::<i64>() {
+                Ok(n) if n < 0 => end.checked_sub(n.unsigned_abs() as usize),
+                Ok(n) => Some(n as usize),
+                Err(_) => None,
+            };
+            if !number.is_some_and(|number| session.history.delete(number)) {
+                writeln!(
+                    io.err(),
+                    "history: {}: history position out of range",
+                    offset
+                )?;
+                return Ok(1);
+            }
+            return Ok(0);
+        }
+        Some("-w") => {
+            crate::history::write(session, args.get(1).map(Path::new))?;
+            return Ok(0);
+        }
+        _ => {}
+    }
+
+    let entries = session.history.entries();
+    let count = match args.first() {
+        None => entries.len(),
+        Some(arg) => match arg.parse::<usize>() {
+            Ok(count) => count,
+            Err(_) => {
+                writeln!(io.err(), "history: {}: numeric argument required", arg)?;
+                return Ok(2);
+            }
+        },
+    };
+
+    let format = session.var("HISTTIMEFORMAT");
+    let skip = entries.len().saturating_sub(count);
+    for (i, entry) in entries.iter().enumerate().skip(skip) {
+        let time = format.map(|format| format_time(format, entry.time));
+        let number = session.history.first() + i;
+        writeln!(
+            io.out(),
+            "{:5}  {}{}",
+            number,
+            time.unwrap_or_default(),
+            entry.line
+        )?;
+    }
+
+    Ok(0)
+}
+
+/// `readonly [-p] [name[=value] ...]`
+fn readonly(session: &mut Session, args: &[String], io: &mut Io) -> io::Result<i32> {
+    let args = match args.first().map(String::as_str) {
+        Some("-p") => &args[1..],
+        _ => args,
+    };
+
+    if args.is_empty() {
+        let names = session.readonly.iter().cloned().collect();
+        return declare(session, "-r", names, io);
+    }
+
+    let mut code = 0;
+    for arg in args {
+        match assign(session, "readonly", arg, io)? {
+            Some(name) => {
+                session.readonly.insert(name);
+            }
+            None => code = 1,
+        }
+    }
+
+    Ok(code)
+}
+
+/// `unset [-v] name ...`
+fn unset(session: &mut Session, args: &[String], io: &mut Io) -> io::Result<i32> {
+    let (functions, names) = match args.first().map(String::as_str) {
+        Some("-f") => (true, &args[1..]),
+        Some("-v") => (false, &args[1..]),
+        _ => (false, args),
+    };
+
+    let mut code = 0;
+    for name in names {
+        if functions {
+            session.functions.remove(name);
+        } else if session.unset_var(name).is_err() {
+            writeln!(io.err(), "unset: {}: cannot unset: readonly variable", name)?;
+            code = 1;
+        }
+    }
+
+    Ok(code)
+}
+
+/// `alias [-p] [name[=value] ...]`
+fn alias(session: &mut Session, args: &[String], io: &mut Io) -> io::Result<i32> {
+    let args = match args.first().map(String::as_str) {
+        Some("-p") => &args[1..],
+        _ => args,
+    };
+
+    if args.is_empty() {
+        let mut names = session.aliases.keys().collect::<Vec<_>>();
+        names.sort();
+        for name in names {
+            writeln!(io.out(), "alias {}={}", name, quote(&session.aliases[name]))?;
+        }
+        return Ok(0);
+    }
+
+    let mut code = 0;
+    for arg in args {
+        match arg.split_once('=') {
+            Some((name, _)) if !alias::is_valid(name) => {
+                writeln!(io.err(), "alias: `{}': invalid alias name", name)?;
+                code = 1;
+            }
+            Some((name, value)) => {
+                session.aliases.insert(name.to_string(), value.to_string());
+            }
+            None => match session.aliases.get(arg) {
+                Some(value) => writeln!(io.out(), "alias {}={}", arg, quote(value))?,
+                None => {
+                    writeln!(io.err(), "alias: {}: not found", arg)?;
+                    code = 1;
+                }
+            },
+        }
+    }
+
+    Ok(code)
+}
+
+/// `unalias [-a] name ...`
+fn unalias(session: &mut Session, args: &[String], io: &mut Io) -> io::Result<i32> {
+    if args.first().is_some_and(|arg| arg == "-a") {
+        session.aliases.clear();
+        return Ok(0);
+    }
+    if args.is_empty() {
+        writeln!(io.err(), "unalias: usage: unalias [-a] name [name ...]")?;
+        return Ok(2);
+    }
+
+    let mut code = 0;
+    for name in args {
+        if session.aliases.remove(name).is_none() {
+            writeln!(io.err(), "unalias: {}: not found", name)?;
+            code = 1;
+        }
+    }
+
+    Ok(code)
+}
+
+/// `source file [args]` or `. file [args]`
+///
+/// A file name without a slash is looked for in `PATH`, then in the current
+/// directory.
+fn source(session: &mut Session, args: &[String], io: &mut Io) -> io::Result<i32> {
+    let Some(file) = args.first() else {
+        writeln!(io.err(), "source: filename argument required")?;
+        return Ok(2);
+    };
+
+    let path = match file.contains('/') {
+        true => None,
+        false => session.var("PATH").and_then(|path| {
+            path.split(':')
+                .map(|dir| session.cwd.join(dir).join(file))
+                .find(|path| path.is_file())
+        }),
+    };
+    let path = path.unwrap_or_else(|| session.cwd.join(file));
+    let script = match fs::read_to_string(&path) {
+        Ok(script) => script,
+        Err(e) => {
+            writeln!(io.err(), "source: {}: {}", file, e)?;
+            return Ok(1);
+        }
+    };
+
+    let status = exec::source(file, &script, &args[1..], session, io)?;
+    Ok(exec::status_code(status))
+}
+
+/// `return [n]`
+fn r#return(session: &mut Session, args: &[String], io: &mut Io) -> io::Result<i32> {
+    if session.function_depth == 0 && session.source_depth == 0 {
+        writeln!(
+            io.err(),
+            "return: can only `return' from a function or sourced script"
+        )?;
+        return Ok(1);
+    }
+
+    let code = match args.first() {
+        None => session.status,
+        Some(arg) => match arg.parse::<i32>() {
+            Ok(code) => code & 0xff,
+            Err(_) => {
+                writeln!(io.err(), "return: {}: numeric argument required", arg)?;
+                2
+            }
+        },
+    };
+    session.returning = Some(code);
+    Ok(code)
+}
+
+/// Handles a `name[=value]` argument to `export` or `readonly`, assigning
+/// the value if there is one. Returns the name, or `None` if it was invalid.
+fn assign(
+    session: &mut Session,
+    builtin: &str,
+    arg: &str,
+    io: &mut Io,
+) -> io::Result<Option<String>> {
+    let (name, value) = match arg.split_once('=') {
+        Some((name, value)) => (name, Some(value)),
+        None => (arg, None),
+    };
+
+    if !is_name(name) {
+        writeln!(io.err(), "{}: `{}': not a valid identifier", builtin, arg)?;
+        return Ok(None);
+    }
+
+    if let Some(value) = value {
+        if let Err(e) = session.set_var(name, value.to_string()) {
+            writeln!(io.err(), "{}: {}", builtin, e)?;
+            return Ok(None);
+        }
+    }
+
+    Ok(Some(name.to_string()))
+}
+
+/// Lists variables in a form that can be read back in, as `export -p` and
+/// `readonly -p` do.
+fn declare(session: &Session, flag: &str, mut names: Vec<String>, io: &mut Io) -> io::Result<i32> {
+    names.sort();
+    for name in names {
+        match session.var(&name) {
+            Some(value) => {
+                let value = value
+                    .chars()
+                    .flat_map(|c| match c {
+                        '"' | '\\' | '$' | '`' => vec!['\\', c],
+                        c => vec![c],
+                    })
+                    .collect::<String>();
+                writeln!(io.out(), "declare {} {}=\"{}\"", flag, name, value)?;
+            }
+            None => writeln!(io.out(), "declare {} {}", flag, name)?,
+        }
+    }
+    Ok(0)
+}
+
+/// `set [-euxfC] [+euxfC] [-o option] [+o option] [--] [arg ...]`
+///
+/// Any arguments after the options replace the positional parameters.
+fn set(session: &mut Session, args: &[String], io: &mut Io) -> io::Result<i32> {
+    if args.is_empty() {
+        let vars = session
+            .vars
+            .iter()
+            .map(|(name, value)| (name, quote(value)));
+        let arrays = session.arrays.iter().map(|(name, elements)| {
+            let elements = elements.iter().map(|e| quote(e)).collect::<Vec<_>>();
+            (name, format!("({})", elements.join(" ")))
+        });
+        let mut vars = vars.chain(arrays).collect::<Vec<_>>();
+        vars.sort();
+        for (name, value) in vars {
+            writeln!(io.out(), "{}={}", name, value)?;
+        }
+        return Ok(0);
+    }
+
+    let mut i = 0;
+    while let Some(arg) = args.get(i) {
+        let value = match arg.chars().next() {
+            _ if arg == "--" => {
+                i += 1;
+                break;
+            }
+            Some('-') if arg.len() > 1 => true,
+            Some('+') if arg.len() > 1 => false,
+            _ => break,
+        };
+        i += 1;
+
+        for flag in arg.chars().skip(1) {
+            let name = match flag {
+                'o' => match args.get(i) {
+                    Some(name) => {
+                        i += 1;
+                        name.as_str()
+                    }
+                    None => {
+                        list_options(&session.options, value, io)?;
+                        continue;
+                    }
+                },
+                flag => match Options::flag(flag) {
+                    Some(name) => name,
+                    None => {
+                        writeln!(io.err(), "set: {}{}: invalid option", &arg[..1], flag)?;
+                        return Ok(2);
+                    }
+                },
+            };
+            if !set_option(&mut session.options, name, value) {
+                writeln!(io.err(), "set: {}: invalid option name", name)?;
+                return Ok(2);
+            }
+        }
+    }
+
+    if i > 0 && args[i - 1] == "--" || i < args.len() {
+        session.args = args[i..].to_vec();
+    }
+
+    Ok(0)
+}
+
+/// Sets one of the `set -o` options, of which `vi` and `emacs` exclude each
+/// other. Returns `false` if there's no such option.
+fn set_option(options: &mut Options, name: &str, value: bool) -> bool {
+    let Some(option) = options.set(name) else {
+        return false;
+    };
+    *option = value;
+    match name {
+        "vi" if value => options.emacs = false,
+        "emacs" if value => options.vi = false,
+        _ => {}
+    }
+    true
+}
+
+/// Lists the `set -o` options, as a table for `-o` or as commands that
+/// restore them for `+o`.
+fn list_options(options: &Options, table: bool, io: &mut Io) -> io::Result<()> {
+    let mut options = options.clone();
+    for (name, _) in Options::SET {
+        let on = *options.set(name).unwrap();
+        if table {
+            let state = if on { "on" } else { "off" };
+            writeln!(io.out(), "{:<15}\t{}", name, state)?;
+        } else {
+            let flag = if on { '-' } else { '+' };
+            writeln!(io.out(), "set {}o {}", flag, name)?;
+        }
+    }
+    Ok(())
+}
+
+/// `shopt [-o] [-s | -u] [optname ...]`
+///
+/// With `-o`, the options are those of `set -o`.
+fn shopt(session: &mut Session, args: &[String], io: &mut Io) -> io::Result<i32> {
+    let mut set = None;
+    let mut set_options = false;
+    let mut names = args;
+    while let Some(flag) = names.first() {
+        match flag.as_str() {
+            "-s" => set = Some(true),
+            "-u" => set = Some(false),
+            "-o" => set_options = true,
+            _ => break,
+        }
+        names = &names[1..];
+    }
+
+    let names = match (names.is_empty(), set_options) {
+        (true, true) => Options::SET
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .collect(),
+        (true, false) => Options::SHOPT.iter().map(|name| name.to_string()).collect(),
+        (false, _) => names.to_vec(),
+    };
+
+    let mut code = 0;
+    for name in &names {
+        let option = match set_options {
+            true => session.options.set(name),
+            false => session.options.shopt(name),
+        };
+        let Some(option) = option else {
+            writeln!(io.err(), "shopt: {}: invalid shell option name", name)?;
+            code = 1;
+            continue;
+        };
+        match set {
+            Some(value) if set_options => {
+                set_option(&mut session.options, name, value);
+            }
+            Some(value) => *option = value,
+            None => {
+                let state = if *option { "on" } else { "off" };
+                writeln!(io.out(), "{}\t{}", name, state)?;
+            }
+        }
+    }
+
+    Ok(code)
+}
+
+/// `trap [-lp] [[action] sigspec ...]`
+///
+/// An action of `-`, or none with a single signal, resets the signals, and
+/// an empty action ignores them.
+fn trap(session: &mut Session, args: &[String], io: &mut Io) -> io::Result<i32> {
+    let (print, args) = match args.first().map(String::as_str) {
+        Some("-l") => {
+            for (name, number) in signal::names() {
+                writeln!(io.out(), "{:2}) SIG{}", number, name)?;
+            }
+            return Ok(0);
+        }
+        Some("-p") => (true, &args[1..]),
+        Some("--") => (args.len() == 1, &args[1..]),
+        _ => (args.is_empty(), args),
+    };
+
+    if print {
+        let mut names = match args.is_empty() {
+            true => session.traps.keys().cloned().collect::<Vec<_>>(),
+            false => args.iter().filter_map(|arg| trap_name(arg)).collect(),
+        };
+        names.sort_by_key(|name| trap_order(name));
+        for name in names {
+            if let Some(action) = session.traps.get(&name) {
+                writeln!(io.out(), "trap -- {} {}", quote(action), name)?;
+            }
+        }
+        return Ok(0);
+    }
+
+    let (action, specs) = match args[0].as_str() {
+        _ if args.len() == 1 => (None, args),
+        "-" => (None, &args[1..]),
+        _ => (Some(&args[0]), &args[1..]),
+    };
+
+    let mut code = 0;
+    for spec in specs {
+        let Some(name) = trap_name(spec) else {
+            writeln!(io.err(), "trap: {}: invalid signal specification", spec)?;
+            code = 1;
+            continue;
+        };
+        if let Some(number) = signal::number(&name) {
+            let result = match action {
+                None => signal::reset(number, session.interactive),
+                Some(action) if action.is_empty() => signal::ignore(number),
+                Some(_) => signal::catch(number),
+            };
+            if let Err(e) = result {
+                writeln!(io.err(), "trap: {}: {}", spec, e)?;
+                code = 1;
+                continue;
+            }
+        }
+        match action {
+            Some(action) => session.traps.insert(name, action.clone()),
+            None => session.traps.remove(&name),
+        };
+    }
+
+    Ok(code)
+}
+
+/// `complete [-pr] [-abcdefv] [-o option] [-A action] [-W words] [-F function] [name ...]`
+fn complete(session: &mut Session, args: &[String], io: &mut Io) -> io::Result<i32> {
+    let options = match SpecOptions::parse("complete", args) {
+        Ok(options) => options,
+        Err(message) => {
+            writeln!(io.err(), "{}", message)?;
+            return Ok(2);
+        }
+    };
+    let names = options.names;
+
+    if options.remove {
+        match names.is_empty() {
+            true => session.completions.clear(),
+            false => names.iter().for_each(|name| {
+                session.completions.remove(name);
+            }),
+        }
+        return Ok(0);
+    }
+
+    if options.print || args.is_empty() {
+        let mut names = match names.is_empty() {
+            true => session.completions.keys().cloned().collect::<Vec<_>>(),
+            false => names.to_vec(),
+        };
+        names.sort();
+        let mut code = 0;
+        for name in names {
+            match session.completions.get(&name) {
+                Some(spec) => writeln!(io.out(), "{}", complete_command(&name, spec))?,
+                None => {
+                    writeln!(io.err(), "complete: {}: no completion specification", name)?;
+                    code = 1;
+                }
+            }
+        }
+        return Ok(code);
+    }
+
+    if names.is_empty() {
+        writeln!(io.err(), "complete: usage: complete [-pr] [-abcdefv] [-o option] [-A action] [-W words] [-F function] [name ...]")?;
+        return Ok(2);
+    }
+    for name in names {
+        session
+            .completions
+            .insert(name.clone(), options.spec.clone());
+    }
+    Ok(0)
+}
+
+/// `compgen [-abcdefv] [-o option] [-A action] [-W words] [-F function] [-V name] [word]`
+///
+/// Prints the matches for `word` one per line, or with `-V` stores them in an
+/// array.
+fn compgen(session: &mut Session, args: &[String], io: &mut Io) -> io::Result<i32> {
+    let options = match SpecOptions::parse("compgen", args) {
+        Ok(options) => options,
+        Err(message) => {
+            writeln!(io.err(), "{}", message)?;
+            return Ok(2);
+        }
+    };
+    let words = options
+        .names
+        .first()
+        .cloned()
+        .into_iter()
+        .collect::<Vec<_>>();
+
+    let matches = complete::generate(&options.spec, &words, session)
+        .into_iter()
+        .map(|candidate| {
+            let word = complete::unquote(&candidate.text);
+            match candidate.complete {
+                true => word,
+                false => word.strip_suffix('/').map(String::from).unwrap_or(word),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    match options.var {
+        Some(name) => {
+            if let Err(e) = session.set_array(&name, matches.clone()) {
+                writeln!(io.err(), "compgen: {}", e)?;
+                return Ok(1);
+            }
+        }
+        None => {
+            for word in &matches {
+                writeln!(io.out(), "{}", word)?;
+            }
+        }
+    }
+
+    Ok(if matches.is_empty() { 1 } else { 0 })
+}
+
+/// The options `complete` and `compgen` take.
+#[derive(Default)]
+struct SpecOptions<'a> {
+    spec: Spec,
+    print: bool,
+    remove: bool,
+    var: Option<String>,
+    names: &'a [String],
+}
+
+impl<'a> SpecOptions<'a> {
+    /// Parses the options, or returns an error message.
+    fn parse(builtin: &str, args: &'a [String]) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut i = 0;
+        while let Some(arg) = args.get(i) {
+            i += 1;
+            if arg == "--" {
+                break;
+            }
+            if !arg.starts_with('-') || arg.len() == 1 {
+                i -= 1;
+                break;
+            }
+            for flag in arg[1..].chars() {
+                let mut value = || match args.get(i) {
+                    Some(value) => {
+                        i += 1;
+                        Ok(value.clone())
+                    }
+                    None => Err(format!(
+                        "{}: -{}: option requires an argument",
+                        builtin, flag
+                    )),
+                };
+                match flag {
+                    'p' if builtin == "complete" => options.print = true,
+                    'r' if builtin == "complete" => options.remove = true,
+                    'V' if builtin == "compgen" => options.var = Some(value()?),
+                    'W' => options.spec.words = Some(value()?),
+                    'F' => options.spec.function = Some(value()?),
+                    'A' => {
+                        let action = value()?;
+                        if !ACTIONS.iter().any(|(name, _)| *name == action) {
+                            return Err(format!("{}: {}: invalid action name", builtin, action));
+                        }
+                        options.spec.actions.push(action);
+                    }
+                    'o' => {
+                        let option = value()?;
+                        if !OPTIONS.contains(&option.as_str()) {
+                            return Err(format!("{}: {}: invalid option name", builtin, option));
+                        }
+                        options.spec.options.push(option);
+                    }
+                    _ => match ACTIONS.iter().find(|(_, short)| *short == Some(flag)) {
+                        Some((action, _)) => options.spec.actions.push(action.to_string()),
+                        None => return Err(format!("{}: -{}: invalid option", builtin, flag)),
+                    },
+                }
+            }
+        }
+        options.names = &args[i..];
+        Ok(options)
+    }
+}
+
+/// The `complete` command that registers `spec` for `name`, as `complete -p`
+/// prints it.
+fn complete_command(name: &str, spec: &Spec) -> String {
+    let mut command = "complete".to_string();
+    for option in &spec.options {
+        command += &format!(" -o {}", option);
+    }
+    for action in &spec.actions {
+        match ACTIONS.iter().find(|(name, _)| name == action) {
+            Some((_, Some(short))) => command += &format!(" -{}", short),
+            _ => command += &format!(" -A {}", action),
+        }
+    }
+    if let Some(words) = &spec.words {
+        command += &format!(" -W {}", quote(words));
+    }
+    if let Some(function) = &spec.function {
+        command += &format!(" -F {}", function);
+    }
+    command + " " + name
+}
+
+/// The name a trap is kept under: a signal name without `SIG`, or one of the
+/// pseudo-signals, for which `0` is `EXIT`.
+fn trap_name(spec: &str) -> Option<String> {
+    let name = spec.to_ascii_uppercase();
+    match name.as_str() {
+        "0" | "EXIT" | "SIGEXIT" => Some("EXIT".to_string()),
+        "DEBUG" | "ERR" | "RETURN" => Some(name),
+        _ => match spec.parse() {
+            Ok(number) => signal::name(number),
+            Err(_) => signal::number(spec).and_then(signal::name),
+        }
+        .map(String::from),
+    }
+}
+
+/// Orders traps as `trap -p` lists them: `EXIT`, the signals by number, then
+/// the other pseudo-signals.
+fn trap_order(name: &str) -> (i32, String) {
+    match name {
+        "EXIT" => (0, String::new()),
+        _ => match signal::number(name) {
+            Some(number) => (number, String::new()),
+            None => (i32::MAX, name.to_string()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::history::Entry;
+
+    #[test]
+    fn test_names() {
+        assert!(NAMES.iter().all(|name| lookup(name).is_some()));
+    }
+
+    #[test]
+    fn test_cd() {
+        let dir = TempDir::new("").unwrap();
+        let path = dir.path().canonicalize().unwrap();
+        let mut session = Session::new();
+        let cwd = session.cwd.clone();
+
+        let args = vec![path.to_str().unwrap().to_string()];
+        assert_eq!(cd(&mut session, &args, &mut Io::default()).unwrap(), 0);
+        assert_eq!(session.cwd, path);
+        assert_eq!(session.var("PWD"), path.to_str());
+        assert_eq!(session.var("OLDPWD"), cwd.to_str());
+
+        let args = vec!["does-not-exist".to_string()];
+        let mut io = Io {
+            stderr: Some(tempfile(&dir)),
+            ..Default::default()
+        };
+        assert_eq!(cd(&mut session, &args, &mut io).unwrap(), 1);
+        assert_eq!(session.cwd, path);
+
+        session.readonly.insert("PWD".to_string());
+        let args = vec![cwd.to_str().unwrap().to_string()];
+        assert_eq!(cd(&mut session, &args, &mut io).unwrap(), 1);
+        assert_eq!(session.cwd, cwd);
+        assert_eq!(session.var("PWD"), path.to_str());
+        assert_eq!(session.var("OLDPWD"), path.to_str());
+    }
+
+    #[test]
+    fn test_variables() {
+        let dir = TempDir::new("").unwrap();
+        let mut session = Session::new();
+        session.vars.clear();
+        session.exported.clear();
+        let mut io = Io {
+            stdout: Some(tempfile(&dir)),
+            stderr: Some(tempfile(&dir)),
+            ..Default::default()
+        };
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            export(&mut session, &args(&["A=1", "B"]), &mut io).unwrap(),
+            0
+        );
+        assert_eq!(session.var("A"), Some("1"));
+        assert!(session.exported.contains("A") && session.exported.contains("B"));
+
+        assert_eq!(
+            export(&mut session, &args(&["-n", "B"]), &mut io).unwrap(),
+            0
+        );
+        assert!(!session.exported.contains("B"));
+
+        assert_eq!(export(&mut session, &args(&["1=x"]), &mut io).unwrap(), 1);
+
+        assert_eq!(
+            readonly(&mut session, &args(&["A=2", "C=3"]), &mut io).unwrap(),
+            0
+        );
+        assert_eq!(session.var("A"), Some("2"));
+        assert_eq!(readonly(&mut session, &args(&["A=4"]), &mut io).unwrap(), 1);
+        assert_eq!(session.var("A"), Some("2"));
+
+        assert_eq!(unset(&mut session, &args(&["A", "D"]), &mut io).unwrap(), 1);
+        assert_eq!(session.var("A"), Some("2"));
+        session.vars.insert("D".to_string(), "5".to_string());
+        assert_eq!(unset(&mut session, &args(&["D"]), &mut io).unwrap(), 0);
+        assert_eq!(session.var("D"), None);
+    }
+
+    #[test]
+    fn test_declare() {
+        let dir = TempDir::new("").unwrap();
+        let mut session = Session::new();
+        session.vars.insert("A".to_string(), "a \"$b\"".to_string());
+        let mut io = Io {
+            stdout: Some(std::fs::File::create(dir.path().join("stdout")).unwrap()),
+            ..Default::default()
+        };
+        let names = vec!["B".to_string(), "A".to_string()];
+        session.vars.remove("B");
+        declare(&session, "-x", names, &mut io).unwrap();
+
+        let output = std::fs::read_to_string(dir.path().join("stdout")).unwrap();
+        assert_eq!(output, "declare -x A=\"a \\\"\\$b\\\"\"\ndeclare -x B\n");
+    }
+
+    #[test]
+    fn test_set() {
+        let dir = TempDir::new("").unwrap();
+        let mut session = Session::new();
+        let mut io = Io {
+            stdout: Some(tempfile(&dir)),
+            stderr: Some(tempfile(&dir)),
+            ..Default::default()
+        };
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            set(&mut session, &args(&["-eu", "-o", "pipefail"]), &mut io).unwrap(),
+            0
+        );
+        assert!(session.options.errexit && session.options.nounset && session.options.pipefail);
+        assert!(session.args.is_empty());
+
+        assert_eq!(
+            set(&mut session, &args(&["+e", "-x", "a", "-b"]), &mut io).unwrap(),
+            0
+        );
+        assert!(!session.options.errexit && session.options.xtrace);
+        assert_eq!(session.args, args(&["a", "-b"]));
+
+        assert_eq!(
+            set(&mut session, &args(&["-fC", "--"]), &mut io).unwrap(),
+            0
+        );
+        assert!(session.options.noglob && session.options.noclobber);
+        assert!(session.args.is_empty());
+
+        assert_eq!(set(&mut session, &args(&["-o", "vi"]), &mut io).unwrap(), 0);
+        assert!(session.options.vi && !session.options.emacs);
+        assert_eq!(
+            shopt(&mut session, &args(&["-o", "-s", "emacs"]), &mut io).unwrap(),
+            0
+        );
+        assert!(!session.options.vi && session.options.emacs);
+
+        assert_eq!(set(&mut session, &args(&["-q"]), &mut io).unwrap(), 2);
+        assert_eq!(
+            set(&mut session, &args(&["-o", "nope"]), &mut io).unwrap(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_history() {
+        let dir = TempDir::new("").unwrap();
+        let mut session = Session::new();
+        session.vars.insert("HISTTIMEFORMAT".into(), "%Y ".into());
+        for line in ["ls", "pwd", "echo hi"] {
+            let entry = Entry {
+                line: line.to_string(),
+                time: 86400 * 366,
+            };
+            session.history.push(entry, "", None);
+        }
+        let mut io = Io {
+            stdout: Some(std::fs::File::create(dir.path().join("stdout")).unwrap()),
+            stderr: Some(tempfile(&dir)),
+            ..Default::default()
+        };
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            history(&mut session, &args(&["-d", "-3"]), &mut io).unwrap(),
+            0
+        );
+        assert_eq!(
+            history(&mut session, &args(&["-d", "3"]), &mut io).unwrap(),
+            1
+        );
+        assert_eq!(history(&mut session, &args(&["1"]), &mut io).unwrap(), 0);
+        let output = std::fs::read_to_string(dir.path().join("stdout")).unwrap();
+        assert_eq!(output, "    2  1971 echo hi\n");
+
+        assert_eq!(history(&mut session, &args(&["-c"]), &mut io).unwrap(), 0);
+        assert!(session.history.is_empty());
+        assert_eq!(history(&mut session, &args(&["x"]), &mut io).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_trap() {
+        let dir = TempDir::new("").unwrap();
+        let mut session = Session::new();
+        let mut io = Io {
+            stdout: Some(std::fs::File::create(dir.path().join("stdout")).unwrap()),
+            stderr: Some(tempfile(&dir)),
+            ..Default::default()
+        };
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            trap(&mut session, &args(&["rm -rf $d", "0", "sigurg"]), &mut io).unwrap(),
+            0
+        );
+        assert_eq!(trap(&mut session, &args(&["", "ERR"]), &mut io).unwrap(), 0);
+        assert_eq!(session.traps["EXIT"], "rm -rf $d");
+        assert_eq!(session.traps["URG"], "rm -rf $d");
+        assert_eq!(session.traps["ERR"], "");
+
+        trap(&mut session, &args(&["-p"]), &mut io).unwrap();
+        let output = std::fs::read_to_string(dir.path().join("stdout")).unwrap();
+        assert_eq!(
+            output,
+            "trap -- 'rm -rf $d' EXIT\ntrap -- 'rm -rf $d' URG\ntrap -- '' ERR\n"
+        );
+
+        assert_eq!(
+            trap(&mut session, &args(&["-", "URG"]), &mut io).unwrap(),
+            0
+        );
+        assert_eq!(trap(&mut session, &args(&["ERR"]), &mut io).unwrap(), 0);
+        assert_eq!(session.traps.keys().collect::<Vec<_>>(), vec!["EXIT"]);
+
+        assert_eq!(
+            trap(&mut session, &args(&["echo", "NOPE"]), &mut io).unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_complete() {
+        let dir = TempDir::new("").unwrap();
+        let mut session = Session::new();
+        session.vars.clear();
+        session.vars.insert("HOME".into(), "/home/me".into());
+        session.vars.insert("HOSTNAME".into(), "box".into());
+        let mut io = Io {
+            stdout: Some(std::fs::File::create(dir.path().join("stdout")).unwrap()),
+            stderr: Some(tempfile(&dir)),
+            ..Default::default()
+        };
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        let input = args(&[
+            "-o",
+            "nospace",
+            "-W",
+            "start stop",
+            "-A",
+            "function",
+            "svc",
+            "x",
+        ]);
+        assert_eq!(complete(&mut session, &input, &mut io).unwrap(), 0);
+        assert_eq!(
+            session.completions["svc"].words.as_deref(),
+            Some("start stop")
+        );
+        assert_eq!(
+            complete(&mut session, &args(&["-r", "x"]), &mut io).unwrap(),
+            0
+        );
+        assert_eq!(complete(&mut session, &args(&["-p"]), &mut io).unwrap(), 0);
+        assert_eq!(
+            complete(&mut session, &args(&["-A", "x", "y"]), &mut io).unwrap(),
+            2
+        );
+        assert_eq!(
+            complete(&mut session, &args(&["-p", "x"]), &mut io).unwrap(),
+            1
+        );
+
+        let input = args(&["-W", "$HOSTNAME HOT", "-b", "-v", "-V", "out", "H"]);
+        assert_eq!(compgen(&mut session, &input, &mut io).unwrap(), 0);
+        assert_eq!(session.elements("out"), vec!["HOME", "HOSTNAME", "HOT"]);
+        assert_eq!(
+            compgen(&mut session, &args(&["-b", "tr"]), &mut io).unwrap(),
+            0
+        );
+        assert_eq!(
+            compgen(&mut session, &args(&["-W", "a", "b"]), &mut io).unwrap(),
+            1
+        );
+
+        let output = std::fs::read_to_string(dir.path().join("stdout")).unwrap();
+        assert_eq!(
+            output,
+            "complete -o nospace -A function -W 'start stop' svc\ntrap\n"
+        );
+    }
+
+    #[test]
+    fn test_alias() {
+        let dir = TempDir::new("").unwrap();
+        let mut session = Session::new();
+        let mut io = Io {
+            stdout: Some(std::fs::File::create(dir.path().join("stdout")).unwrap()),
+            stderr: Some(tempfile(&dir)),
+            ..Default::default()
+        };
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        let input = args(&["gs=git status", "k=kubectl", "a/b=x"]);
+        assert_eq!(alias(&mut session, &input, &mut io).unwrap(), 1);
+        assert_eq!(session.aliases["gs"], "git status");
+        assert_eq!(alias(&mut session, &args(&["k", "x"]), &mut io).unwrap(), 1);
+        assert_eq!(alias(&mut session, &[], &mut io).unwrap(), 0);
+
+        assert_eq!(
+            unalias(&mut session, &args(&["k", "x"]), &mut io).unwrap(),
+            1
+        );
+        assert_eq!(session.aliases.len(), 1);
+        assert_eq!(unalias(&mut session, &args(&["-a"]), &mut io).unwrap(), 0);
+        assert!(session.aliases.is_empty());
+
+        let output = std::fs::read_to_string(dir.path().join("stdout")).unwrap();
+        assert_eq!(
+            output,
+            "alias k='kubectl'\nalias gs='git status'\nalias k='kubectl'\n"
+        );
+    }
+
+    fn tempfile(dir: &TempDir) -> std::fs::File {
+        std::fs::File::create(dir.path().join("stderr")).unwrap()
+    }
+}
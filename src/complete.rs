@@ -0,0 +1,542 @@
+use std::{fs, os::unix::fs::PermissionsExt, path::Path};
+
+use crate::{
+    builtin,
+    exec::{call_function, Io},
+    expand::{expand_string, is_name},
+    grammar::Token,
+    lex::{at_command_start, Lexer},
+    session::Session,
+};
+
+/// The kinds of names `complete -A` and `compgen -A` take, with the options
+/// that are short for them.
+pub const ACTIONS: &[(&str, Option<char>)] = &[
+    ("alias", Some('a')),
+    ("builtin", Some('b')),
+    ("command", Some('c')),
+    ("directory", Some('d')),
+    ("export", Some('e')),
+    ("file", Some('f')),
+    ("function", None),
+    ("variable", Some('v')),
+];
+
+/// The options `complete -o` takes.
+pub const OPTIONS: &[&str] = &["default", "dirnames", "nospace"];
+
+/// How to complete the arguments of a command, as registered with
+/// `complete`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Spec {
+    /// The kinds of names to complete, from [`ACTIONS`].
+    pub actions: Vec<String>,
+    /// A list of words to complete, from `-W`.
+    pub words: Option<String>,
+    /// A function that sets `COMPREPLY`, from `-F`.
+    pub function: Option<String>,
+    /// From [`OPTIONS`].
+    pub options: Vec<String>,
+}
+
+/// The possible completions of the word before the cursor.
+#[derive(Debug, Default, PartialEq)]
+pub struct Completion {
+    /// Where the word starts, in chars from the start of the line.
+    pub start: usize,
+    /// The word as typed.
+    pub word: String,
+    pub candidates: Vec<Candidate>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Candidate {
+    /// The text that replaces the word, quoted as needed.
+    pub text: String,
+    /// What to list when there are several candidates.
+    pub display: String,
+    /// Whether the word is complete, so that a space should follow it.
+    pub complete: bool,
+}
+
+/// Completes the last word of `line`, the text before the cursor. Commands
+/// are completed where a command would start, variable names after `$`, and
+/// paths everywhere else.
+pub fn complete(line: &str, session: &Session) -> Completion {
+    let chars = line.chars().collect::<Vec<_>>();
+    let start = word_start(&chars);
+    let word = chars[start..].iter().collect::<String>();
+    let before = chars[..start].iter().collect::<String>();
+
+    let mut completion = match variable_start(&word) {
+        Some(dollar) => Completion {
+            start: start + word[..dollar].chars().count(),
+            word: word[dollar..].to_string(),
+            candidates: variables(&word[dollar..], session),
+        },
+        None => {
+            let prefix = unquote(&word);
+            let candidates = match command_words(&before) {
+                None if prefix.contains('/') => paths(&prefix, true, session),
+                None => commands(&prefix, session),
+                Some(mut words) => match words.first().and_then(|command| spec(command, session)) {
+                    Some(spec) => {
+                        words.push(word.clone());
+                        programmable(spec, &words, session)
+                    }
+                    None => paths(&prefix, false, session),
+                },
+            };
+            Completion {
+                start,
+                word,
+                candidates,
+            }
+        }
+    };
+
+    completion.candidates.sort();
+    completion.candidates.dedup_by(|a, b| a.text == b.text);
+    completion
+}
+
+/// The longest prefix that all the candidates share.
+pub fn common_prefix(candidates: &[Candidate]) -> String {
+    let Some((first, rest)) = candidates.split_first() else {
+        return String::new();
+    };
+    let mut prefix = first.text.as_str();
+    for candidate in rest {
+        let len = prefix
+            .char_indices()
+            .zip(candidate.text.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(prefix.len().min(candidate.text.len()), |((i, _), _)| i);
+        prefix = &prefix[..len];
+    }
+    prefix.to_string()
+}
+
+/// Where the last word starts: after the last unquoted blank or operator.
+fn word_start(chars: &[char]) -> usize {
+    let mut start = 0;
+    let mut quote = None;
+    let mut escape = false;
+
+    for (i, &c) in chars.iter().enumerate() {
+        match c {
+            _ if escape => escape = false,
+            '\\' if quote != Some('\'') => escape = true,
+            '\'' | '"' if quote.is_none() => quote = Some(c),
+            c if Some(c) == quote => quote = None,
+            c if quote.is_none() && (c.is_whitespace() || ";|&<>()".contains(c)) => start = i + 1,
+            _ => {}
+        }
+    }
+    start
+}
+
+/// The words of the command that `before` ends in, or `None` if a word
+/// after it would be a command name. Assignments, `time` and `!` may come
+/// before the command.
+fn command_words(before: &str) -> Option<Vec<String>> {
+    let Ok(tokens) = Lexer::lex(before) else {
+        return Some(vec![]);
+    };
+    let start = tokens
+        .iter()
+        .rposition(|token| !matches!(token, Token::Input(_)))
+        .map_or(0, |i| i + 1);
+    let is_prefix = |word: &str| {
+        word == "time" || word == "!" || word.split_once('=').is_some_and(|(name, _)| is_name(name))
+    };
+
+    let mut words = tokens[start..].iter().filter_map(|token| match token {
+        Token::Input(word) => Some(word.clone()),
+        _ => None,
+    });
+    let mut words = words.by_ref().skip_while(|word| is_prefix(word)).peekable();
+    if words.peek().is_none() && at_command_start(&tokens[..start]) {
+        return None;
+    }
+    Some(words.collect())
+}
+
+/// The spec registered for a command, by its name or the last part of its
+/// path.
+fn spec<'a>(command: &str, session: &'a Session) -> Option<&'a Spec> {
+    let command = unquote(command);
+    let name = command.rsplit('/').next().unwrap_or_default();
+    session
+        .completions
+        .get(&command)
+        .or_else(|| session.completions.get(name))
+}
+
+/// Completes the last of `words` with a spec, falling back on paths if it
+/// has the `default` option, or directories for `dirnames`, and finds
+/// nothing.
+fn programmable(spec: &Spec, words: &[String], session: &Session) -> Vec<Candidate> {
+    let mut candidates = generate(spec, words, session);
+    let prefix = unquote(words.last().map_or("", String::as_str));
+    let option = |name: &str| spec.options.iter().any(|option| option == name);
+    if candidates.is_empty() && option("default") {
+        candidates = paths(&prefix, false, session);
+    } else if candidates.is_empty() && option("dirnames") {
+        candidates = paths(&prefix, false, session);
+        candidates.retain(|candidate| !candidate.complete);
+    }
+    if option("nospace") {
+        for candidate in &mut candidates {
+            candidate.complete = false;
+        }
+    }
+    candidates
+}
+
+/// Generates the matches a spec gives for the last of `words`, the words of
+/// a command line. Names from actions and `-W` are those that start with
+/// the word, while a `-F` function's `COMPREPLY` is taken as it is.
+pub fn generate(spec: &Spec, words: &[String], session: &Session) -> Vec<Candidate> {
+    let prefix = unquote(words.last().map_or("", String::as_str));
+    let word = |name: &str| Candidate {
+        text: name.to_string(),
+        display: name.to_string(),
+        complete: true,
+    };
+    let names = |names: &mut dyn Iterator<Item = &String>| {
+        let mut names = names
+            .filter(|name| name.starts_with(&prefix))
+            .collect::<Vec<_>>();
+        names.sort();
+        names.into_iter().map(|name| word(name)).collect::<Vec<_>>()
+    };
+
+    let mut candidates = vec![];
+    for action in &spec.actions {
+        candidates.extend(match action.as_str() {
+            "alias" => names(&mut session.aliases.keys()),
+            "builtin" => builtin::NAMES
+                .iter()
+                .filter(|name| name.starts_with(&prefix))
+                .map(|name| word(name))
+                .collect(),
+            "command" => commands(&prefix, session),
+            "directory" => paths(&prefix, false, session)
+                .into_iter()
+                .filter(|candidate| !candidate.complete)
+                .collect(),
+            "export" => names(&mut session.exported.iter()),
+            "file" => paths(&prefix, false, session),
+            "function" => names(&mut session.functions.keys()),
+            "variable" => names(&mut session.vars.keys().chain(session.arrays.keys())),
+            _ => vec![],
+        });
+    }
+
+    if let Some(words) = &spec.words {
+        let words = expand_string(&Token::Input(words.clone()), &mut session.clone())
+            .unwrap_or_else(|_| words.clone());
+        let words = words
+            .split_whitespace()
+            .filter(|word| word.starts_with(&prefix));
+        candidates.extend(words.map(word));
+    }
+
+    if let Some(function) = &spec.function {
+        candidates.extend(reply(function, words, session).iter().map(|w| word(w)));
+    }
+
+    candidates
+}
+
+/// Calls a completion function as bash does, with the command, the word
+/// being completed and the word before it as arguments, the array
+/// `COMP_WORDS` and `COMP_CWORD`, `COMP_LINE` and `COMP_POINT` set, and
+/// returns the matches it puts in the array `COMPREPLY`. The function runs in
+/// a copy of the session.
+fn reply(function: &str, words: &[String], session: &Session) -> Vec<String> {
+    let Some(body) = session.functions.get(function) else {
+        return vec![];
+    };
+    let mut session = session.clone();
+    let line = words.join(" ");
+    let cword = words.len().saturating_sub(1);
+    let vars = [
+        ("COMP_CWORD", cword.to_string()),
+        ("COMP_POINT", line.chars().count().to_string()),
+        ("COMP_LINE", line),
+    ];
+    for (name, value) in vars {
+        session.vars.insert(name.to_string(), value);
+    }
+    session.vars.remove("COMP_WORDS");
+    session
+        .arrays
+        .insert("COMP_WORDS".to_string(), words.to_vec());
+    session.vars.remove("COMPREPLY");
+    session.arrays.remove("COMPREPLY");
+
+    let arg = |i: Option<usize>| i.and_then(|i| words.get(i)).cloned().unwrap_or_default();
+    let args = [arg(Some(0)), arg(Some(cword)), arg(cword.checked_sub(1))];
+    let _ = call_function(body, &args, &mut session, Io::default());
+    session.elements("COMPREPLY")
+}
+
+/// Where a variable name being typed starts, at its `$` or `${`.
+fn variable_start(word: &str) -> Option<usize> {
+    let dollar = word.rfind('$')?;
+    let name = word[dollar + 1..]
+        .strip_prefix('{')
+        .unwrap_or(&word[dollar + 1..]);
+    let quoted = word[..dollar].matches('\'').count() % 2 == 1;
+    (!quoted && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')).then_some(dollar)
+}
+
+fn variables(word: &str, session: &Session) -> Vec<Candidate> {
+    let (braced, prefix) = match word[1..].strip_prefix('{') {
+        Some(prefix) => (true, prefix),
+        None => (false, &word[1..]),
+    };
+    session
+        .vars
+        .keys()
+        .chain(session.arrays.keys())
+        .filter(|name| name.starts_with(prefix))
+        .map(|name| Candidate {
+            text: match braced {
+                true => format!("${{{}}}", name),
+                false => format!("${}", name),
+            },
+            display: name.clone(),
+            complete: true,
+        })
+        .collect()
+}
+
+/// Aliases, builtins, functions and the executables on `PATH` whose names
+/// start with `prefix`.
+fn commands(prefix: &str, session: &Session) -> Vec<Candidate> {
+    let mut names = builtin::NAMES
+        .iter()
+        .filter(|name| name.starts_with(prefix))
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>();
+
+    for dir in session.var("PATH").unwrap_or_default().split(':') {
+        let dir = session.cwd.join(if dir.is_empty() { "." } else { dir });
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with(prefix) && is_executable(&entry.path()) {
+                names.push(name);
+            }
+        }
+    }
+
+    names.extend(
+        session
+            .aliases
+            .keys()
+            .chain(session.functions.keys())
+            .filter(|name| name.starts_with(prefix))
+            .cloned(),
+    );
+
+    names
+        .into_iter()
+        .map(|name| Candidate {
+            text: escape(&name),
+            display: name,
+            complete: true,
+        })
+        .collect()
+}
+
+/// The files and directories whose paths start with `prefix`. Hidden files
+/// are only included if the name being typed starts with a `.`. For a
+/// command, only directories and executables are included.
+fn paths(prefix: &str, executables: bool, session: &Session) -> Vec<Candidate> {
+    let (dir, name) = match prefix.rfind('/') {
+        Some(slash) => (&prefix[..=slash], &prefix[slash + 1..]),
+        None => ("", prefix),
+    };
+
+    // A leading `~` stays in the word, but is expanded to read the directory.
+    let path = match dir.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            format!("{}{}", session.var("HOME").unwrap_or_default(), rest)
+        }
+        _ => dir.to_string(),
+    };
+    let Ok(entries) = fs::read_dir(session.cwd.join(if path.is_empty() { "." } else { &path }))
+    else {
+        return vec![];
+    };
+
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let file = entry.file_name().to_string_lossy().into_owned();
+            if !file.starts_with(name) || file.starts_with('.') && !name.starts_with('.') {
+                return None;
+            }
+            let is_dir = entry.path().is_dir();
+            if executables && !is_dir && !is_executable(&entry.path()) {
+                return None;
+            }
+            let suffix = if is_dir { "/" } else { "" };
+            Some(Candidate {
+                text: format!("{}{}{}", escape_path(dir), escape(&file), suffix),
+                display: format!("{}{}", file, suffix),
+                complete: !is_dir,
+            })
+        })
+        .collect()
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+/// Removes the quotes and backslashes from a partly typed word.
+pub fn unquote(word: &str) -> String {
+    let mut unquoted = String::new();
+    let mut quote = None;
+    let mut chars = word.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if quote.is_none() => unquoted.extend(chars.next()),
+            '\'' | '"' if quote.is_none() => quote = Some(c),
+            c if Some(c) == quote => quote = None,
+            c => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+/// Escapes the characters in a name that the shell would otherwise treat
+/// specially.
+fn escape(name: &str) -> String {
+    let mut escaped = String::new();
+    for c in name.chars() {
+        if c.is_whitespace() || "'\"\\$`&|;<>()*?[]{}!#".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Escapes a directory, other than a leading `~`.
+fn escape_path(dir: &str) -> String {
+    match dir.strip_prefix('~') {
+        Some(rest) => format!("~{}", escape(rest)),
+        None => escape(dir),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::pipeline::Pipeline;
+
+    fn texts(line: &str, session: &Session) -> Vec<String> {
+        let completion = complete(line, session);
+        completion.candidates.into_iter().map(|c| c.text).collect()
+    }
+
+    #[test]
+    fn test_complete() {
+        let dir = TempDir::new("").unwrap();
+        let bin = dir.path().join("bin");
+        fs::create_dir_all(dir.path().join("My Documents")).unwrap();
+        fs::create_dir(&bin).unwrap();
+        File::create(dir.path().join("notes.txt")).unwrap();
+        File::create(dir.path().join(".hidden")).unwrap();
+        File::create(bin.join("tool")).unwrap();
+        fs::set_permissions(bin.join("tool"), fs::Permissions::from_mode(0o755)).unwrap();
+        File::create(bin.join("toast")).unwrap();
+
+        let mut session = Session::new();
+        session.cwd = dir.path().to_path_buf();
+        session.vars.clear();
+        session
+            .vars
+            .insert("PATH".into(), bin.to_str().unwrap().into());
+        session
+            .vars
+            .insert("HOME".into(), dir.path().to_str().unwrap().into());
+        session.vars.insert("HOSTNAME".into(), "box".into());
+
+        assert_eq!(texts("to", &session), vec!["tool"]);
+        assert_eq!(texts("ls; A=1 tr", &session), vec!["trap"]);
+        assert_eq!(texts("ex", &session), vec!["exit", "export"]);
+        assert_eq!(texts("cat M", &session), vec!["My\\ Documents/"]);
+        assert_eq!(texts("cat \"My D", &session), vec!["My\\ Documents/"]);
+        assert_eq!(texts("cat >n", &session), vec!["notes.txt"]);
+        assert_eq!(texts("cat .", &session), vec![".hidden"]);
+        assert_eq!(texts("cat ~/no", &session), vec!["~/notes.txt"]);
+        assert_eq!(texts("./bin/t", &session), vec!["./bin/tool"]);
+        assert_eq!(texts("cat bin/t", &session), vec!["bin/toast", "bin/tool"]);
+        assert_eq!(texts("echo $HO", &session), vec!["$HOME", "$HOSTNAME"]);
+        assert_eq!(texts("echo a${HOS", &session), vec!["${HOSTNAME}"]);
+        assert_eq!(texts("echo '$HO", &session), Vec::<String>::new());
+
+        let completion = complete("echo x$HO", &session);
+        assert_eq!((completion.start, completion.word.as_str()), (6, "$HO"));
+        assert_eq!(common_prefix(&completion.candidates), "$HO");
+    }
+
+    #[test]
+    fn test_programmable() {
+        let dir = TempDir::new("").unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        File::create(dir.path().join("stop.txt")).unwrap();
+        let mut session = Session::new();
+        session.cwd = dir.path().to_path_buf();
+
+        let input = "complete -W 'start stop status' svc; \
+            complete -o default -W up net; \
+            _git() { \
+                cur=${COMP_WORDS[COMP_CWORD]}; \
+                COMPREPLY=( $(compgen -W 'add commit checkout' -- \"$cur\") \
+                    \"$1-$2-$3 ${#COMP_WORDS[@]}\" ); \
+            }; \
+            complete -F _git git; \
+            complete -d -o nospace cd";
+        Pipeline::run(input, &mut session).unwrap();
+
+        assert_eq!(texts("svc st", &session), vec!["start", "status", "stop"]);
+        assert_eq!(texts("sudo; svc x", &session), Vec::<String>::new());
+        assert_eq!(texts("/usr/bin/svc sto", &session), vec!["stop"]);
+        assert_eq!(texts("net s", &session), vec!["src/", "stop.txt"]);
+        assert_eq!(texts("git a b", &session), vec!["git-b-a 3"]);
+        assert_eq!(
+            texts("git c", &session),
+            vec!["checkout", "commit", "git-c-git 2"]
+        );
+        assert_eq!(texts("_g", &session), vec!["_git"]);
+
+        let completion = complete("cd s", &session);
+        assert_eq!(completion.candidates.len(), 1);
+        assert!(!completion.candidates[0].complete);
+    }
+
+    #[test]
+    fn test_word_start() {
+        let start = |line: &str| word_start(&line.chars().collect::<Vec<_>>());
+        assert_eq!(start("echo foo"), 5);
+        assert_eq!(start("echo foo\\ ba"), 5);
+        assert_eq!(start("echo 'a b"), 5);
+        assert_eq!(start("ls|gr"), 3);
+        assert_eq!(start("echo "), 5);
+    }
+}
@@ -0,0 +1,913 @@
+use std::io::{self, Read, Write};
+
+use crate::{
+    complete::{self, common_prefix},
+    history::History,
+    prompt::{printed, END_IGNORE, START_IGNORE},
+    session::Session,
+    signal,
+};
+
+/// Reads lines for the REPL. On a terminal, lines are edited in raw mode with
+/// emacs or vi keybindings, and previous lines can be recalled from the
+/// history.
+#[derive(Debug, Default)]
+pub struct Editor {
+    /// The text last killed, for Ctrl-Y and vi's `p`.
+    kill: String,
+    /// Whether vi keys are read as commands, rather than inserted.
+    command: bool,
+    /// A vi `d`, `c` or `y` waiting for the motion it applies to.
+    operator: Option<char>,
+}
+
+/// A key press, decoded from the bytes the terminal sends.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Key {
+    Char(char),
+    Ctrl(char),
+    Alt(char),
+    AltBackspace,
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    WordLeft,
+    WordRight,
+    Up,
+    Down,
+    Home,
+    End,
+    Unknown,
+}
+
+/// The line being edited, and the cursor position within it.
+#[derive(Debug, Default)]
+struct Line {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+/// A Ctrl-R reverse incremental search through the history.
+struct Search {
+    query: String,
+    /// The index of the matching history entry.
+    found: Option<usize>,
+}
+
+/// Puts the terminal in raw mode, and restores it when dropped, including
+/// when unwinding from a panic.
+struct RawMode {
+    original: libc::termios,
+}
+
+impl Editor {
+    pub fn new() -> Editor {
+        Editor::default()
+    }
+
+    /// Prints `prompt` and appends a line from stdin to `line`, editing it if
+    /// the `emacs` or `vi` option is set and stdin is a terminal. Returns `false` at
+    /// the end of input, and an `Interrupted` error if Ctrl-C was pressed
+    /// while reading.
+    pub fn read_line(
+        &mut self,
+        prompt: &str,
+        line: &mut String,
+        session: &Session,
+    ) -> io::Result<bool> {
+        let mut stdout = io::stdout();
+        // SAFETY: `isatty` only inspects the descriptor.
+        let editing = session.options.emacs || session.options.vi;
+        if !editing || unsafe { libc::isatty(libc::STDIN_FILENO) } != 1 {
+            write!(stdout, "{}", printed(prompt))?;
+            stdout.flush()?;
+            return read_plain(line);
+        }
+
+        let _raw = RawMode::enable()?;
+        match self.edit(prompt, session, &mut io::stdin().lock(), &mut stdout)? {
+            Some(text) => {
+                line.push_str(&text);
+                line.push('\n');
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Edits a line read key by key from `input`, drawing it on `output`.
+    /// Returns `None` for Ctrl-D on an empty line or the end of input.
+    fn edit(
+        &mut self,
+        prompt: &str,
+        session: &Session,
+        input: &mut impl Read,
+        output: &mut impl Write,
+    ) -> io::Result<Option<String>> {
+        // Only the last line of the prompt is redrawn.
+        write!(output, "{}", printed(prompt))?;
+        let prompt = prompt.rsplit('\n').next().unwrap_or_default();
+
+        let history = &session.history;
+        let mut line = Line::default();
+        let mut index = history.len();
+        let mut draft = vec![];
+        let mut search: Option<Search> = None;
+        let mut tabbed = false;
+        let vi = session.options.vi;
+        // Each line starts in vi's insert mode.
+        self.command = false;
+        self.operator = None;
+        line.draw(prompt, output)?;
+
+        loop {
+            let Some(key) = read_key(input)? else {
+                if line.chars.is_empty() {
+                    return Ok(None);
+                }
+                break;
+            };
+            let list = tabbed && key == Key::Char('\t');
+            tabbed = key == Key::Char('\t');
+
+            if let Some(s) = &mut search {
+                match key {
+                    Key::Char(c) => {
+                        s.query.push(c);
+                        s.found = history.search(&s.query, s.found.map_or(index, |i| i + 1));
+                    }
+                    Key::Backspace => {
+                        s.query.pop();
+                        s.found = history.search(&s.query, history.len());
+                    }
+                    Key::Ctrl('R') => {
+                        let from = s.found.unwrap_or(history.len());
+                        s.found = history.search(&s.query, from).or(s.found);
+                    }
+                    Key::Ctrl('G') | Key::Ctrl('C') => {
+                        search = None;
+                        line.draw(prompt, output)?;
+                        continue;
+                    }
+                    _ => {
+                        if let Some(found) = s.found {
+                            index = found;
+                            line = Line::from(&history.entries()[found].line);
+                        }
+                        search = None;
+                        line.draw(prompt, output)?;
+                        if key == Key::Enter {
+                            break;
+                        }
+                        // Any other key ends the search and is then handled
+                        // as usual.
+                        self.handle(key, history, &mut line, &mut index, &mut draft, vi);
+                        line.draw(prompt, output)?;
+                        continue;
+                    }
+                }
+                let s = search.as_ref().unwrap();
+                let found = s.found.and_then(|i| history.line(i)).unwrap_or_default();
+                write!(output, "\r(reverse-i-search)`{}': {}\x1b[K", s.query, found)?;
+                output.flush()?;
+                continue;
+            }
+
+            match key {
+                // Escape then Enter in vi mode.
+                Key::Enter | Key::Alt('\r') => break,
+                Key::Ctrl('C') => {
+                    write!(output, "^C")?;
+                    output.flush()?;
+                    return Err(io::ErrorKind::Interrupted.into());
+                }
+                Key::Ctrl('D') if line.chars.is_empty() => return Ok(None),
+                Key::Ctrl('L') => write!(output, "\x1b[H\x1b[2J")?,
+                Key::Char('\t') => Editor::complete(session, &mut line, list, output)?,
+                Key::Ctrl('R') => {
+                    search = Some(Search {
+                        query: String::new(),
+                        found: None,
+                    });
+                    write!(output, "\r(reverse-i-search)`': \x1b[K")?;
+                    output.flush()?;
+                    continue;
+                }
+                key => self.handle(key, history, &mut line, &mut index, &mut draft, vi),
+            }
+            line.draw(prompt, output)?;
+        }
+
+        write!(output, "\r\n")?;
+        output.flush()?;
+        Ok(Some(line.chars.into_iter().collect()))
+    }
+
+    /// Completes the word before the cursor. If there are several candidates
+    /// and nothing to add, a second Tab in a row lists them.
+    fn complete(
+        session: &Session,
+        line: &mut Line,
+        list: bool,
+        output: &mut impl Write,
+    ) -> io::Result<()> {
+        let text = line.chars[..line.pos].iter().collect::<String>();
+        let completion = complete::complete(&text, session);
+        let replacement = match completion.candidates.as_slice() {
+            [] => None,
+            [only] if only.complete => Some(format!("{} ", only.text)),
+            [only] => Some(only.text.clone()),
+            candidates => {
+                let prefix = common_prefix(candidates);
+                (prefix != completion.word && prefix.len() >= completion.word.len())
+                    .then_some(prefix)
+            }
+        };
+
+        match replacement {
+            Some(text) => {
+                line.chars.splice(completion.start..line.pos, text.chars());
+                line.pos = completion.start + text.chars().count();
+            }
+            None if list && completion.candidates.len() > 1 => {
+                let names = completion
+                    .candidates
+                    .iter()
+                    .map(|candidate| candidate.display.as_str())
+                    .collect::<Vec<_>>();
+                write!(output, "\r\n{}", columns(&names, terminal_width()))?;
+            }
+            None => write!(output, "\x07")?,
+        }
+        Ok(())
+    }
+
+    /// Applies an editing key to `line`. `index` is the history entry being
+    /// shown, and `draft` keeps the new line while the history is browsed.
+    /// With `vi`, keys in insert mode edit as in emacs mode.
+    fn handle(
+        &mut self,
+        key: Key,
+        history: &History,
+        line: &mut Line,
+        index: &mut usize,
+        draft: &mut Vec<char>,
+        vi: bool,
+    ) {
+        if vi {
+            // The terminal sends Escape as the prefix of an Alt key, so the
+            // key after it is the first command.
+            if let Key::Alt(c) = key {
+                if !self.command {
+                    self.command = true;
+                    line.pos = line.pos.saturating_sub(1);
+                }
+                self.operator = None;
+                return self.vi_command(Key::Char(c), history, line, index, draft);
+            }
+            if self.command {
+                return self.vi_command(key, history, line, index, draft);
+            }
+        }
+
+        match key {
+            Key::Char(c) => line.insert(&[c]),
+            Key::Backspace | Key::Ctrl('H') => line.backspace(),
+            Key::Delete | Key::Ctrl('D') => line.delete(),
+            Key::Left | Key::Ctrl('B') => line.pos = line.pos.saturating_sub(1),
+            Key::Right | Key::Ctrl('F') => line.pos = (line.pos + 1).min(line.chars.len()),
+            Key::WordLeft | Key::Alt('b') => line.pos = line.word_start(),
+            Key::WordRight | Key::Alt('f') => line.pos = line.word_end(),
+            Key::Home | Key::Ctrl('A') => line.pos = 0,
+            Key::End | Key::Ctrl('E') => line.pos = line.chars.len(),
+            Key::Ctrl('K') => self.kill = line.kill(line.pos..line.chars.len()),
+            Key::Ctrl('U') => self.kill = line.kill(0..line.pos),
+            Key::Ctrl('W') => {
+                let start = line.chars[..line.pos]
+                    .iter()
+                    .rposition(|c| !c.is_whitespace())
+                    .map_or(0, |end| {
+                        line.chars[..end]
+                            .iter()
+                            .rposition(|c| c.is_whitespace())
+                            .map_or(0, |i| i + 1)
+                    });
+                self.kill = line.kill(start..line.pos);
+            }
+            Key::AltBackspace => self.kill = line.kill(line.word_start()..line.pos),
+            Key::Alt('d') => self.kill = line.kill(line.pos..line.word_end()),
+            Key::Ctrl('Y') => line.insert(&self.kill.chars().collect::<Vec<_>>()),
+            Key::Ctrl('T') if line.pos > 0 && line.chars.len() > 1 => {
+                let pos = line.pos.min(line.chars.len() - 1);
+                line.chars.swap(pos - 1, pos);
+                line.pos = pos + 1;
+            }
+            Key::Up | Key::Ctrl('P') if *index > 0 => {
+                if *index == history.len() {
+                    *draft = line.chars.clone();
+                }
+                *index -= 1;
+                *line = Line::from(&history.entries()[*index].line);
+            }
+            Key::Down | Key::Ctrl('N') if *index < history.len() => {
+                *index += 1;
+                *line = match history.line(*index) {
+                    Some(entry) => Line::from(entry),
+                    None => Line::from(&draft.iter().collect::<String>()),
+                };
+            }
+            _ => {}
+        }
+    }
+
+    /// Applies a key in vi command mode.
+    fn vi_command(
+        &mut self,
+        key: Key,
+        history: &History,
+        line: &mut Line,
+        index: &mut usize,
+        draft: &mut Vec<char>,
+    ) {
+        let c = match key {
+            Key::Char(c) => c,
+            Key::Backspace | Key::Left => 'h',
+            Key::Right => 'l',
+            Key::Home => '0',
+            Key::End => '$',
+            Key::Delete => 'x',
+            Key::Up => 'k',
+            Key::Down => 'j',
+            _ => return,
+        };
+        let len = line.chars.len();
+
+        if let Some(operator) = self.operator.take() {
+            let range = if c == operator {
+                0..len
+            } else {
+                // Like vi, `cw` changes up to the end of the word only.
+                let motion = if operator == 'c' && c == 'w' { 'e' } else { c };
+                let Some(to) = vi_motion(line, motion) else {
+                    return;
+                };
+                line.pos.min(to)..line.pos.max(to)
+            };
+            if operator == 'y' {
+                self.kill = line.chars[range].iter().collect();
+            } else {
+                self.kill = line.kill(range);
+            }
+            self.command = operator != 'c';
+        } else {
+            match c {
+                'i' => self.command = false,
+                'a' => {
+                    line.pos = (line.pos + 1).min(len);
+                    self.command = false;
+                }
+                'I' => {
+                    line.pos = 0;
+                    self.command = false;
+                }
+                'A' => {
+                    line.pos = len;
+                    self.command = false;
+                }
+                'x' if line.pos < len => self.kill = line.kill(line.pos..line.pos + 1),
+                'X' if line.pos > 0 => self.kill = line.kill(line.pos - 1..line.pos),
+                'D' | 'C' => {
+                    self.kill = line.kill(line.pos..len);
+                    self.command = c == 'D';
+                }
+                'd' | 'c' | 'y' => self.operator = Some(c),
+                'p' | 'P' => {
+                    if c == 'p' {
+                        line.pos = (line.pos + 1).min(len);
+                    }
+                    line.insert(&self.kill.chars().collect::<Vec<_>>());
+                    line.pos = line.pos.saturating_sub(1);
+                }
+                'k' | '-' => self.handle(Key::Up, history, line, index, draft, false),
+                'j' | '+' => self.handle(Key::Down, history, line, index, draft, false),
+                // `e` moves onto the last character of the word.
+                'e' => line.pos = vi_motion(line, c).unwrap_or(0).saturating_sub(1),
+                c => {
+                    if let Some(to) = vi_motion(line, c) {
+                        line.pos = to;
+                    }
+                }
+            }
+        }
+
+        // In command mode the cursor is on a character, not after the last.
+        if self.command && line.pos == line.chars.len() {
+            line.pos = line.pos.saturating_sub(1);
+        }
+    }
+}
+
+impl Line {
+    fn from(text: &str) -> Line {
+        let chars = text.chars().collect::<Vec<_>>();
+        Line {
+            pos: chars.len(),
+            chars,
+        }
+    }
+
+    fn insert(&mut self, chars: &[char]) {
+        self.chars.splice(self.pos..self.pos, chars.iter().copied());
+        self.pos += chars.len();
+    }
+
+    fn backspace(&mut self) {
+        if self.pos > 0 {
+            self.pos -= 1;
+            self.chars.remove(self.pos);
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.pos < self.chars.len() {
+            self.chars.remove(self.pos);
+        }
+    }
+
+    /// Removes the characters in `range`, leaving the cursor at its start,
+    /// and returns them.
+    fn kill(&mut self, range: std::ops::Range<usize>) -> String {
+        self.pos = range.start;
+        self.chars.drain(range).collect()
+    }
+
+    /// The start of the word before the cursor.
+    fn word_start(&self) -> usize {
+        let mut pos = self.pos;
+        while pos > 0 && !is_word(self.chars[pos - 1]) {
+            pos -= 1;
+        }
+        while pos > 0 && is_word(self.chars[pos - 1]) {
+            pos -= 1;
+        }
+        pos
+    }
+
+    /// The end of the word after the cursor.
+    fn word_end(&self) -> usize {
+        let mut pos = self.pos;
+        while pos < self.chars.len() && !is_word(self.chars[pos]) {
+            pos += 1;
+        }
+        while pos < self.chars.len() && is_word(self.chars[pos]) {
+            pos += 1;
+        }
+        pos
+    }
+
+    /// Redraws the prompt and the line, and puts the cursor in place.
+    fn draw(&self, prompt: &str, output: &mut impl Write) -> io::Result<()> {
+        let text = self.chars.iter().collect::<String>();
+        write!(output, "\r{}{}\x1b[K\r", printed(prompt), text)?;
+        let column = width(prompt) + self.pos;
+        if column > 0 {
+            write!(output, "\x1b[{}C", column)?;
+        }
+        output.flush()
+    }
+}
+
+impl RawMode {
+    fn enable() -> io::Result<RawMode> {
+        // SAFETY: `termios` is plain data, filled in by `tcgetattr`.
+        let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let original = termios;
+
+        // Output processing is left on, so that `\n` still starts a new line
+        // in anything printed while editing.
+        termios.c_iflag &= !(libc::BRKINT | libc::ICRNL | libc::INPCK | libc::ISTRIP | libc::IXON);
+        termios.c_cflag |= libc::CS8;
+        termios.c_lflag &= !(libc::ECHO | libc::ICANON | libc::IEXTEN | libc::ISIG);
+        termios.c_cc[libc::VMIN] = 1;
+        termios.c_cc[libc::VTIME] = 0;
+        set_termios(&termios)?;
+
+        Ok(RawMode { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = set_termios(&self.original);
+    }
+}
+
+fn set_termios(termios: &libc::termios) -> io::Result<()> {
+    // SAFETY: `termios` is a valid, initialised struct.
+    if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, termios) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Lays out `items` in columns that fit in `width`, reading down and then
+/// across, as `ls` does.
+fn columns(items: &[&str], width: usize) -> String {
+    let column = items
+        .iter()
+        .map(|item| item.chars().count())
+        .max()
+        .unwrap_or(0)
+        + 2;
+    let count = (width / column).max(1);
+    let rows = items.len().div_ceil(count);
+
+    let mut text = String::new();
+    for row in 0..rows {
+        let mut line = String::new();
+        for item in items.iter().skip(row).step_by(rows) {
+            line.push_str(&format!("{:<1$}", item, column));
+        }
+        text.push_str(line.trim_end());
+        text.push_str("\r\n");
+    }
+    text
+}
+
+/// The width of the terminal on stdout, or 80 if it isn't one.
+fn terminal_width() -> usize {
+    // SAFETY: `winsize` is plain data, filled in by the ioctl.
+    unsafe {
+        let mut size = std::mem::zeroed::<libc::winsize>();
+        if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) == 0 && size.ws_col > 0 {
+            return size.ws_col as usize;
+        }
+    }
+    80
+}
+
+/// Where the vi motion `c` moves the cursor, or `None` if `c` isn't one.
+/// For `e`, this is just after the end of the word.
+fn vi_motion(line: &Line, c: char) -> Option<usize> {
+    let (chars, pos, len) = (&line.chars, line.pos, line.chars.len());
+    Some(match c {
+        'h' => pos.saturating_sub(1),
+        'l' => (pos + 1).min(len),
+        '0' => 0,
+        '^' => chars.iter().position(|c| !c.is_whitespace()).unwrap_or(len),
+        '$' => len,
+        'w' => {
+            let mut i = pos;
+            if let Some(&first) = chars.get(i) {
+                while i < len && !chars[i].is_whitespace() && is_word(chars[i]) == is_word(first) {
+                    i += 1;
+                }
+            }
+            while i < len && chars[i].is_whitespace() {
+                i += 1;
+            }
+            i
+        }
+        'b' => line.word_start(),
+        'e' => {
+            let mut i = (pos + 1).min(len);
+            while i < len && !is_word(chars[i]) {
+                i += 1;
+            }
+            while i < len && is_word(chars[i]) {
+                i += 1;
+            }
+            i
+        }
+        _ => return None,
+    })
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// How many columns a prompt takes on the screen. Nothing between the
+/// markers from `\[` and `\]` counts, and neither do ANSI escape sequences
+/// outside them.
+fn width(prompt: &str) -> usize {
+    let mut chars = prompt.chars();
+    let mut width = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            START_IGNORE => {
+                chars.by_ref().find(|&c| c == END_IGNORE);
+            }
+            // A control sequence ends with a byte from `@` to `~`.
+            '\x1b' if chars.clone().next() == Some('[') => {
+                chars.next();
+                chars.by_ref().find(|c| ('@'..='~').contains(c));
+            }
+            // An operating system command, such as setting the title, ends
+            // with a bell or `ESC \`.
+            '\x1b' if chars.clone().next() == Some(']') => {
+                chars.by_ref().find(|&c| c == '\x07' || c == '\\');
+            }
+            '\x1b' => {
+                chars.next();
+            }
+            c if c.is_control() => {}
+            _ => width += 1,
+        }
+    }
+    width
+}
+
+/// Appends a line from stdin to `line` without editing it. Returns `false`
+/// at the end of input, and an `Interrupted` error if Ctrl-C was pressed
+/// while reading.
+fn read_plain(line: &mut String) -> io::Result<bool> {
+    let mut stdin = io::stdin().lock();
+    let mut bytes = vec![];
+    let mut byte = [0];
+
+    loop {
+        match stdin.read(&mut byte)? {
+            0 if bytes.is_empty() => return Ok(false),
+            0 => break,
+            _ => {
+                bytes.push(byte[0]);
+                if byte[0] == b'\n' {
+                    break;
+                }
+            }
+        }
+    }
+
+    line.push_str(&String::from_utf8_lossy(&bytes));
+    Ok(true)
+}
+
+/// Reads a byte, or `None` at the end of input. Reads interrupted by signals
+/// other than SIGINT are retried.
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    loop {
+        match input.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted && !signal::interrupted() => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Reads and decodes a key press, or `None` at the end of input.
+fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+    let Some(byte) = read_byte(input)? else {
+        return Ok(None);
+    };
+
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        0x7f => Key::Backspace,
+        0x1b => match read_byte(input)? {
+            Some(b'[') => read_escape(input)?,
+            Some(b'O') => match read_byte(input)? {
+                Some(b'H') => Key::Home,
+                Some(b'F') => Key::End,
+                Some(b'A') => Key::Up,
+                Some(b'B') => Key::Down,
+                Some(b'C') => Key::Right,
+                Some(b'D') => Key::Left,
+                _ => Key::Unknown,
+            },
+            Some(0x7f) => Key::AltBackspace,
+            Some(b'\r' | b'\n') => Key::Alt('\r'),
+            Some(byte) if byte.is_ascii_graphic() => Key::Alt(byte as char),
+            _ => Key::Unknown,
+        },
+        byte if byte < 0x20 && byte != b'\t' => Key::Ctrl((byte + b'@') as char),
+        byte => {
+            // Gather the rest of a multi-byte UTF-8 character.
+            let len = match byte {
+                0xf0.. => 4,
+                0xe0.. => 3,
+                0xc0.. => 2,
+                _ => 1,
+            };
+            let mut bytes = vec![byte];
+            while bytes.len() < len {
+                match read_byte(input)? {
+                    Some(byte) => bytes.push(byte),
+                    None => break,
+                }
+            }
+            match std::str::from_utf8(&bytes) {
+                Ok(s) => Key::Char(s.chars().next().unwrap()),
+                Err(_) => Key::Unknown,
+            }
+        }
+    };
+    Ok(Some(key))
+}
+
+/// Decodes a CSI escape sequence, after the `ESC [`.
+fn read_escape(input: &mut impl Read) -> io::Result<Key> {
+    let mut params = String::new();
+    let last = loop {
+        match read_byte(input)? {
+            Some(byte @ 0x40..=0x7e) => break byte,
+            Some(byte) => params.push(byte as char),
+            None => return Ok(Key::Unknown),
+        }
+    };
+
+    Ok(match (params.as_str(), last) {
+        (_, b'A') => Key::Up,
+        (_, b'B') => Key::Down,
+        ("1;5" | "1;3", b'C') => Key::WordRight,
+        ("1;5" | "1;3", b'D') => Key::WordLeft,
+        (_, b'C') => Key::Right,
+        (_, b'D') => Key::Left,
+        (_, b'H') | ("1" | "7", b'~') => Key::Home,
+        (_, b'F') | ("4" | "8", b'~') => Key::End,
+        ("3", b'~') => Key::Delete,
+        _ => Key::Unknown,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::history::Entry;
+
+    fn edit(editor: &mut Editor, session: &Session, input: &[u8]) -> io::Result<Option<String>> {
+        editor.edit("> ", session, &mut &input[..], &mut vec![])
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut editor = Editor::new();
+        let session = Session::new();
+        let cases: &[(&[u8], &str)] = &[
+            (b"echo hi\r", "echo hi"),
+            (b"abc\x1b[D\x1b[DX\r", "aXbc"),
+            (b"abc\x02\x02\x06\x7fX\r", "aXc"),
+            (b"bc\x01a\x05d\r", "abcd"),
+            (b"abc\x1b[H\x1b[3~\r", "bc"),
+            (b"echo foo bar\x1bb\x1bbX\r", "echo Xfoo bar"),
+            (b"echo foo bar\x01\x1b[1;5C\x1b[1;5CX\r", "echo fooX bar"),
+            (b"echo foo\x17bar\r", "echo bar"),
+            (b"echo foo bar\x1bb\x0b\x01\x19 \r", "bar echo foo "),
+            (b"echo foo\x01\x1bd\x15\r", " foo"),
+            (b"ab\x14\r", "ba"),
+            (b"\xc3\xa9t\xc3\xa9\x1b[D\x7f\r", "\u{e9}\u{e9}"),
+            (b"partial", "partial"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                edit(&mut editor, &session, input).unwrap(),
+                Some(expected.to_string()),
+                "{:?}",
+                String::from_utf8_lossy(input)
+            );
+        }
+
+        assert_eq!(edit(&mut editor, &session, b"").unwrap(), None);
+        assert_eq!(edit(&mut editor, &session, b"\x04").unwrap(), None);
+        assert_eq!(
+            edit(&mut editor, &session, b"ab\x01\x04\r").unwrap(),
+            Some("b".into())
+        );
+        let e = edit(&mut editor, &session, b"ab\x03").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::Interrupted);
+    }
+
+    #[test]
+    fn test_history() {
+        let mut editor = Editor::new();
+        let mut session = Session::new();
+        for line in ["echo one", "echo two", "ls"] {
+            let entry = Entry {
+                line: line.to_string(),
+                time: 0,
+            };
+            session.history.push(entry, "", None);
+        }
+
+        let cases: &[(&[u8], &str)] = &[
+            (b"\x1b[A\r", "ls"),
+            (b"\x1b[A\x1b[A\x1b[A\x1b[A\r", "echo one"),
+            (b"new\x10\x10\x0e\x0e\r", "new"),
+            (b"\x1b[A\x1b[AX\r", "echo twoX"),
+            (b"\x12two\r", "echo two"),
+            (b"\x12echo\x12\r", "echo one"),
+            (b"\x12o\x12\x12\x12\x1b[DX\r", "echo onXe"),
+            (b"\x12tw\x7f\x7fl\x05!\r", "ls!"),
+            (b"keep\x12nope\x07\r", "keep"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                edit(&mut editor, &session, input).unwrap(),
+                Some(expected.to_string()),
+                "{:?}",
+                String::from_utf8_lossy(input)
+            );
+        }
+    }
+
+    #[test]
+    fn test_vi() {
+        let mut editor = Editor::new();
+        let mut session = Session::new();
+        session.options.vi = true;
+        let entry = Entry {
+            line: "ls".to_string(),
+            time: 0,
+        };
+        session.history.push(entry, "", None);
+
+        let cases: &[(&[u8], &str)] = &[
+            (b"echo hi\r", "echo hi"),
+            (b"echo foo\x1bbiX \r", "echo X foo"),
+            (b"abc\x1b0x\r", "bc"),
+            (b"abc\x1bhD\r", "a"),
+            (b"echo foo bar\x1b0dwx\r", "oo bar"),
+            (b"echo foo bar\x1bbcwbaz\x1b\r", "echo foo baz"),
+            (b"echo foo\x1bdd\r", ""),
+            (b"echo foo\x1bccnew\r", "new"),
+            (b"ab\x1b0ylp\r", "aab"),
+            (b"abc\x1bIX\x1bAY\r", "XabcY"),
+            (b"one two\x1b0eD\r", "on"),
+            (b"\x1bk\r", "ls"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                edit(&mut editor, &session, input).unwrap(),
+                Some(expected.to_string()),
+                "{:?}",
+                String::from_utf8_lossy(input)
+            );
+        }
+    }
+
+    #[test]
+    fn test_complete() {
+        let dir = TempDir::new("").unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        for name in ["notes.txt", "foo.rs", "fob.rs"] {
+            File::create(dir.path().join(name)).unwrap();
+        }
+        let mut editor = Editor::new();
+        let mut session = Session::new();
+        session.cwd = dir.path().to_path_buf();
+        session.vars.insert("ZZVAR".into(), "1".into());
+
+        let cases: &[(&[u8], &str)] = &[
+            (b"cat no\t\r", "cat notes.txt "),
+            (b"cd s\tlib\r", "cd src/lib"),
+            (b"cat fo\t\r", "cat fo"),
+            (b"cat x\t\r", "cat x"),
+            (b"echo $ZZV\tx\r", "echo $ZZVAR x"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                edit(&mut editor, &session, input).unwrap(),
+                Some(expected.to_string()),
+                "{:?}",
+                String::from_utf8_lossy(input)
+            );
+        }
+
+        let mut output = vec![];
+        let input = b"cat fo\t\t\r";
+        editor
+            .edit("> ", &session, &mut &input[..], &mut output)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("\x07"));
+        assert!(output.contains("\r\nfob.rs  foo.rs\r\n"));
+    }
+
+    #[test]
+    fn test_columns() {
+        let items = ["a", "bb", "c", "d", "e"];
+        assert_eq!(columns(&items, 80), "a   bb  c   d   e\r\n");
+        assert_eq!(columns(&items, 9), "a   d\r\nbb  e\r\nc\r\n");
+        assert_eq!(columns(&items, 1), "a\r\nbb\r\nc\r\nd\r\ne\r\n");
+    }
+
+    #[test]
+    fn test_width() {
+        assert_eq!(width("> "), 2);
+        assert_eq!(width("\x01\x1b[1;32m\x02ok\x01\x1b[0m\x02> "), 4);
+        assert_eq!(width("\x1b[31mé\x1b[m "), 2);
+        assert_eq!(width("\x1b]0;title\x07$ "), 2);
+        assert_eq!(printed("\x01\x1b[1m\x02$"), "\x1b[1m$");
+    }
+}
@@ -4,12 +4,51 @@ use crate::grammar::Token;
 pub enum Error {
     #[error("parse error near {0}")]
     Parse(Token),
+    #[error("no match: {0}")]
+    NoMatch(String),
+    #[error("{0}: ambiguous redirect")]
+    AmbiguousRedirect(String),
+    #[error("{0}: bad substitution")]
+    BadSubstitution(String),
+    #[error("${0}: cannot assign in this way")]
+    BadAssignment(String),
+    #[error("{0}: {1}")]
+    Parameter(String, String),
+    #[error("{0}")]
+    Arithmetic(String),
+    #[error("{0}: unbound variable")]
+    Unbound(String),
+    #[error("{0}: cannot overwrite existing file")]
+    Clobber(String),
+    #[error("{0}: readonly variable")]
+    Readonly(String),
+    #[error("here-document delimited by end-of-file (wanted `{0}')")]
+    UnterminatedHereDoc(String),
+    #[error("unexpected end of file while looking for matching `{0}'")]
+    UnterminatedQuote(char),
+    #[error("{0}")]
+    History(String),
+    #[error("command substitution: {0}")]
+    Substitution(String),
+}
+
+impl Error {
+    /// Whether the input ended before the command did, so that more lines
+    /// could complete it.
+    pub fn is_incomplete(&self) -> bool {
+        match self {
+            Error::Parse(Token::Input(token)) => token.is_empty(),
+            Error::UnterminatedHereDoc(_) | Error::UnterminatedQuote(_) => true,
+            _ => false,
+        }
+    }
 }
 
 impl From<Error> for std::io::Error {
     fn from(e: Error) -> std::io::Error {
         match &e {
             Error::Parse(_) => std::io::Error::new(std::io::ErrorKind::InvalidInput, e),
+            _ => std::io::Error::other(e),
         }
     }
 }
@@ -1,10 +1,33 @@
 use std::{
-    fs::OpenOptions,
-    io,
-    process::{Child, Command, ExitStatus, Stdio},
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    os::{
+        fd::{AsRawFd, OwnedFd},
+        unix::process::{CommandExt, ExitStatusExt},
+    },
+    process::{Child, Command, ExitStatus},
+    sync::atomic::Ordering,
+    thread::JoinHandle,
+    time::Instant,
 };
 
-use crate::{ast::Ast, grammar::Token};
+use crate::{
+    alias,
+    ast::Ast,
+    builtin,
+    error::Error,
+    expand::{
+        evaluate, expand, expand_assignment, expand_here_doc, expand_one, expand_string, quote,
+        Value,
+    },
+    grammar::Token,
+    lex::Lexer,
+    parse::Parser,
+    prompt::{self, printed},
+    session::Session,
+    signal,
+    time::{self, Times, Usage, DEFAULT_FORMAT, POSIX_FORMAT},
+};
 
 pub enum RunningProcess {
     Background,
@@ -20,105 +43,887 @@ impl RunningProcess {
     }
 }
 
-pub fn execute(ast: &Ast) -> io::Result<RunningProcess> {
+/// The standard streams a node is executed with. `None` inherits the shell's.
+#[derive(Debug, Default)]
+pub struct Io {
+    pub stdin: Option<File>,
+    pub stdout: Option<File>,
+    pub stderr: Option<File>,
+}
+
+impl Io {
+    pub fn try_clone(&self) -> io::Result<Io> {
+        Ok(Io {
+            stdin: try_clone(&self.stdin)?,
+            stdout: try_clone(&self.stdout)?,
+            stderr: try_clone(&self.stderr)?,
+        })
+    }
+
+    /// Where builtins write their output.
+    pub fn out(&mut self) -> Box<dyn Write + '_> {
+        match &mut self.stdout {
+            Some(file) => Box::new(file),
+            None => Box::new(io::stdout()),
+        }
+    }
+
+    /// Where builtins write their diagnostics.
+    pub fn err(&mut self) -> Box<dyn Write + '_> {
+        match &mut self.stderr {
+            Some(file) => Box::new(file),
+            None => Box::new(io::stderr()),
+        }
+    }
+}
+
+fn try_clone(file: &Option<File>) -> io::Result<Option<File>> {
+    file.as_ref().map(File::try_clone).transpose()
+}
+
+/// Something started by the executor that can be waited on for its status.
+#[derive(Debug)]
+pub enum Process {
+    /// A command, with the usage to add its CPU time to while `time` runs.
+    Child(Child, Option<Usage>),
+    Thread(JoinHandle<io::Result<ExitStatus>>),
+    Pipeline(Vec<Process>),
+    /// A pipeline under `set -o pipefail`, whose status is that of the last
+    /// process to fail.
+    PipeFail(Vec<Process>),
+    Exited(ExitStatus),
+}
+
+impl Process {
+    pub fn wait(self) -> io::Result<ExitStatus> {
+        match self {
+            Process::Child(mut child, Some(usage)) => time::wait(&mut child, &usage),
+            Process::Child(mut child, None) => child.wait(),
+            Process::Thread(handle) => handle
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("thread panicked"))),
+            Process::Pipeline(processes) => {
+                let mut status = ExitStatus::from_raw(0);
+                for process in processes {
+                    status = process.wait()?;
+                }
+                Ok(status)
+            }
+            Process::PipeFail(processes) => {
+                let mut status = ExitStatus::from_raw(0);
+                for process in processes {
+                    let next = process.wait()?;
+                    if !next.success() {
+                        status = next;
+                    }
+                }
+                Ok(status)
+            }
+            Process::Exited(status) => Ok(status),
+        }
+    }
+}
+
+pub fn exit_status(code: i32) -> ExitStatus {
+    ExitStatus::from_raw((code & 0xff) << 8)
+}
+
+/// The status as `$?` reports it: the exit code, or 128 plus the number of
+/// the signal that killed the process.
+pub fn status_code(status: ExitStatus) -> i32 {
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(1)
+}
+
+pub fn pipe() -> io::Result<(File, File)> {
+    let (reader, writer) = io::pipe()?;
+    Ok((
+        File::from(OwnedFd::from(reader)),
+        File::from(OwnedFd::from(writer)),
+    ))
+}
+
+pub fn execute(ast: &Ast, session: &mut Session) -> io::Result<RunningProcess> {
     match ast {
         Ast::Background { inner } => {
-            let inner = inner.clone();
-            std::thread::spawn(move || {
-                let mut child = exec_impl(&inner, None, None)?;
-                child.wait()
-            });
+            exec_background(inner, session, Io::default());
             Ok(RunningProcess::Background)
         }
         _ => {
-            let mut child = exec_impl(ast, None, None)?;
-            let status = child.wait()?;
+            let status = exec_impl(ast, session, Io::default())?.wait()?;
+            session.status = status_code(status);
+            failed(ast, status, session)?;
+            run_signal_traps(session)?;
             Ok(RunningProcess::Foreground(status))
         }
     }
 }
 
-fn exec_impl(ast: &Ast, stdin: Option<Stdio>, stdout: Option<Stdio>) -> io::Result<Child> {
+fn exec_impl(ast: &Ast, session: &mut Session, io: Io) -> io::Result<Process> {
     match ast {
-        Ast::Command { command, args } => exec_command(command, args, stdin, stdout),
-        Ast::Pipe { left, right } => exec_pipe(left, right, stdout),
-        Ast::RedirectOut { left, right } => exec_redirect_out(left, right),
-        Ast::RedirectAppend { left, right } => exec_redirect_append(left, right),
-        Ast::And { left, right } => exec_and(left, right, stdout),
-        Ast::Or { left, right } => exec_or(left, right, stdout),
-        Ast::Sequence { left, right } => exec_sequence(left, right, stdout),
-        Ast::Subshell { inner } => exec_impl(inner, stdin, stdout),
-        Ast::Background { inner } => exec_impl(inner, stdin, stdout), // TODO
+        Ast::Command {
+            assignments,
+            command,
+            args,
+        } => exec_command(assignments, command, args, session, io),
+        Ast::Assignment { assignments } => exec_assignment(assignments, session, io),
+        Ast::Pipe { left, right } => exec_pipe(left, right, false, session, io),
+        Ast::PipeAll { left, right } => exec_pipe(left, right, true, session, io),
+        Ast::RedirectOut { left, right } => exec_redirect_out(left, right, session, io),
+        Ast::RedirectAppend { left, right } => exec_redirect_append(left, right, session, io),
+        Ast::HereDoc { left, body, expand } => exec_here_doc(left, body, *expand, session, io),
+        Ast::HereString { left, right } => exec_here_string(left, right, session, io),
+        Ast::And { left, right } => exec_and(left, right, session, io),
+        Ast::Or { left, right } => exec_or(left, right, session, io),
+        Ast::Sequence { left, right } => exec_sequence(left, right, session, io),
+        Ast::Subshell { inner } => exec_subshell(inner, session, io),
+        Ast::Group { inner } => exec_impl(inner, session, io),
+        Ast::Background { inner } => {
+            exec_background(inner, session, io);
+            Ok(Process::Exited(exit_status(0)))
+        }
+        Ast::Not { inner } => exec_not(inner, session, io),
+        Ast::Time { inner, posix } => exec_time(inner, *posix, session, io),
+        Ast::Arithmetic { expression } => exec_arithmetic(expression, session),
+        Ast::Function { name, body } => {
+            session.functions.insert(name.clone(), (**body).clone());
+            Ok(Process::Exited(exit_status(0)))
+        }
     }
 }
 
+/// Runs a function's body with `args` as the positional parameters, then
+/// the `RETURN` trap.
+pub fn call_function(
+    body: &Ast,
+    args: &[String],
+    session: &mut Session,
+    io: Io,
+) -> io::Result<ExitStatus> {
+    let saved = std::mem::replace(&mut session.args, args.to_vec());
+    session.function_depth += 1;
+    let status = exec_impl(body, session, io).and_then(Process::wait);
+    session.function_depth -= 1;
+    session.args = saved;
+
+    let status = match session.returning.take() {
+        Some(code) => exit_status(code),
+        None => status?,
+    };
+    session.status = status_code(status);
+    run_trap("RETURN", session)?;
+    Ok(status)
+}
+
 fn exec_command(
+    assignments: &[Token],
     command: &Token,
     args: &[Token],
-    stdin: Option<Stdio>,
-    stdout: Option<Stdio>,
-) -> io::Result<Child> {
-    let mut cmd = Command::new(command.as_ref());
+    session: &mut Session,
+    mut io: Io,
+) -> io::Result<Process> {
+    run_trap("DEBUG", session)?;
+    let mut substitutions = Substitutions::default();
+    let words = std::iter::once(command)
+        .chain(args)
+        .map(|word| substitutions.substitute(word, session))
+        .collect::<io::Result<Vec<_>>>()?;
+    let fields = expand(&words, session)?;
+    let assignments = assignments
+        .iter()
+        .map(|word| expand_assignment(word, session))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if session.options.xtrace {
+        trace(&assignments, &fields, session, &mut io)?;
+    }
+
+    // Assignments apply to the shell itself when there's no command left
+    // after expansion, or it's a special builtin.
+    let Some((command, args)) = fields.split_first() else {
+        assign(session, assignments)?;
+        return Ok(substitutions.finish(Process::Exited(exit_status(0))));
+    };
 
-    for arg in args {
-        cmd.arg(arg.as_ref());
+    // Functions come before builtins, other than special builtins.
+    let builtin = builtin::lookup(command);
+    let function = match builtin {
+        Some(_) if builtin::is_special(command) => None,
+        _ => session.functions.get(command).cloned(),
+    };
+    if function.is_some() || builtin.is_some() {
+        let status = if builtin::is_special(command) {
+            assign(session, assignments)?;
+            builtin.unwrap()(session, args, &mut io).map(exit_status)?
+        } else {
+            let saved = assignments
+                .iter()
+                .map(|(name, _)| {
+                    let var = session.vars.get(name).cloned();
+                    (name.clone(), var, session.arrays.get(name).cloned())
+                })
+                .collect::<Vec<_>>();
+            assign(session, assignments)?;
+            let status = match function {
+                Some(body) => call_function(&body, args, session, io),
+                None => builtin.unwrap()(session, args, &mut io).map(exit_status),
+            };
+            for (name, var, array) in saved {
+                session.vars.remove(&name);
+                session.arrays.remove(&name);
+                if let Some(var) = var {
+                    session.vars.insert(name.clone(), var);
+                }
+                if let Some(array) = array {
+                    session.arrays.insert(name, array);
+                }
+            }
+            status?
+        };
+        return Ok(substitutions.finish(Process::Exited(status)));
     }
 
-    if let Some(stdin) = stdin {
+    let mut cmd = Command::new(command);
+    cmd.current_dir(&session.cwd);
+    cmd.args(args);
+    cmd.env_clear();
+    cmd.envs(session.env());
+    // Arrays can't be exported.
+    cmd.envs(assignments.iter().filter_map(|(name, value)| match value {
+        Value::String(value) => Some((name, value)),
+        Value::Array(_) => None,
+    }));
+    substitutions.keep_open(&mut cmd);
+    if session.interactive {
+        // Signals ignored with `trap` stay ignored in the command.
+        let ignored = session
+            .traps
+            .iter()
+            .filter(|(_, action)| action.is_empty())
+            .filter_map(|(name, _)| signal::number(name))
+            .collect::<Vec<_>>();
+        // SAFETY: restoring signal dispositions is async-signal-safe.
+        unsafe { cmd.pre_exec(move || signal::restore_defaults(&ignored)) };
+    }
+
+    if let Some(stdin) = io.stdin {
         cmd.stdin(stdin);
     }
 
-    if let Some(stdout) = stdout {
+    if let Some(stdout) = io.stdout {
         cmd.stdout(stdout);
     }
 
-    cmd.spawn()
+    if let Some(stderr) = io.stderr {
+        cmd.stderr(stderr);
+    }
+
+    let child = cmd.spawn()?;
+    Ok(substitutions.finish(Process::Child(child, session.usage.clone())))
+}
+
+fn exec_assignment(
+    assignments: &[Token],
+    session: &mut Session,
+    mut io: Io,
+) -> io::Result<Process> {
+    run_trap("DEBUG", session)?;
+    let assignments = assignments
+        .iter()
+        .map(|word| expand_assignment(word, session))
+        .collect::<Result<Vec<_>, _>>()?;
+    if session.options.xtrace {
+        trace(&assignments, &[], session, &mut io)?;
+    }
+    assign(session, assignments)?;
+    Ok(Process::Exited(exit_status(0)))
+}
+
+/// Prints a command as it's about to run for `set -x`, after `PS4`.
+fn trace(
+    assignments: &[(String, Value)],
+    fields: &[String],
+    session: &mut Session,
+    io: &mut Io,
+) -> io::Result<()> {
+    let words = assignments
+        .iter()
+        .map(|(name, value)| match value {
+            Value::String(value) => format!("{}={}", name, trace_word(value)),
+            Value::Array(elements) => {
+                let elements = elements.iter().map(|e| trace_word(e)).collect::<Vec<_>>();
+                format!("{}=({})", name, elements.join(" "))
+            }
+        })
+        .chain(fields.iter().map(|field| trace_word(field)))
+        .collect::<Vec<_>>();
+    // Commands run while expanding `PS4` aren't traced.
+    let ps4 = session.var("PS4").unwrap_or("+ ").to_string();
+    session.options.xtrace = false;
+    let prefix = prompt::expand(&ps4, session);
+    session.options.xtrace = true;
+    writeln!(io.err(), "{}{}", printed(&prefix), words.join(" "))
+}
+
+fn trace_word(word: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "%+,-./:=@_".contains(c);
+    if !word.is_empty() && word.chars().all(plain) {
+        word.to_string()
+    } else {
+        quote(word)
+    }
+}
+
+fn assign(session: &mut Session, assignments: Vec<(String, Value)>) -> io::Result<()> {
+    for (name, value) in assignments {
+        match value {
+            Value::String(value) => session.set_var(&name, value)?,
+            Value::Array(elements) => session.set_array(&name, elements)?,
+        }
+    }
+    Ok(())
+}
+
+/// The process substitutions of a command, which run until it has finished.
+#[derive(Default)]
+struct Substitutions {
+    /// The shell's ends of the pipes, which the command reaches via
+    /// `/dev/fd`.
+    fds: Vec<File>,
+    processes: Vec<Process>,
+}
+
+impl Substitutions {
+    /// Starts `word` if it's a process substitution, `<(list)` or `>(list)`,
+    /// and replaces it with the path of a pipe from or to the list. The list
+    /// otherwise has the shell's streams, as the command's redirections
+    /// don't apply to it.
+    fn substitute(&mut self, word: &Token, session: &Session) -> io::Result<Token> {
+        let text = word.to_string();
+        let (input, inner) = match text.split_at_checked(2) {
+            Some(("<(", inner)) => (true, inner),
+            Some((">(", inner)) => (false, inner),
+            _ => return Ok(word.clone()),
+        };
+        let Some(inner) = inner.strip_suffix(')') else {
+            return Ok(word.clone());
+        };
+        let tokens = Lexer::lex_with(inner, &session.options)?;
+        let ast = Parser::parse(&alias::expand(tokens, session)?)?;
+
+        let (reader, writer) = pipe()?;
+        let (fd, inner_io) = if input {
+            let io = Io {
+                stdout: Some(writer),
+                ..Io::default()
+            };
+            (reader, io)
+        } else {
+            let io = Io {
+                stdin: Some(reader),
+                ..Io::default()
+            };
+            (writer, io)
+        };
+
+        let mut session = session.clone();
+        self.processes
+            .push(Process::Thread(std::thread::spawn(move || {
+                exec_impl(&ast, &mut session, inner_io)?.wait()
+            })));
+
+        let path = format!("/dev/fd/{}", fd.as_raw_fd());
+        self.fds.push(fd);
+        Ok(Token::Input(path))
+    }
+
+    /// Lets `cmd` inherit the pipes, which are otherwise closed on exec.
+    fn keep_open(&self, cmd: &mut Command) {
+        if self.fds.is_empty() {
+            return;
+        }
+
+        let fds = self.fds.iter().map(File::as_raw_fd).collect::<Vec<_>>();
+        // SAFETY: `fcntl` is async-signal-safe and the closure doesn't
+        // allocate.
+        unsafe {
+            cmd.pre_exec(move || {
+                for &fd in &fds {
+                    if libc::fcntl(fd, libc::F_SETFD, 0) == -1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+    }
+
+    /// Closes the shell's ends of the pipes now that the command has started,
+    /// and reaps the substitutions along with it. The command's status is the
+    /// one reported.
+    fn finish(self, process: Process) -> Process {
+        drop(self.fds);
+        if self.processes.is_empty() {
+            return process;
+        }
+        let mut processes = self.processes;
+        processes.push(process);
+        Process::Pipeline(processes)
+    }
 }
 
-fn exec_pipe(left: &Ast, right: &Ast, stdout: Option<Stdio>) -> io::Result<Child> {
-    let child = exec_impl(left, None, Some(Stdio::piped()))?;
-    exec_impl(right, Some(Stdio::from(child.stdout.unwrap())), stdout)
+/// Both sides of a pipe run against a copy of the session, as in a subshell.
+/// With `stderr`, for `|&`, the left's stderr goes into the pipe too.
+fn exec_pipe(
+    left: &Ast,
+    right: &Ast,
+    stderr: bool,
+    session: &Session,
+    io: Io,
+) -> io::Result<Process> {
+    let (reader, writer) = pipe()?;
+
+    let left_io = Io {
+        stdin: io.stdin,
+        stderr: if stderr {
+            Some(writer.try_clone()?)
+        } else {
+            try_clone(&io.stderr)?
+        },
+        stdout: Some(writer),
+    };
+    // The left-hand side runs on its own thread, so that a function or
+    // builtin can fill the pipe while the right-hand side drains it.
+    let left = {
+        let left = left.clone();
+        let mut session = session.clone();
+        Process::Thread(std::thread::spawn(move || {
+            exec_impl(&left, &mut session, left_io)?.wait()
+        }))
+    };
+
+    let right_io = Io {
+        stdin: Some(reader),
+        stdout: io.stdout,
+        stderr: io.stderr,
+    };
+    let right = exec_impl(right, &mut session.clone(), right_io)?;
+
+    if session.options.pipefail {
+        Ok(Process::PipeFail(vec![left, right]))
+    } else {
+        Ok(Process::Pipeline(vec![left, right]))
+    }
 }
 
-fn exec_redirect_out(left: &Ast, right: &Token) -> io::Result<Child> {
+fn exec_redirect_out(
+    left: &Ast,
+    right: &Token,
+    session: &mut Session,
+    io: Io,
+) -> io::Result<Process> {
+    let name = expand_one(right, session)?;
+    let path = session.cwd.join(&name);
+    if session.options.noclobber && path.is_file() {
+        return Err(Error::Clobber(name).into());
+    }
     let file = OpenOptions::new()
         .create(true)
         .truncate(true)
         .write(true)
-        .open(right.as_ref())?;
-    exec_impl(left, None, Some(Stdio::from(file)))
+        .open(path)?;
+    exec_impl(
+        left,
+        session,
+        Io {
+            stdout: Some(file),
+            ..io
+        },
+    )
 }
 
-fn exec_redirect_append(left: &Ast, right: &Token) -> io::Result<Child> {
-    let file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(right.as_ref())?;
-    exec_impl(left, None, Some(Stdio::from(file)))
+fn exec_here_doc(
+    left: &Ast,
+    body: &str,
+    expand: bool,
+    session: &mut Session,
+    io: Io,
+) -> io::Result<Process> {
+    let text = if expand {
+        expand_here_doc(body, session)?
+    } else {
+        body.to_string()
+    };
+    exec_with_input(left, text, session, io)
 }
 
-fn exec_and(left: &Ast, right: &Ast, stdout: Option<Stdio>) -> io::Result<Child> {
-    let mut child = exec_impl(left, None, None)?;
-    if child.wait()?.success() {
-        exec_impl(right, None, stdout)
+fn exec_here_string(
+    left: &Ast,
+    right: &Token,
+    session: &mut Session,
+    io: Io,
+) -> io::Result<Process> {
+    let text = expand_string(right, session)? + "\n";
+    exec_with_input(left, text, session, io)
+}
+
+/// Runs `left` with `text` on its stdin. The text is written from another
+/// thread so that a body bigger than the pipe buffer can't block the shell.
+/// The thread is waited for along with `left`, which needn't read it all.
+fn exec_with_input(left: &Ast, text: String, session: &mut Session, io: Io) -> io::Result<Process> {
+    let (reader, mut writer) = pipe()?;
+    let handle = std::thread::spawn(move || match writer.write_all(text.as_bytes()) {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(e),
+        _ => Ok(ExitStatus::from_raw(0)),
+    });
+    let process = exec_impl(
+        left,
+        session,
+        Io {
+            stdin: Some(reader),
+            ..io
+        },
+    )?;
+    Ok(Process::Pipeline(vec![Process::Thread(handle), process]))
+}
+
+fn exec_redirect_append(
+    left: &Ast,
+    right: &Token,
+    session: &mut Session,
+    io: Io,
+) -> io::Result<Process> {
+    let path = expand_one(right, session)?;
+    let path = session.cwd.join(path);
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    exec_impl(
+        left,
+        session,
+        Io {
+            stdout: Some(file),
+            ..io
+        },
+    )
+}
+
+fn exec_and(left: &Ast, right: &Ast, session: &mut Session, io: Io) -> io::Result<Process> {
+    let status = exec_condition(left, session, io.try_clone()?)?;
+    session.status = status_code(status);
+    run_signal_traps(session)?;
+    if status.success() && !stopped(session) {
+        exec_last(right, session, io)
     } else {
-        Ok(child)
+        Ok(Process::Exited(status))
     }
 }
 
-fn exec_or(left: &Ast, right: &Ast, stdout: Option<Stdio>) -> io::Result<Child> {
-    let mut child = exec_impl(left, None, None)?;
-    if !child.wait()?.success() {
-        exec_impl(right, None, stdout)
+fn exec_or(left: &Ast, right: &Ast, session: &mut Session, io: Io) -> io::Result<Process> {
+    let status = exec_condition(left, session, io.try_clone()?)?;
+    session.status = status_code(status);
+    run_signal_traps(session)?;
+    if !status.success() && !stopped(session) {
+        exec_last(right, session, io)
     } else {
-        Ok(child)
+        Ok(Process::Exited(status))
+    }
+}
+
+/// Whether the rest of a list should be skipped, because the shell is
+/// exiting, a function is returning or Ctrl-C interrupted it.
+fn stopped(session: &Session) -> bool {
+    session.exit.is_some()
+        || session.returning.is_some()
+        || session.interactive && signal::interrupted()
+}
+
+/// Runs `ast` to completion as a condition, where `set -e` doesn't apply.
+fn exec_condition(ast: &Ast, session: &mut Session, io: Io) -> io::Result<ExitStatus> {
+    let outer = std::mem::replace(&mut session.in_condition, true);
+    let status = exec_impl(ast, session, io).and_then(Process::wait);
+    session.in_condition = outer;
+    status
+}
+
+/// Runs the command after the final `&&` or `||` of a list, the only one in
+/// it that `set -e` and the `ERR` trap apply to.
+fn exec_last(ast: &Ast, session: &mut Session, io: Io) -> io::Result<Process> {
+    if !session.options.errexit && !session.traps.contains_key("ERR") || session.in_condition {
+        return exec_impl(ast, session, io);
+    }
+    let status = exec_impl(ast, session, io)?.wait()?;
+    session.status = status_code(status);
+    failed(ast, status, session)?;
+    Ok(Process::Exited(status))
+}
+
+/// Runs the `ERR` trap, and exits the shell under `set -e`, if `ast` failed,
+/// unless it's exempt.
+fn failed(ast: &Ast, status: ExitStatus, session: &mut Session) -> io::Result<()> {
+    if status.success() || session.in_condition || session.exit.is_some() || ignores_errexit(ast) {
+        return Ok(());
+    }
+    run_trap("ERR", session)?;
+    if session.options.errexit {
+        session.exit = Some(status_code(status));
+    }
+    Ok(())
+}
+
+/// Runs the trap set for `name`, if any, through the usual lexer, parser and
+/// executor. `$?` is kept as it was before the trap.
+pub fn run_trap(name: &str, session: &mut Session) -> io::Result<()> {
+    let action = match session.traps.get(name) {
+        Some(action) if !action.is_empty() && !session.in_trap => action.clone(),
+        _ => return Ok(()),
+    };
+
+    let status = session.status;
+    session.in_trap = true;
+    let result = Lexer::lex_with(&action, &session.options)
+        .and_then(|tokens| alias::expand(tokens, session))
+        .and_then(|tokens| Parser::parse(&tokens))
+        .map_err(io::Error::from)
+        .and_then(|ast| exec_impl(&ast, session, Io::default())?.wait());
+    session.in_trap = false;
+    session.status = status;
+    result.map(|_| ())
+}
+
+/// Runs the traps for the signals that have arrived since the last check.
+/// The signal handler only records them, so that traps run between commands.
+fn run_signal_traps(session: &mut Session) -> io::Result<()> {
+    if session.in_trap {
+        return Ok(());
+    }
+    let mut signals = session
+        .traps
+        .keys()
+        .filter_map(|name| signal::number(name))
+        .collect::<Vec<_>>();
+    signals.sort();
+    for number in signals {
+        if signal::take_pending(number) {
+            run_trap(signal::name(number).unwrap(), session)?;
+        }
     }
+    Ok(())
 }
 
-fn exec_sequence(left: &Ast, right: &Ast, stdout: Option<Stdio>) -> io::Result<Child> {
-    let mut child = exec_impl(left, None, None)?;
-    _ = child.wait();
-    exec_impl(right, None, stdout)
+/// Whether the status of `ast` comes from a part of it that is exempt from
+/// `set -e`, or has already been checked.
+fn ignores_errexit(ast: &Ast) -> bool {
+    match ast {
+        Ast::And { .. } | Ast::Or { .. } | Ast::Not { .. } => true,
+        Ast::Sequence { right: inner, .. }
+        | Ast::Group { inner }
+        | Ast::Time { inner, .. }
+        | Ast::RedirectOut { left: inner, .. }
+        | Ast::RedirectAppend { left: inner, .. }
+        | Ast::HereDoc { left: inner, .. }
+        | Ast::HereString { left: inner, .. } => ignores_errexit(inner),
+        _ => false,
+    }
+}
+
+fn exec_sequence(left: &Ast, right: &Ast, session: &mut Session, io: Io) -> io::Result<Process> {
+    let status = exec_impl(left, session, io.try_clone()?)?.wait();
+    if let Ok(status) = status {
+        session.status = status_code(status);
+        failed(left, status, session)?;
+    }
+    run_signal_traps(session)?;
+    if stopped(session) {
+        return status.map(Process::Exited);
+    }
+    exec_impl(right, session, io)
+}
+
+/// Runs `inner` against a copy of the session that is thrown away afterwards,
+/// so `cd`, assignments and `exit` inside the parentheses stay there. Traps
+/// are reset in the subshell, other than ignored signals.
+fn exec_subshell(inner: &Ast, session: &Session, io: Io) -> io::Result<Process> {
+    let mut session = session.clone();
+    session.traps.retain(|_, action| action.is_empty());
+    let status = exec_impl(inner, &mut session, io)?.wait()?;
+    session.status = status_code(status);
+    run_trap("EXIT", &mut session)?;
+    Ok(Process::Exited(status))
+}
+
+fn exec_not(inner: &Ast, session: &mut Session, io: Io) -> io::Result<Process> {
+    let status = exec_condition(inner, session, io)?;
+    Ok(Process::Exited(exit_status(if status.success() {
+        1
+    } else {
+        0
+    })))
+}
+
+/// `(( ... ))` succeeds when the expression is non-zero.
+fn exec_arithmetic(expression: &str, session: &mut Session) -> io::Result<Process> {
+    let value = evaluate(expression, session)?;
+    Ok(Process::Exited(exit_status((value == 0) as i32)))
+}
+
+/// Runs `inner` to completion and reports how long it took on stderr,
+/// formatted according to `TIMEFORMAT`.
+fn exec_time(inner: &Ast, posix: bool, session: &mut Session, mut io: Io) -> io::Result<Process> {
+    let start = Instant::now();
+    let usage = Usage::default();
+    let outer = session.usage.replace(usage.clone());
+    let status = exec_impl(inner, session, io.try_clone()?).and_then(Process::wait);
+    session.usage = outer;
+    let (user, sys) = usage.get();
+    // A `time` inside another counts towards both.
+    if let Some(outer) = &session.usage {
+        outer.add(user, sys);
+    }
+    let status = status?;
+    let times = Times {
+        real: start.elapsed(),
+        user,
+        sys,
+    };
+
+    let format = match session.var("TIMEFORMAT") {
+        _ if posix => POSIX_FORMAT,
+        Some(format) => format,
+        None => DEFAULT_FORMAT,
+    };
+    if !format.is_empty() {
+        writeln!(io.err(), "{}", times.format(format))?;
+    }
+
+    Ok(Process::Exited(status))
+}
+
+fn exec_background(inner: &Ast, session: &Session, io: Io) -> JoinHandle<io::Result<ExitStatus>> {
+    let inner = inner.clone();
+    let mut session = session.clone();
+    session.jobs.fetch_add(1, Ordering::Relaxed);
+    std::thread::spawn(move || {
+        let status = exec_impl(&inner, &mut session, io).and_then(Process::wait);
+        session.jobs.fetch_sub(1, Ordering::Relaxed);
+        status
+    })
+}
+
+/// Runs a script in the current session a command at a time, as for a
+/// startup file. Errors are reported with `name` and the line the command
+/// starts on, and the rest of the script still runs. Returns the status of
+/// the last command.
+pub fn run_script(
+    name: &str,
+    script: &str,
+    session: &mut Session,
+    io: &mut Io,
+) -> io::Result<ExitStatus> {
+    let lines = script.split_inclusive('\n').collect::<Vec<_>>();
+    let mut status = exit_status(0);
+    let mut command = String::new();
+    let mut start = 0;
+
+    for (i, line) in lines.iter().enumerate() {
+        if command.is_empty() {
+            start = i + 1;
+        }
+        command.push_str(line);
+        let parsed = Lexer::lex_with(&command, &session.options).and_then(|tokens| {
+            match tokens.is_empty() {
+                true => Ok(None),
+                false => alias::expand(tokens, session).map(Some),
+            }
+        });
+        let parsed = parsed.and_then(|tokens| match tokens {
+            Some(tokens) => Parser::parse(&tokens).map(Some),
+            None => Ok(None),
+        });
+        let ast = match parsed {
+            // Keep reading lines until the command is complete.
+            Err(e) if e.is_incomplete() && i + 1 < lines.len() => continue,
+            Ok(None) => {
+                command.clear();
+                continue;
+            }
+            Ok(Some(ast)) => Ok(ast),
+            Err(e) => Err(e),
+        };
+        command.clear();
+
+        let result = ast.map_err(io::Error::from).and_then(|ast| {
+            let status = exec_impl(&ast, session, io.try_clone()?)?.wait()?;
+            session.status = status_code(status);
+            failed(&ast, status, session)?;
+            run_signal_traps(session)?;
+            Ok(status)
+        });
+        status = match result {
+            Ok(status) => status,
+            Err(e) => {
+                writeln!(io.err(), "{}: line {}: {}", name, start, e)?;
+                session.status = 1;
+                exit_status(1)
+            }
+        };
+        if stopped(session) {
+            break;
+        }
+    }
+
+    Ok(status)
+}
+
+/// Runs a file's contents in the current session for `source`, with `args`
+/// as the positional parameters if there are any, then the `RETURN` trap.
+/// `return` stops the file.
+pub fn source(
+    name: &str,
+    script: &str,
+    args: &[String],
+    session: &mut Session,
+    io: &mut Io,
+) -> io::Result<ExitStatus> {
+    let saved = match args.is_empty() {
+        true => None,
+        false => Some(std::mem::replace(&mut session.args, args.to_vec())),
+    };
+    session.source_depth += 1;
+    let status = run_script(name, script, session, io);
+    session.source_depth -= 1;
+    if let Some(saved) = saved {
+        session.args = saved;
+    }
+
+    let status = match session.returning.take() {
+        Some(code) => exit_status(code),
+        None => status?,
+    };
+    session.status = status_code(status);
+    run_trap("RETURN", session)?;
+    Ok(status)
+}
+
+/// Runs `source` in a copy of the session and returns what it writes to
+/// stdout, as for a command substitution.
+pub fn capture(source: &str, session: &Session) -> io::Result<String> {
+    let tokens = Lexer::lex_with(source, &session.options)?;
+    let ast = Parser::parse(&alias::expand(tokens, session)?)?;
+    let (mut reader, writer) = pipe()?;
+    let io = Io {
+        stdout: Some(writer),
+        ..Io::default()
+    };
+    let mut session = session.clone();
+    let thread = std::thread::spawn(move || exec_impl(&ast, &mut session, io)?.wait());
+
+    let mut output = Vec::new();
+    reader.read_to_end(&mut output)?;
+    thread
+        .join()
+        .map_err(|_| io::Error::other("command panicked"))??;
+    Ok(String::from_utf8_lossy(&output).into_owned())
 }
 
 #[cfg(test)]
@@ -131,26 +936,69 @@ mod tests {
 
     use super::*;
 
+    fn capture(exec: impl FnOnce(Io) -> io::Result<Process>) -> String {
+        let (mut reader, writer) = pipe().unwrap();
+        let process = exec(Io {
+            stdout: Some(writer),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut output = String::new();
+        reader.read_to_string(&mut output).unwrap();
+        process.wait().unwrap();
+        output
+    }
+
+    #[test]
+    fn test_run_script() {
+        let dir = TempDir::new("").unwrap();
+        let mut session = Session::new();
+        session.cwd = dir.path().to_path_buf();
+        let mut io = Io {
+            stdout: Some(File::create(dir.path().join("out")).unwrap()),
+            stderr: Some(File::create(dir.path().join("err")).unwrap()),
+            ..Default::default()
+        };
+
+        let script = "# setup\n\
+            greet() {\n  echo \"hi $1\"\n}\n\
+            does-not-exist\n\
+            greet 'a\nb'; x=1 \\\n  y=2\n\
+            echo )\n\
+            echo done\n";
+        let status = run_script("rc", script, &mut session, &mut io).unwrap();
+        assert!(status.success());
+        assert_eq!((session.var("x"), session.var("y")), (Some("1"), Some("2")));
+
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("out"), "hi a\nb\ndone\n");
+        let err = read("err");
+        assert!(err.starts_with("rc: line 5: "));
+        assert!(err.ends_with("rc: line 9: parse error near )\n"));
+    }
+
     #[test]
     fn test_exec_command() {
         let command = input!("echo");
         let args = vec![input!("foo")];
-        let stdout = Stdio::piped();
-        let child = exec_command(&command, &args, None, Some(stdout)).unwrap();
-        let output = child.wait_with_output().unwrap();
-        assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "foo\n");
+        let output = capture(|io| exec_command(&[], &command, &args, &mut Session::new(), io));
+        assert_eq!(&output, "foo\n");
     }
 
     #[test]
     fn test_exec_redirect_out() {
         let left = Ast::Command {
+            assignments: vec![],
             command: input!("echo"),
             args: vec![input!("foo")],
         };
         let dir = TempDir::new("").unwrap();
         let path = dir.path().join("output.txt");
         let right = input!(path.to_str().unwrap());
-        exec_redirect_out(&left, &right).unwrap().wait().unwrap();
+        exec_redirect_out(&left, &right, &mut Session::new(), Io::default())
+            .unwrap()
+            .wait()
+            .unwrap();
         let mut result = String::new();
         File::open(&path)
             .unwrap()
@@ -169,11 +1017,15 @@ mod tests {
             .unwrap();
 
         let left = Ast::Command {
+            assignments: vec![],
             command: input!("echo"),
             args: vec![input!("bar")],
         };
         let right = input!(path.to_str().unwrap());
-        exec_redirect_append(&left, &right).unwrap().wait().unwrap();
+        exec_redirect_append(&left, &right, &mut Session::new(), Io::default())
+            .unwrap()
+            .wait()
+            .unwrap();
         let mut result = String::new();
         File::open(&path)
             .unwrap()
@@ -185,104 +1037,117 @@ mod tests {
     #[test]
     fn test_exec_pipe() {
         let left = Ast::Command {
+            assignments: vec![],
             command: input!("echo"),
             args: vec![input!("foo\nbar")],
         };
         let right = Ast::Command {
+            assignments: vec![],
             command: input!("grep"),
             args: vec![input!("foo")],
         };
-        let stdout = Stdio::piped();
-        let child = exec_pipe(&left, &right, Some(stdout)).unwrap();
-        let output = child.wait_with_output().unwrap();
-        assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "foo\n");
+        let output = capture(|io| exec_pipe(&left, &right, false, &Session::new(), io));
+        assert_eq!(&output, "foo\n");
 
         let left = Ast::Command {
+            assignments: vec![],
             command: input!("echo"),
             args: vec![input!("foo   bar")],
         };
         let right = Ast::Command {
+            assignments: vec![],
             command: input!("tr"),
             args: vec![input!("-s"), input!(" ")],
         };
-        let stdout = Stdio::piped();
-        let out = exec_pipe(&left, &right, Some(stdout)).unwrap();
-        let output = out.wait_with_output().unwrap();
-        assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "foo bar\n");
+        let output = capture(|io| exec_pipe(&left, &right, false, &Session::new(), io));
+        assert_eq!(&output, "foo bar\n");
+
+        let left = Ast::Command {
+            assignments: vec![],
+            command: input!("ls"),
+            args: vec![input!("/nonexistent")],
+        };
+        let right = Ast::Command {
+            assignments: vec![],
+            command: input!("wc"),
+            args: vec![input!("-l")],
+        };
+        let output = capture(|io| exec_pipe(&left, &right, true, &Session::new(), io));
+        assert_eq!(output.trim(), "1");
     }
 
     #[test]
     fn test_exec_and() {
         let left = Ast::Command {
+            assignments: vec![],
             command: input!("true"),
             args: vec![],
         };
         let right = Ast::Command {
+            assignments: vec![],
             command: input!("echo"),
             args: vec![input!("foo")],
         };
-        let stdout = Stdio::piped();
-        let child = exec_and(&left, &right, Some(stdout)).unwrap();
-        let output = child.wait_with_output().unwrap();
-        assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "foo\n");
+        let output = capture(|io| exec_and(&left, &right, &mut Session::new(), io));
+        assert_eq!(&output, "foo\n");
 
         let left = Ast::Command {
+            assignments: vec![],
             command: input!("false"),
             args: vec![],
         };
         let right = Ast::Command {
+            assignments: vec![],
             command: input!("echo"),
             args: vec![input!("foo")],
         };
-        let stdout = Stdio::piped();
-        let child = exec_and(&left, &right, Some(stdout)).unwrap();
-        let output = child.wait_with_output().unwrap();
-        assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "");
+        let output = capture(|io| exec_and(&left, &right, &mut Session::new(), io));
+        assert_eq!(&output, "");
     }
 
     #[test]
     fn test_exec_or() {
         let left = Ast::Command {
+            assignments: vec![],
             command: input!("false"),
             args: vec![],
         };
         let right = Ast::Command {
+            assignments: vec![],
             command: input!("echo"),
             args: vec![input!("foo")],
         };
-        let stdout = Stdio::piped();
-        let child = exec_or(&left, &right, Some(stdout)).unwrap();
-        let output = child.wait_with_output().unwrap();
-        assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "foo\n");
+        let output = capture(|io| exec_or(&left, &right, &mut Session::new(), io));
+        assert_eq!(&output, "foo\n");
 
         let left = Ast::Command {
+            assignments: vec![],
             command: input!("true"),
             args: vec![],
         };
         let right = Ast::Command {
+            assignments: vec![],
             command: input!("echo"),
             args: vec![input!("foo")],
         };
-        let stdout = Stdio::piped();
-        let child = exec_or(&left, &right, Some(stdout)).unwrap();
-        let output = child.wait_with_output().unwrap();
-        assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "");
+        let output = capture(|io| exec_or(&left, &right, &mut Session::new(), io));
+        assert_eq!(&output, "");
     }
 
     #[test]
     fn test_exec_sequence() {
         let left = Ast::Command {
+            assignments: vec![],
             command: input!("echo"),
             args: vec![input!("foo")],
         };
         let right = Ast::Command {
+            assignments: vec![],
             command: input!("echo"),
             args: vec![input!("bar")],
         };
-        let stdout = Stdio::piped();
-        let child = exec_sequence(&left, &right, Some(stdout)).unwrap();
-        let output = child.wait_with_output().unwrap();
-        assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "bar\n");
+        let output = capture(|io| exec_sequence(&left, &right, &mut Session::new(), io));
+        assert_eq!(&output, "foo\nbar\n");
     }
 
     #[test]
@@ -291,6 +1156,7 @@ mod tests {
         let path = dir.path().join("output.txt");
         let left = Ast::RedirectOut {
             left: Box::new(Ast::Command {
+                assignments: vec![],
                 command: input!("echo"),
                 args: vec![input!("foo")],
             }),
@@ -298,12 +1164,16 @@ mod tests {
         };
         let right = Ast::RedirectAppend {
             left: Box::new(Ast::Command {
+                assignments: vec![],
                 command: input!("echo"),
                 args: vec![input!("bar")],
             }),
             right: input!(path.to_str().unwrap()),
         };
-        exec_sequence(&left, &right, None).unwrap().wait().unwrap();
+        exec_sequence(&left, &right, &mut Session::new(), Io::default())
+            .unwrap()
+            .wait()
+            .unwrap();
         let mut result = String::new();
         File::open(&path)
             .unwrap()
@@ -315,17 +1185,17 @@ mod tests {
     #[test]
     fn test_exec_sequence_left_error() {
         let left = Ast::Command {
+            assignments: vec![],
             command: input!("false"),
             args: vec![],
         };
         let right = Ast::Command {
+            assignments: vec![],
             command: input!("echo"),
             args: vec![input!("bar")],
         };
-        let stdout = Stdio::piped();
-        let child = exec_sequence(&left, &right, Some(stdout)).unwrap();
-        let output = child.wait_with_output().unwrap();
-        assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "bar\n");
+        let output = capture(|io| exec_sequence(&left, &right, &mut Session::new(), io));
+        assert_eq!(&output, "bar\n");
     }
 
     #[test]
@@ -335,6 +1205,7 @@ mod tests {
                 inner: Box::new(Ast::Subshell {
                     inner: Box::new(Ast::Subshell {
                         inner: Box::new(Ast::Command {
+                            assignments: vec![],
                             command: input!("echo"),
                             args: vec![input!("foo")],
                         }),
@@ -342,28 +1213,161 @@ mod tests {
                 }),
             }),
         };
-        let stdout = Stdio::piped();
-        let child = exec_impl(&ast, None, Some(stdout)).unwrap();
-        let output = child.wait_with_output().unwrap();
-        assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "foo\n");
+        let output = capture(|io| exec_impl(&ast, &mut Session::new(), io));
+        assert_eq!(&output, "foo\n");
+    }
+
+    #[test]
+    fn test_exec_impl_group_redirect() {
+        let dir = TempDir::new("").unwrap();
+        let path = dir.path().join("output.txt");
+        let ast = Ast::RedirectOut {
+            left: Box::new(Ast::Group {
+                inner: Box::new(Ast::Sequence {
+                    left: Box::new(Ast::Command {
+                        assignments: vec![],
+                        command: input!("echo"),
+                        args: vec![input!("foo")],
+                    }),
+                    right: Box::new(Ast::Command {
+                        assignments: vec![],
+                        command: input!("echo"),
+                        args: vec![input!("bar")],
+                    }),
+                }),
+            }),
+            right: input!(path.to_str().unwrap()),
+        };
+        exec_impl(&ast, &mut Session::new(), Io::default())
+            .unwrap()
+            .wait()
+            .unwrap();
+        let mut result = String::new();
+        File::open(&path)
+            .unwrap()
+            .read_to_string(&mut result)
+            .unwrap();
+        assert_eq!(&result, "foo\nbar\n");
+    }
+
+    #[test]
+    fn test_exec_impl_group_piped() {
+        let ast = Ast::Pipe {
+            left: Box::new(Ast::Group {
+                inner: Box::new(Ast::Sequence {
+                    left: Box::new(Ast::Command {
+                        assignments: vec![],
+                        command: input!("echo"),
+                        args: vec![input!("foo")],
+                    }),
+                    right: Box::new(Ast::Command {
+                        assignments: vec![],
+                        command: input!("echo"),
+                        args: vec![input!("bar")],
+                    }),
+                }),
+            }),
+            right: Box::new(Ast::Command {
+                assignments: vec![],
+                command: input!("wc"),
+                args: vec![input!("-l")],
+            }),
+        };
+        let output = capture(|io| exec_impl(&ast, &mut Session::new(), io));
+        assert_eq!(output.trim(), "2");
+    }
+
+    #[test]
+    fn test_exec_not() {
+        let ast = Ast::Command {
+            assignments: vec![],
+            command: input!("false"),
+            args: vec![],
+        };
+        let status = exec_not(&ast, &mut Session::new(), Io::default())
+            .unwrap()
+            .wait()
+            .unwrap();
+        assert!(status.success());
+
+        let ast = Ast::Command {
+            assignments: vec![],
+            command: input!("true"),
+            args: vec![],
+        };
+        let status = exec_not(&ast, &mut Session::new(), Io::default())
+            .unwrap()
+            .wait()
+            .unwrap();
+        assert_eq!(status.code(), Some(1));
+    }
+
+    #[test]
+    fn test_exec_time() {
+        let ast = Ast::Command {
+            assignments: vec![],
+            command: input!("sh"),
+            args: vec![input!("-c"), input!("exit 4")],
+        };
+        let mut session = Session::new();
+        session
+            .vars
+            .insert("TIMEFORMAT".to_string(), "took %0R".to_string());
+        let (mut reader, writer) = pipe().unwrap();
+        let io = Io {
+            stderr: Some(writer),
+            ..Default::default()
+        };
+        let status = exec_time(&ast, false, &mut session, io)
+            .unwrap()
+            .wait()
+            .unwrap();
+        assert_eq!(status.code(), Some(4));
+        let mut output = String::new();
+        reader.read_to_string(&mut output).unwrap();
+        assert_eq!(&output, "took 0\n");
+
+        // Commands on other threads, like the left of a pipe, count too.
+        let busy = "sh -c 'i=0; while [ $i -lt 100000 ]; do i=$((i + 1)); done' | cat";
+        let ast = Parser::parse(&Lexer::lex(busy).unwrap()).unwrap();
+        session
+            .vars
+            .insert("TIMEFORMAT".to_string(), "%3U %3S".to_string());
+        let (mut reader, writer) = pipe().unwrap();
+        let io = Io {
+            stderr: Some(writer),
+            ..Default::default()
+        };
+        exec_time(&ast, false, &mut session, io)
+            .unwrap()
+            .wait()
+            .unwrap();
+        let mut output = String::new();
+        reader.read_to_string(&mut output).unwrap();
+        let cpu = output
+            .split_whitespace()
+            .map(|time| time.parse::<f64>().unwrap())
+            .sum::<f64>();
+        assert!(cpu > 0.0, "{}", output);
+        assert!(session.usage.is_none());
     }
 
     #[test]
     fn test_exec_impl_with_pipe() {
         let ast = Ast::Pipe {
             left: Box::new(Ast::Command {
+                assignments: vec![],
                 command: input!("echo"),
                 args: vec![input!("foo\nbar")],
             }),
             right: Box::new(Ast::Command {
+                assignments: vec![],
                 command: input!("grep"),
                 args: vec![input!("foo")],
             }),
         };
-        let stdout = Stdio::piped();
-        let child = exec_impl(&ast, None, Some(stdout)).unwrap();
-        let output = child.wait_with_output().unwrap();
-        assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "foo\n");
+        let output = capture(|io| exec_impl(&ast, &mut Session::new(), io));
+        assert_eq!(&output, "foo\n");
     }
 
     #[test]
@@ -371,25 +1375,23 @@ mod tests {
         let ast = Ast::Pipe {
             left: Box::new(Ast::Pipe {
                 left: Box::new(Ast::Command {
+                    assignments: vec![],
                     command: input!("echo"),
                     args: vec![input!("foo")],
                 }),
                 right: Box::new(Ast::Command {
+                    assignments: vec![],
                     command: input!("wc"),
                     args: vec![],
                 }),
             }),
             right: Box::new(Ast::Command {
+                assignments: vec![],
                 command: input!("wc"),
                 args: vec![],
             }),
         };
-        let stdout = Stdio::piped();
-        let child = exec_impl(&ast, None, Some(stdout)).unwrap();
-        let output = child.wait_with_output().unwrap();
-        assert_eq!(
-            std::str::from_utf8(&output.stdout).unwrap(),
-            "       1       3      25\n"
-        );
+        let output = capture(|io| exec_impl(&ast, &mut Session::new(), io));
+        assert_eq!(&output, "       1       3      25\n");
     }
 }
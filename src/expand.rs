@@ -0,0 +1,1325 @@
+use crate::{
+    arith, error::Error, exec::capture, glob, glob::Pattern, grammar::Token, lex::Lexer,
+    session::Session,
+};
+
+/// A character of a word after quote removal, remembering whether it was
+/// quoted so that later stages can treat it literally.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Char {
+    pub c: char,
+    pub quoted: bool,
+}
+
+/// Expands the words of a command into the fields it is run with.
+pub fn expand(words: &[Token], session: &mut Session) -> Result<Vec<String>, Error> {
+    let mut fields = vec![];
+    for word in words {
+        fields.extend(expand_word(&word.to_string(), session)?);
+    }
+    Ok(fields)
+}
+
+/// Expands a word that has to produce exactly one field, such as the target
+/// of a redirection.
+pub fn expand_one(word: &Token, session: &mut Session) -> Result<String, Error> {
+    let mut fields = expand_word(&word.to_string(), session)?;
+    match fields.len() {
+        1 => Ok(fields.remove(0)),
+        _ => Err(Error::AmbiguousRedirect(word.to_string())),
+    }
+}
+
+/// Expands a word into a single string, without brace expansion, field
+/// splitting or globbing, as for a here-string.
+pub fn expand_string(word: &Token, session: &mut Session) -> Result<String, Error> {
+    let word = expand_tilde(&word.to_string(), session);
+    Ok(to_string(&expand_chars(&word, session)?))
+}
+
+/// The value of an assignment.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    /// The elements of `name=(...)`.
+    Array(Vec<String>),
+}
+
+/// Splits a `NAME=value` word and expands the value into a single string, or
+/// a `NAME=(word...)` word and expands the words into the elements of an
+/// array as they would be the arguments of a command.
+pub fn expand_assignment(word: &Token, session: &mut Session) -> Result<(String, Value), Error> {
+    let word = word.to_string();
+    let (name, value) = word.split_once('=').unwrap_or((&word, ""));
+    if let Some(inner) = value.strip_prefix('(').and_then(|v| v.strip_suffix(')')) {
+        let mut words = vec![];
+        for token in Lexer::lex_with(inner, &session.options)? {
+            match token {
+                Token::Input(_) => words.push(token),
+                Token::Semicolon => {}
+                token => return Err(Error::Parse(token)),
+            }
+        }
+        return Ok((name.to_string(), Value::Array(expand(&words, session)?)));
+    }
+    let value = expand_string(&Token::Input(value.to_string()), session)?;
+    Ok((name.to_string(), Value::String(value)))
+}
+
+fn expand_word(word: &str, session: &mut Session) -> Result<Vec<String>, Error> {
+    let mut fields = vec![];
+    for word in expand_braces(word) {
+        fields.extend(expand_fields(&word, session)?);
+    }
+    Ok(fields)
+}
+
+fn expand_fields(word: &str, session: &mut Session) -> Result<Vec<String>, Error> {
+    let word = expand_tilde(word, session);
+    let expansion = expand_parameters(&word, session)?;
+    let ifs = session.var("IFS").unwrap_or(" \t\n").to_string();
+
+    let mut fields = vec![];
+    for chars in split_fields(expansion, &ifs) {
+        if session.options.noglob || !glob::has_magic(&chars, &session.options) {
+            fields.push(to_string(&chars));
+            continue;
+        }
+
+        let matches = glob::glob(&chars, &session.cwd, &session.options);
+        if !matches.is_empty() {
+            fields.extend(matches);
+        } else if session.options.failglob {
+            return Err(Error::NoMatch(word.to_string()));
+        } else if !session.options.nullglob {
+            fields.push(to_string(&chars));
+        }
+    }
+
+    Ok(fields)
+}
+
+/// Expands `{a,b}` alternatives and `{x..y[..step]}` sequences in a word as
+/// written, before any other expansion. Quoted and unmatched braces, and
+/// braces without a top-level comma or a valid sequence, are left alone.
+pub fn expand_braces(word: &str) -> Vec<String> {
+    let chars = word.chars().collect::<Vec<_>>();
+    let mut i = 0;
+
+    while let Some(open) = find_unquoted(&chars, i, |c| c == '{') {
+        i = open + 1;
+
+        let Some((close, commas)) = find_brace_end(&chars, open) else {
+            continue;
+        };
+        let prefix = chars[..open].iter().collect::<String>();
+        let amble = &chars[open + 1..close];
+        let suffix = chars[close + 1..].iter().collect::<String>();
+
+        let alternatives = if commas.is_empty() {
+            match sequence(&amble.iter().collect::<String>()) {
+                Some(items) => items,
+                None => continue,
+            }
+        } else {
+            let mut start = open + 1;
+            let mut alternatives = vec![];
+            for comma in commas.into_iter().chain([close]) {
+                alternatives.push(chars[start..comma].iter().collect::<String>());
+                start = comma + 1;
+            }
+            alternatives
+        };
+
+        return alternatives
+            .into_iter()
+            .flat_map(|alternative| expand_braces(&format!("{}{}{}", prefix, alternative, suffix)))
+            .collect();
+    }
+
+    vec![word.to_string()]
+}
+
+/// Finds the next character from `start` matching `f` that isn't quoted,
+/// escaped or inside a `${...}`.
+fn find_unquoted(chars: &[char], start: usize, f: impl Fn(char) -> bool) -> Option<usize> {
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '\'' => i = skip_until(chars, i + 1, |c| c == '\''),
+            '"' => {
+                while i + 1 < chars.len() && chars[i + 1] != '"' {
+                    i += if chars[i + 1] == '\\' { 2 } else { 1 };
+                }
+                i += 1;
+            }
+            '$' if chars.get(i + 1) == Some(&'{') => i = skip_until(chars, i + 2, |c| c == '}'),
+            c if f(c) => return Some(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+fn skip_until(chars: &[char], start: usize, f: impl Fn(char) -> bool) -> usize {
+    chars[start.min(chars.len())..]
+        .iter()
+        .position(|&c| f(c))
+        .map_or(chars.len(), |i| start + i)
+}
+
+/// Finds the `}` matching the `{` at `open`, and the top-level commas
+/// between them.
+fn find_brace_end(chars: &[char], open: usize) -> Option<(usize, Vec<usize>)> {
+    let mut depth = 0;
+    let mut commas = vec![];
+    let mut i = open + 1;
+
+    while let Some(j) = find_unquoted(chars, i, |c| matches!(c, '{' | '}' | ',')) {
+        match chars[j] {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some((j, commas)),
+            '}' => depth -= 1,
+            _ if depth == 0 => commas.push(j),
+            _ => {}
+        }
+        i = j + 1;
+    }
+
+    None
+}
+
+/// Expands the inside of a `{x..y[..step]}` sequence expression, of either
+/// integers or single characters.
+fn sequence(amble: &str) -> Option<Vec<String>> {
+    let parts = amble.split("..").collect::<Vec<_>>();
+    let (start, end, step) = match parts[..] {
+        [start, end] => (start, end, None),
+        [start, end, step] => (start, end, Some(step.parse::<i64>().ok()?)),
+        _ => return None,
+    };
+    let step = step.unwrap_or(1).unsigned_abs().max(1) as usize;
+
+    if let (Ok(x), Ok(y)) = (start.parse::<i64>(), end.parse::<i64>()) {
+        let padded = |s: &str| {
+            s.trim_start_matches('-').len() > 1 && s.trim_start_matches('-').starts_with('0')
+        };
+        let width = if padded(start) || padded(end) {
+            start.len().max(end.len())
+        } else {
+            0
+        };
+        let items: Vec<i64> = if x <= y {
+            (x..=y).step_by(step).collect()
+        } else {
+            (y..=x).rev().step_by(step).collect()
+        };
+        return Some(
+            items
+                .into_iter()
+                .map(|n| format!("{:0width$}", n, width = width))
+                .collect(),
+        );
+    }
+
+    let mut start_chars = start.chars();
+    let mut end_chars = end.chars();
+    match (
+        start_chars.next(),
+        start_chars.next(),
+        end_chars.next(),
+        end_chars.next(),
+    ) {
+        (Some(x), None, Some(y), None) if x.is_ascii_alphabetic() && y.is_ascii_alphabetic() => {
+            let items: Vec<char> = if x <= y {
+                (x..=y).step_by(step).collect()
+            } else {
+                (y..=x).rev().step_by(step).collect()
+            };
+            Some(items.into_iter().map(String::from).collect())
+        }
+        _ => None,
+    }
+}
+
+/// A word after parameter expansion and quote removal.
+struct Expansion {
+    chars: Vec<Char>,
+    /// Whether each character came from an unquoted expansion, and so is
+    /// subject to field splitting.
+    splittable: Vec<bool>,
+    /// Whether the word contained any quoting, in which case it produces a
+    /// field even when it expands to nothing.
+    quoted: bool,
+    /// Indices of the characters that start a new field whatever `IFS` is,
+    /// between the values of a quoted `$@` or `${name[@]}`.
+    breaks: Vec<usize>,
+}
+
+/// Expands parameters in a word as written and strips its quotes and
+/// backslashes, marking the characters they protected as quoted.
+pub fn expand_chars(word: &str, session: &mut Session) -> Result<Vec<Char>, Error> {
+    Ok(expand_parameters(word, session)?.chars)
+}
+
+fn expand_parameters(word: &str, session: &mut Session) -> Result<Expansion, Error> {
+    let word = word.chars().collect::<Vec<_>>();
+    let mut chars = vec![];
+    let mut splittable = vec![];
+    let mut quoted = false;
+    let mut breaks = vec![];
+    let mut i = 0;
+
+    while i < word.len() {
+        let c = word[i];
+        i += 1;
+
+        if matches!(c, '\\' | '\'') {
+            quoted = true;
+        }
+
+        match c {
+            '\\' => {
+                if let Some(&c) = word.get(i) {
+                    chars.push(Char { c, quoted: true });
+                    i += 1;
+                }
+            }
+            '\'' => {
+                while let Some(&c) = word.get(i) {
+                    i += 1;
+                    if c == '\'' {
+                        break;
+                    }
+                    chars.push(Char { c, quoted: true });
+                }
+            }
+            '"' => {
+                // A `"$@"` or `"${name[@]}"` with no values makes no field,
+                // unless something else in the quotes does.
+                let start = chars.len();
+                let mut only_empty_fields = false;
+                while let Some(&c) = word.get(i) {
+                    i += 1;
+                    match c {
+                        '"' => break,
+                        '\\' if matches!(word.get(i), Some('$' | '`' | '"' | '\\' | '\n')) => {
+                            chars.push(Char {
+                                c: word[i],
+                                quoted: true,
+                            });
+                            i += 1;
+                        }
+                        '$' => match quoted_fields(&word, i, session) {
+                            Some((fields, len)) => {
+                                i += len;
+                                only_empty_fields |= fields.is_empty() && chars.len() == start;
+                                for (n, field) in fields.iter().enumerate() {
+                                    if n > 0 {
+                                        breaks.push(chars.len());
+                                    }
+                                    chars.extend(field.chars().map(|c| Char { c, quoted: true }));
+                                }
+                            }
+                            None => chars.extend(expand_dollar(&word, &mut i, true, session)?),
+                        },
+                        c => chars.push(Char { c, quoted: true }),
+                    }
+                }
+                quoted |= !only_empty_fields || chars.len() > start;
+            }
+            '$' => {
+                let expanded = expand_dollar(&word, &mut i, false, session)?;
+                splittable.resize(chars.len(), false);
+                splittable.resize(chars.len() + expanded.len(), true);
+                chars.extend(expanded);
+            }
+            c => chars.push(Char { c, quoted: false }),
+        }
+    }
+
+    splittable.resize(chars.len(), false);
+
+    Ok(Expansion {
+        chars,
+        splittable,
+        quoted,
+        breaks,
+    })
+}
+
+/// The values of the `$@`, `${@}` or `${name[@]}` at `word[i]`, just after a
+/// `$` in double quotes, each of which makes a separate field, and how many
+/// characters it takes.
+fn quoted_fields(word: &[char], i: usize, session: &Session) -> Option<(Vec<String>, usize)> {
+    match word.get(i)? {
+        '@' => return Some((session.args.clone(), 1)),
+        '{' => {}
+        _ => return None,
+    }
+    let end = find_closing_brace(word, i + 1)?;
+    let inner = word[i + 1..end].iter().collect::<String>();
+    let fields = match inner.strip_suffix("[@]") {
+        _ if inner == "@" => session.args.clone(),
+        Some(name) if is_name(name) => session.elements(name),
+        _ => return None,
+    };
+    Some((fields, end + 1 - i))
+}
+
+/// Splits the unquoted results of expansions into fields wherever they
+/// contain a character of `ifs`. A run of `IFS` whitespace, or a single other
+/// `IFS` character with any whitespace around it, separates two fields, and
+/// whitespace at either end is dropped. A quoted `$@` or `${name[@]}` also
+/// separates fields between its values.
+fn split_fields(expansion: Expansion, ifs: &str) -> Vec<Vec<Char>> {
+    let Expansion {
+        chars,
+        splittable,
+        quoted,
+        breaks,
+    } = expansion;
+    let is_ifs = |i: usize| splittable[i] && !chars[i].quoted && ifs.contains(chars[i].c);
+    let is_blank = |i: usize| is_ifs(i) && matches!(chars[i].c, ' ' | '\t' | '\n');
+    let skip_blanks = |i: &mut usize| {
+        while *i < chars.len() && is_blank(*i) {
+            *i += 1;
+        }
+    };
+
+    let mut fields = vec![];
+    let mut field = vec![];
+    let mut breaks = breaks.into_iter().peekable();
+    // Whether the last field was ended by a break, so the next one is kept
+    // even if it's empty.
+    let mut broken = false;
+    let mut i = 0;
+    skip_blanks(&mut i);
+
+    loop {
+        while breaks.next_if(|&b| b <= i).is_some() {
+            fields.push(std::mem::take(&mut field));
+            broken = true;
+        }
+        if i == chars.len() {
+            break;
+        }
+
+        if !is_ifs(i) {
+            field.push(chars[i]);
+            i += 1;
+            continue;
+        }
+
+        skip_blanks(&mut i);
+        if i < chars.len() && is_ifs(i) {
+            i += 1;
+            skip_blanks(&mut i);
+        }
+        fields.push(std::mem::take(&mut field));
+        broken = false;
+    }
+
+    if !field.is_empty() || broken || fields.is_empty() && quoted {
+        fields.push(field);
+    }
+
+    fields
+}
+
+/// Expands the body of a here-document, which is treated like text in double
+/// quotes except that a `"` has no special meaning.
+pub fn expand_here_doc(body: &str, session: &mut Session) -> Result<String, Error> {
+    let body = body.chars().collect::<Vec<_>>();
+    let mut chars = vec![];
+    let mut i = 0;
+
+    while i < body.len() {
+        let c = body[i];
+        i += 1;
+
+        match c {
+            '\\' if body.get(i) == Some(&'\n') => i += 1,
+            '\\' if matches!(body.get(i), Some('$' | '`' | '\\')) => {
+                chars.push(Char {
+                    c: body[i],
+                    quoted: true,
+                });
+                i += 1;
+            }
+            '$' => chars.extend(expand_dollar(&body, &mut i, true, session)?),
+            c => chars.push(Char { c, quoted: true }),
+        }
+    }
+
+    Ok(to_string(&chars))
+}
+
+/// Expands the `$` expression starting at `word[*i]`, just after the `$`,
+/// and moves `i` past it.
+fn expand_dollar(
+    word: &[char],
+    i: &mut usize,
+    quoted: bool,
+    session: &mut Session,
+) -> Result<Vec<Char>, Error> {
+    let chars = |s: String| s.chars().map(|c| Char { c, quoted }).collect();
+
+    match word.get(*i) {
+        Some('{') => {
+            let end = find_closing_brace(word, *i + 1)
+                .ok_or_else(|| Error::BadSubstitution(word[*i - 1..].iter().collect()))?;
+            let inner = word[*i + 1..end].iter().collect::<String>();
+            *i = end + 1;
+            let mut expanded = expand_braced(&inner, session)?;
+            if quoted {
+                expanded.iter_mut().for_each(|c| c.quoted = true);
+            }
+            Ok(expanded)
+        }
+        Some('(') if word.get(*i + 1) == Some(&'(') => {
+            let bad = || Error::BadSubstitution(word[*i - 1..].iter().collect());
+            let end = find_closing_paren(word, *i + 1).ok_or_else(bad)?;
+            if word.get(end + 1) != Some(&')') {
+                return Err(bad());
+            }
+            let expression = word[*i + 2..end].iter().collect::<String>();
+            *i = end + 2;
+            Ok(chars(evaluate(&expression, session)?.to_string()))
+        }
+        Some('(') => {
+            let end = find_closing_paren(word, *i)
+                .ok_or_else(|| Error::BadSubstitution(word[*i - 1..].iter().collect()))?;
+            let command = word[*i + 1..end].iter().collect::<String>();
+            *i = end + 1;
+            let output =
+                capture(&command, session).map_err(|e| Error::Substitution(e.to_string()))?;
+            Ok(chars(output.trim_end_matches('\n').to_string()))
+        }
+        Some(&c) if is_special(c) || c.is_ascii_digit() => {
+            *i += 1;
+            Ok(chars(expand_parameter(&c.to_string(), session)?))
+        }
+        Some(&c) if c.is_ascii_alphabetic() || c == '_' => {
+            let len = word[*i..]
+                .iter()
+                .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+                .count();
+            let name = word[*i..*i + len].iter().collect::<String>();
+            *i += len;
+            Ok(chars(expand_parameter(&name, session)?))
+        }
+        _ => Ok(vec![Char { c: '$', quoted }]),
+    }
+}
+
+/// Finds the `}` closing a `${`, skipping quoted text and nested `${...}`.
+fn find_closing_brace(word: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    while i < word.len() {
+        match word[i] {
+            '\\' => i += 1,
+            '\'' => i = skip_until(word, i + 1, |c| c == '\''),
+            '$' if word.get(i + 1) == Some(&'{') => {
+                depth += 1;
+                i += 1;
+            }
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Finds the `)` matching the `(` at `start`, skipping quoted text, nested
+/// `$(...)` and backquoted commands.
+pub fn find_closing_paren(word: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut in_double_quotes = false;
+    let mut i = start;
+    while i < word.len() {
+        match word[i] {
+            '\\' => i += 1,
+            '\'' if !in_double_quotes => i = skip_until(word, i + 1, |c| c == '\''),
+            '"' => in_double_quotes = !in_double_quotes,
+            '`' => {
+                i += 1;
+                while i < word.len() && word[i] != '`' {
+                    i += if word[i] == '\\' { 2 } else { 1 };
+                }
+            }
+            '$' if word.get(i + 1) == Some(&'(') => i = find_closing_paren(word, i + 1)?,
+            _ if in_double_quotes => {}
+            '(' => depth += 1,
+            ')' if depth == 1 => return Some(i),
+            ')' => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+fn is_special(c: char) -> bool {
+    matches!(c, '?' | '$' | '#' | '@' | '*' | '!' | '-')
+}
+
+/// The value of a parameter, or nothing if it's unset, unless `set -u` makes
+/// that an error.
+fn expand_parameter(name: &str, session: &mut Session) -> Result<String, Error> {
+    match parameter(name, session) {
+        Some(value) => Ok(value),
+        None if session.options.nounset => {
+            if !session.interactive {
+                session.exit = Some(1);
+            }
+            Err(Error::Unbound(name.to_string()))
+        }
+        None => Ok(String::new()),
+    }
+}
+
+/// The value of a variable, positional or special parameter, if it is set.
+pub fn parameter(name: &str, session: &Session) -> Option<String> {
+    match name {
+        "?" => Some(session.status.to_string()),
+        "$" => Some(std::process::id().to_string()),
+        "#" => Some(session.args.len().to_string()),
+        "@" | "*" => Some(session.args.join(" ")),
+        "0" => Some(session.name.clone()),
+        "!" | "-" => None,
+        name if name.chars().all(|c| c.is_ascii_digit()) => {
+            let n = name.parse::<usize>().ok()?;
+            session.args.get(n.checked_sub(1)?).cloned()
+        }
+        name => session.var(name).map(str::to_string),
+    }
+}
+
+/// Expands the inside of `${...}`.
+fn expand_braced(inner: &str, session: &mut Session) -> Result<Vec<Char>, Error> {
+    let bad = || Error::BadSubstitution(format!("${{{}}}", inner));
+    let unquoted = |s: String| s.chars().map(|c| Char { c, quoted: false }).collect();
+
+    // ${!prefix*} and ${!prefix@}
+    if let Some(prefix) = inner
+        .strip_prefix('!')
+        .and_then(|s| s.strip_suffix('*').or_else(|| s.strip_suffix('@')))
+    {
+        let mut names = session
+            .vars
+            .keys()
+            .chain(session.arrays.keys())
+            .filter(|name| name.starts_with(prefix))
+            .cloned()
+            .collect::<Vec<_>>();
+        names.sort();
+        return Ok(unquoted(names.join(" ")));
+    }
+
+    // ${#name}, ${#name[@]} and ${#name[subscript]}
+    if let Some(name) = inner.strip_prefix('#').filter(|name| !name.is_empty()) {
+        if let Some((name, subscript)) = split_subscript(name) {
+            let length = match subscript {
+                "@" | "*" => session.elements(name).len(),
+                _ => element(name, subscript, session)?
+                    .unwrap_or_default()
+                    .chars()
+                    .count(),
+            };
+            return Ok(unquoted(length.to_string()));
+        }
+        if !is_parameter_name(name) {
+            return Err(bad());
+        }
+        let value = expand_parameter(name, session)?;
+        return Ok(unquoted(value.chars().count().to_string()));
+    }
+
+    let name_len = match inner.chars().next() {
+        Some(c) if is_special(c) => 1,
+        Some(c) if c.is_ascii_digit() => inner.chars().take_while(char::is_ascii_digit).count(),
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => inner
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+            .count(),
+        _ => return Err(bad()),
+    };
+    let (name, op) = inner.split_at(name_len);
+    // ${name[subscript]}, followed by any of the operators below.
+    let (value, op, subscript) = match op.strip_prefix('[').and_then(|rest| rest.split_once(']')) {
+        Some((subscript, op)) if is_name(name) => (element(name, subscript, session)?, op, true),
+        _ => (parameter(name, session), op, false),
+    };
+    let tests_unset = matches!(
+        op.trim_start_matches(':').chars().next(),
+        Some('-' | '=' | '?' | '+')
+    );
+    if value.is_none() && !tests_unset {
+        expand_parameter(name, session)?;
+    }
+
+    if op.is_empty() {
+        return Ok(unquoted(value.unwrap_or_default()));
+    }
+
+    // ${name:-word}, ${name-word} and friends. With the colon, an empty value
+    // is treated like an unset one.
+    let (colon, rest) = match op.strip_prefix(':') {
+        Some(rest) if rest.starts_with(['-', '=', '?', '+']) => (true, rest),
+        _ => (false, op),
+    };
+    if let Some(kind @ ('-' | '=' | '?' | '+')) = rest.chars().next() {
+        let word = &rest[1..];
+        let set = value.as_ref().is_some_and(|v| !colon || !v.is_empty());
+        return match (kind, set) {
+            ('-', true) | ('=', true) | ('?', true) => Ok(unquoted(value.unwrap())),
+            ('+', false) => Ok(vec![]),
+            ('-', false) | ('+', true) => expand_chars(word, session),
+            ('=', false) => {
+                if !is_name(name) || subscript {
+                    return Err(Error::BadAssignment(name.to_string()));
+                }
+                let value = to_string(&expand_chars(word, session)?);
+                session.set_var(name, value.clone())?;
+                Ok(unquoted(value))
+            }
+            ('?', false) => {
+                let message = match to_string(&expand_chars(word, session)?) {
+                    message if message.is_empty() => "parameter null or not set".to_string(),
+                    message => message,
+                };
+                if !session.interactive {
+                    session.exit = Some(1);
+                }
+                Err(Error::Parameter(name.to_string(), message))
+            }
+            _ => unreachable!(),
+        };
+    }
+
+    let value = value.unwrap_or_default();
+    let text = value.chars().collect::<Vec<_>>();
+    let substring = |start: usize, end: usize| text[start..end].iter().collect::<String>();
+
+    // ${name:offset} and ${name:offset:length}
+    if let Some(rest) = op.strip_prefix(':') {
+        let (offset, length) = match rest.split_once(':') {
+            Some((offset, length)) => (offset, Some(length)),
+            None => (rest, None),
+        };
+        let len = text.len() as i64;
+        let offset = evaluate(offset, session)?;
+        let start = if offset < 0 { len + offset } else { offset }.clamp(0, len);
+        let end = match length {
+            None => len,
+            Some(length) => {
+                let length = evaluate(length, session)?;
+                if length < 0 {
+                    len + length
+                } else {
+                    start.saturating_add(length)
+                }
+            }
+        }
+        .clamp(start, len);
+        return Ok(unquoted(substring(start as usize, end as usize)));
+    }
+
+    // ${name^^}, ${name^}, ${name,,} and ${name,}
+    let case = match op {
+        "^^" => Some(value.to_uppercase()),
+        ",," => Some(value.to_lowercase()),
+        "^" | "," => {
+            let mut chars = value.chars();
+            Some(match chars.next() {
+                Some(first) if op == "^" => first.to_uppercase().chain(chars).collect(),
+                Some(first) => first.to_lowercase().chain(chars).collect(),
+                None => String::new(),
+            })
+        }
+        _ => None,
+    };
+    if let Some(value) = case {
+        return Ok(unquoted(value));
+    }
+
+    // ${name#pattern}, ${name##pattern}, ${name%pattern} and ${name%%pattern}
+    for (prefix, longest, from_start) in [
+        ("##", true, true),
+        ("#", false, true),
+        ("%%", true, false),
+        ("%", false, false),
+    ] {
+        let Some(pattern) = op.strip_prefix(prefix) else {
+            continue;
+        };
+        let pattern = Pattern::new(&expand_chars(pattern, session)?, &session.options);
+        let mut lengths = (0..=text.len()).collect::<Vec<_>>();
+        if longest {
+            lengths.reverse();
+        }
+        let value = lengths
+            .into_iter()
+            .find_map(|n| {
+                let (removed, kept) = if from_start {
+                    (substring(0, n), substring(n, text.len()))
+                } else {
+                    (
+                        substring(text.len() - n, text.len()),
+                        substring(0, text.len() - n),
+                    )
+                };
+                pattern.matches(&removed).then_some(kept)
+            })
+            .unwrap_or(value);
+        return Ok(unquoted(value));
+    }
+
+    // ${name/pattern/replacement}, with `//` replacing every match and `/#`
+    // and `/%` anchoring the match to the start or end.
+    if let Some(rest) = op.strip_prefix('/') {
+        let (mode, rest) = match rest.chars().next() {
+            Some(c @ ('/' | '#' | '%')) => (Some(c), &rest[1..]),
+            _ => (None, rest),
+        };
+        let (pattern, replacement) = rest.split_once('/').unwrap_or((rest, ""));
+        let pattern = Pattern::new(&expand_chars(pattern, session)?, &session.options);
+        let replacement = to_string(&expand_chars(replacement, session)?);
+
+        let mut output = String::new();
+        let mut start = 0;
+        while start <= text.len() {
+            let anchored = mode == Some('#') && start > 0;
+            let found = (!anchored)
+                .then(|| {
+                    let ends = if mode == Some('%') {
+                        text.len()..=text.len()
+                    } else {
+                        start + 1..=text.len()
+                    };
+                    ends.rev()
+                        .find(|&end| end > start && pattern.matches(&substring(start, end)))
+                })
+                .flatten();
+            match found {
+                Some(end) => {
+                    output.push_str(&replacement);
+                    start = end;
+                    if mode != Some('/') {
+                        output.push_str(&substring(start, text.len()));
+                        return Ok(unquoted(output));
+                    }
+                }
+                None if start < text.len() => {
+                    output.push(text[start]);
+                    start += 1;
+                }
+                None => break,
+            }
+        }
+        return Ok(unquoted(output));
+    }
+
+    Err(bad())
+}
+
+/// Splits `name[subscript]` into its name and subscript.
+fn split_subscript(word: &str) -> Option<(&str, &str)> {
+    let (name, rest) = word.split_once('[')?;
+    let subscript = rest.strip_suffix(']')?;
+    is_name(name).then_some((name, subscript))
+}
+
+/// The value of `name[subscript]`: an element of an array, counting back
+/// from the end for a negative index, or all of them separated by spaces for
+/// `@` or `*`. A variable is an array of one.
+fn element(name: &str, subscript: &str, session: &mut Session) -> Result<Option<String>, Error> {
+    let elements = session.elements(name);
+    if matches!(subscript, "@" | "*") {
+        return Ok((!elements.is_empty()).then(|| elements.join(" ")));
+    }
+    let index = evaluate(subscript, session)?;
+    let index = if index < 0 {
+        index + elements.len() as i64
+    } else {
+        index
+    };
+    Ok(usize::try_from(index)
+        .ok()
+        .and_then(|index| elements.get(index).cloned()))
+}
+
+/// Evaluates an arithmetic expression after expanding the parameters in it,
+/// as in `$(( ... ))` and the offset and length in `${name:offset:length}`.
+pub fn evaluate(expression: &str, session: &mut Session) -> Result<i64, Error> {
+    let expression = to_string(&expand_chars(expression, session)?);
+    arith::evaluate(&expression, session)
+}
+
+fn is_parameter_name(name: &str) -> bool {
+    name.len() == 1 && name.chars().all(is_special)
+        || name.chars().all(|c| c.is_ascii_digit())
+        || is_name(name)
+}
+
+/// Whether `name` is a valid variable name.
+pub fn is_name(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Quotes `s` so that the shell reads it back as a single literal word.
+pub fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Expands unquoted tilde prefixes in a word as written: `~` to `HOME`,
+/// `~user` to that user's home directory, and `~+` and `~-` to `PWD` and
+/// `OLDPWD`. In a word that looks like an assignment they are also expanded
+/// after the `=` and any `:`.
+fn expand_tilde(word: &str, session: &Session) -> String {
+    let chars = word.chars().collect::<Vec<_>>();
+    let assignment = chars
+        .iter()
+        .position(|&c| c == '=')
+        .filter(|&eq| is_name(&chars[..eq].iter().collect::<String>()));
+
+    let mut starts = vec![0];
+    if let Some(eq) = assignment {
+        starts.push(eq + 1);
+        while let Some(colon) = find_unquoted(&chars, *starts.last().unwrap(), |c| c == ':') {
+            starts.push(colon + 1);
+        }
+    }
+
+    let mut expanded = String::new();
+    let mut i = 0;
+    for start in starts {
+        if start < i || chars.get(start) != Some(&'~') {
+            continue;
+        }
+        let end = chars[start..]
+            .iter()
+            .position(|&c| c == '/' || assignment.is_some() && c == ':')
+            .map_or(chars.len(), |end| start + end);
+        let prefix = chars[start + 1..end].iter().collect::<String>();
+        if prefix.contains(['\'', '"', '\\', '$', '`']) {
+            continue;
+        }
+        if let Some(dir) = tilde_prefix(&prefix, session) {
+            expanded.extend(&chars[i..start]);
+            expanded.push_str(&quote(&dir));
+            i = end;
+        }
+    }
+    expanded.extend(&chars[i..]);
+
+    expanded
+}
+
+fn tilde_prefix(prefix: &str, session: &Session) -> Option<String> {
+    match prefix {
+        "" => session
+            .var("HOME")
+            .map(str::to_string)
+            .or_else(|| home_dir(None)),
+        "+" => Some(session.var("PWD").map_or_else(
+            || session.cwd.to_string_lossy().into_owned(),
+            str::to_string,
+        )),
+        "-" => session.var("OLDPWD").map(str::to_string),
+        user => home_dir(Some(user)),
+    }
+}
+
+/// Looks up a home directory in the passwd database, for `user` or for the
+/// current user.
+fn home_dir(user: Option<&str>) -> Option<String> {
+    let user = user.map(std::ffi::CString::new).transpose().ok()?;
+    let mut passwd = std::mem::MaybeUninit::<libc::passwd>::uninit();
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut result = std::ptr::null_mut();
+
+    // SAFETY: all pointers are valid for the duration of the call, and `buf`
+    // outlives the strings in `passwd` that point into it.
+    let home = unsafe {
+        let status = match &user {
+            Some(user) => libc::getpwnam_r(
+                user.as_ptr(),
+                passwd.as_mut_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            ),
+            None => libc::getpwuid_r(
+                libc::getuid(),
+                passwd.as_mut_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            ),
+        };
+        if status != 0 || result.is_null() {
+            return None;
+        }
+        std::ffi::CStr::from_ptr((*result).pw_dir)
+    };
+
+    Some(home.to_string_lossy().into_owned())
+}
+
+fn to_string(chars: &[Char]) -> String {
+    chars.iter().map(|c| c.c).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::input;
+
+    #[test]
+    fn test_remove_quotes() {
+        let session = &mut Session::new();
+        let mut remove_quotes = |word: &str| expand_chars(word, session).unwrap();
+        assert_eq!(to_string(&remove_quotes("foo")), "foo");
+        assert_eq!(to_string(&remove_quotes("'foo bar'")), "foo bar");
+        assert_eq!(to_string(&remove_quotes("\"foo\"bar")), "foobar");
+        assert_eq!(to_string(&remove_quotes("foo\\ bar")), "foo bar");
+        assert_eq!(to_string(&remove_quotes("'a\\b'")), "a\\b");
+        assert_eq!(to_string(&remove_quotes("\"a\\b\\\"\"")), "a\\b\"");
+        assert_eq!(
+            remove_quotes("a'*'"),
+            vec![
+                Char {
+                    c: 'a',
+                    quoted: false
+                },
+                Char {
+                    c: '*',
+                    quoted: true
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expand_glob() {
+        let dir = TempDir::new("").unwrap();
+        File::create(dir.path().join("b.rs")).unwrap();
+        File::create(dir.path().join("a.rs")).unwrap();
+        let mut session = Session::new();
+        session.cwd = dir.path().to_path_buf();
+
+        let words = vec![input!("ls"), input!("*.rs")];
+        assert_eq!(
+            expand(&words, &mut session).unwrap(),
+            vec!["ls", "a.rs", "b.rs"]
+        );
+
+        let words = vec![input!("'*'.rs"), input!("\\*.rs"), input!("*.md")];
+        assert_eq!(
+            expand(&words, &mut session).unwrap(),
+            vec!["*.rs", "*.rs", "*.md"]
+        );
+
+        session.options.nullglob = true;
+        assert_eq!(
+            expand(&[input!("*.md")], &mut session).unwrap(),
+            Vec::<String>::new()
+        );
+
+        session.options.failglob = true;
+        assert_eq!(
+            expand(&[input!("*.md")], &mut session),
+            Err(Error::NoMatch("*.md".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_expand_tilde() {
+        let mut session = Session::new();
+        session
+            .vars
+            .insert("HOME".to_string(), "/home/me".to_string());
+        session.vars.insert("PWD".to_string(), "/src".to_string());
+        session
+            .vars
+            .insert("OLDPWD".to_string(), "/old".to_string());
+
+        let mut expand = |word: &str| expand(&[input!(word)], &mut session).unwrap();
+        assert_eq!(expand("~"), vec!["/home/me"]);
+        assert_eq!(expand("~/src"), vec!["/home/me/src"]);
+        assert_eq!(expand("~+/x"), vec!["/src/x"]);
+        assert_eq!(expand("~-"), vec!["/old"]);
+        assert_eq!(expand("~root"), vec![home_dir(Some("root")).unwrap()]);
+        assert_eq!(expand("~no-such-user/x"), vec!["~no-such-user/x"]);
+        assert_eq!(expand("'~'/x"), vec!["~/x"]);
+        assert_eq!(expand("\\~"), vec!["~"]);
+        assert_eq!(expand("\"~\""), vec!["~"]);
+        assert_eq!(expand("a~"), vec!["a~"]);
+        assert_eq!(
+            expand("PATH=~/bin:~/.local"),
+            vec!["PATH=/home/me/bin:/home/me/.local"]
+        );
+        assert_eq!(expand("--prefix=~"), vec!["--prefix=~"]);
+    }
+
+    #[test]
+    fn test_expand_braces() {
+        assert_eq!(expand_braces("file{,.bak}"), vec!["file", "file.bak"]);
+        assert_eq!(
+            expand_braces("dir/{src,test}/{a,b}"),
+            vec!["dir/src/a", "dir/src/b", "dir/test/a", "dir/test/b"]
+        );
+        assert_eq!(expand_braces("{a,{b,c}d}e"), vec!["ae", "bde", "cde"]);
+        assert_eq!(expand_braces("{1..4}"), vec!["1", "2", "3", "4"]);
+        assert_eq!(expand_braces("{1..10..4}"), vec!["1", "5", "9"]);
+        assert_eq!(expand_braces("{3..1}"), vec!["3", "2", "1"]);
+        assert_eq!(expand_braces("{08..10}"), vec!["08", "09", "10"]);
+        assert_eq!(expand_braces("{-1..1}"), vec!["-1", "0", "1"]);
+        assert_eq!(expand_braces("{-01..1}"), vec!["-01", "000", "001"]);
+        assert_eq!(expand_braces("{a..e..2}"), vec!["a", "c", "e"]);
+        assert_eq!(expand_braces("{e..c}"), vec!["e", "d", "c"]);
+        assert_eq!(expand_braces("{a}"), vec!["{a}"]);
+        assert_eq!(expand_braces("{a,b"), vec!["{a,b"]);
+        assert_eq!(expand_braces("{1..z}"), vec!["{1..z}"]);
+        assert_eq!(expand_braces("'{a,b}'"), vec!["'{a,b}'"]);
+        assert_eq!(expand_braces("\\{a,b}"), vec!["\\{a,b}"]);
+        assert_eq!(expand_braces("{'a,b',c}"), vec!["'a,b'", "c"]);
+        assert_eq!(expand_braces("${a,b}"), vec!["${a,b}"]);
+        assert_eq!(expand_braces("{x}{a,b}"), vec!["{x}a", "{x}b"]);
+    }
+
+    #[test]
+    fn test_expand_parameters() {
+        let mut session = Session::new();
+        session.vars.clear();
+        session.vars.insert("x".to_string(), "foo bar".to_string());
+        session.vars.insert("empty".to_string(), String::new());
+        session
+            .vars
+            .insert("path".to_string(), "/usr/local/lib.tar.gz".to_string());
+        session.args = vec!["one".to_string(), "two".to_string()];
+        session.status = 3;
+
+        let mut expand = |word: &str| to_string(&expand_chars(word, &mut session).unwrap());
+        assert_eq!(expand("$x"), "foo bar");
+        assert_eq!(expand("a${x}b"), "afoo barb");
+        assert_eq!(expand("\"$x\""), "foo bar");
+        assert_eq!(expand("'$x'"), "$x");
+        assert_eq!(expand("\\$x"), "$x");
+        assert_eq!(expand("$nope$"), "$");
+        assert_eq!(expand("$? $# $1 ${2} $3"), "3 2 one two ");
+        assert_eq!(expand("${#x}"), "7");
+        assert_eq!(expand("${nope:-default}"), "default");
+        assert_eq!(expand("${empty:-default}"), "default");
+        assert_eq!(expand("${empty-default}"), "");
+        assert_eq!(expand("${x:+set}"), "set");
+        assert_eq!(expand("${nope+set}"), "");
+        assert_eq!(expand("${nope:-$x}"), "foo bar");
+        assert_eq!(expand("${path#*/}"), "usr/local/lib.tar.gz");
+        assert_eq!(expand("${path##*/}"), "lib.tar.gz");
+        assert_eq!(expand("${path%.*}"), "/usr/local/lib.tar");
+        assert_eq!(expand("${path%%.*}"), "/usr/local/lib");
+        assert_eq!(expand("${path#'/usr'}"), "/local/lib.tar.gz");
+        assert_eq!(expand("${x/o/0}"), "f0o bar");
+        assert_eq!(expand("${x//o/0}"), "f00 bar");
+        assert_eq!(expand("${x/#foo/baz}"), "baz bar");
+        assert_eq!(expand("${x/%bar}"), "foo ");
+        assert_eq!(expand("${x//[ao]}"), "f br");
+        assert_eq!(expand("${x:4}"), "bar");
+        assert_eq!(expand("${x:1:2}"), "oo");
+        assert_eq!(expand("${x: -3}"), "bar");
+        assert_eq!(expand("${x:1:-1}"), "oo ba");
+        assert_eq!(expand("${x^^}"), "FOO BAR");
+        assert_eq!(expand("${x^}"), "Foo bar");
+        assert_eq!(expand("${x^^},,"), "FOO BAR,,");
+        assert_eq!(expand("${!pa*}"), "path");
+        assert_eq!(expand("${new:=assigned} $new"), "assigned assigned");
+    }
+
+    #[test]
+    fn test_expand_arrays() {
+        let mut session = Session::new();
+        session.vars.insert("x".to_string(), "foo".to_string());
+        let elements = ["one", "two  three", ""].map(String::from).to_vec();
+        session.set_array("a", elements).unwrap();
+
+        let mut expand = |word: &str| to_string(&expand_chars(word, &mut session).unwrap());
+        assert_eq!(expand("$a ${a[1]} ${a[-3]}"), "one two  three one");
+        assert_eq!(expand("${a[0+1]^^}"), "TWO  THREE");
+        assert_eq!(expand("${#a[@]} ${#a[1]} ${#a}"), "3 10 3");
+        assert_eq!(
+            expand("${a[5]:-none} ${x[0]} ${x[1]-unset}"),
+            "none foo unset"
+        );
+        assert_eq!(expand("${!a*}"), "a");
+
+        let mut split = |word: &str| super::expand(&[input!(word)], &mut session).unwrap();
+        assert_eq!(split("\"${a[@]}\""), vec!["one", "two  three", ""]);
+        assert_eq!(split("${a[@]}"), vec!["one", "two", "three"]);
+        assert_eq!(split("\"${a[*]}\""), vec!["one two  three "]);
+        assert_eq!(split("\"${nope[@]}\""), Vec::<String>::new());
+
+        assert_eq!(
+            expand_assignment(&input!("b=(1 '2 3' ${x}{4,5})"), &mut session),
+            Ok((
+                "b".to_string(),
+                Value::Array(["1", "2 3", "foo4", "foo5"].map(String::from).to_vec())
+            ))
+        );
+        assert_eq!(
+            expand_assignment(&input!("b=(1 | 2)"), &mut session),
+            Err(Error::Parse(Token::Pipe))
+        );
+    }
+
+    #[test]
+    fn test_split_fields() {
+        let mut session = Session::new();
+        session
+            .vars
+            .insert("x".to_string(), "  foo  bar ".to_string());
+        session.vars.insert("empty".to_string(), String::new());
+        session
+            .vars
+            .insert("csv".to_string(), "a, b,,c:".to_string());
+
+        let mut split = |word: &str| expand(&[input!(word)], &mut session).unwrap();
+        assert_eq!(split("$x"), vec!["foo", "bar"]);
+        assert_eq!(split("\"$x\""), vec!["  foo  bar "]);
+        assert_eq!(split("a${x}b"), vec!["a", "foo", "bar", "b"]);
+        assert_eq!(split("'a b'$x"), vec!["a b", "foo", "bar"]);
+        assert_eq!(split("${nope:-1 2}"), vec!["1", "2"]);
+        assert_eq!(split("${nope:-'1 2'}"), vec!["1 2"]);
+        assert_eq!(split("$empty"), Vec::<String>::new());
+        assert_eq!(split("\"$empty\""), vec![""]);
+        assert_eq!(split("$empty''"), vec![""]);
+
+        session.args = vec!["a".to_string(), "b c".to_string(), String::new()];
+        let mut split = |word: &str| expand(&[input!(word)], &mut session).unwrap();
+        assert_eq!(split("\"$@\""), vec!["a", "b c", ""]);
+        assert_eq!(split("x\"${@}\"y"), vec!["xa", "b c", "y"]);
+        assert_eq!(split("$@"), vec!["a", "b", "c"]);
+        assert_eq!(split("\"$*\""), vec!["a b c "]);
+        session.args.clear();
+        let mut split = |word: &str| expand(&[input!(word)], &mut session).unwrap();
+        assert_eq!(split("\"$@\""), Vec::<String>::new());
+        assert_eq!(split("\"$@\"''"), vec![""]);
+        assert_eq!(split("\"x$@\""), vec!["x"]);
+
+        session.vars.insert("IFS".to_string(), ", :".to_string());
+        let mut split = |word: &str| expand(&[input!(word)], &mut session).unwrap();
+        assert_eq!(split("$csv"), vec!["a", "b", "", "c"]);
+        assert_eq!(split("$x"), vec!["foo", "bar"]);
+
+        session.vars.insert("IFS".to_string(), String::new());
+        let mut split = |word: &str| expand(&[input!(word)], &mut session).unwrap();
+        assert_eq!(split("$x"), vec!["  foo  bar "]);
+    }
+
+    #[test]
+    fn test_expand_here_doc() {
+        let mut session = Session::new();
+        session.vars.insert("x".to_string(), "foo".to_string());
+        assert_eq!(
+            expand_here_doc(
+                "\"$x\" '${x}'\n\\$x \\\\ \\a\\\nb $((1 + 1))\n",
+                &mut session
+            ),
+            Ok("\"foo\" 'foo'\n$x \\ \\ab 2\n".to_string())
+        );
+    }
+
+    #[test]
+    fn test_command_substitution() {
+        let mut session = Session::new();
+        session.vars.insert("x".to_string(), "a b".to_string());
+
+        let mut expand = |word: &str| expand(&[input!(word)], &mut session).unwrap();
+        assert_eq!(expand("$(echo $x)"), vec!["a", "b"]);
+        assert_eq!(expand("\"$(echo $x)\""), vec!["a b"]);
+        assert_eq!(expand("<$(printf 'x\\n\\n')>"), vec!["<x>"]);
+        assert_eq!(expand("$(echo $(echo hi))"), vec!["hi"]);
+        assert_eq!(expand("\"a$(echo \")\")b\""), vec!["a)b"]);
+        assert_eq!(
+            expand("$(echo ')' \\) \"$(echo \\\")\")\")"),
+            vec![")", ")", "\")"]
+        );
+        assert_eq!(expand("$(echo `\\)`)"), vec!["`)`"]);
+        assert_eq!(expand("$(true)"), Vec::<String>::new());
+
+        assert!(matches!(
+            expand_chars("$(nonexistent-command)", &mut session),
+            Err(Error::Substitution(_))
+        ));
+    }
+
+    #[test]
+    fn test_expand_arithmetic() {
+        let mut session = Session::new();
+        session.vars.insert("n".to_string(), "3".to_string());
+        session.vars.insert("x".to_string(), "foo bar".to_string());
+
+        let mut expand = |word: &str| to_string(&expand_chars(word, &mut session).unwrap());
+        assert_eq!(expand("$((1 + 2 * 3))"), "7");
+        assert_eq!(expand("a$(( (n + 1) * 2 ))b"), "a8b");
+        assert_eq!(expand("\"$((n << 2))\""), "12");
+        assert_eq!(expand("$(($n ** 2)) $((n++)) $n"), "9 3 4");
+        assert_eq!(expand("${x:n:n-2}"), "ba");
+
+        assert_eq!(
+            expand_chars("$((n / 0))", &mut session),
+            Err(Error::Arithmetic("division by 0".to_string()))
+        );
+        assert_eq!(
+            expand_chars("$((1)", &mut session),
+            Err(Error::BadSubstitution("$((1)".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_expand_parameter_errors() {
+        let mut session = Session::new();
+        assert_eq!(
+            expand_chars("${nope:?is missing}", &mut session),
+            Err(Error::Parameter(
+                "nope".to_string(),
+                "is missing".to_string()
+            ))
+        );
+        assert_eq!(session.exit, Some(1));
+
+        let mut session = Session::new();
+        session.interactive = true;
+        assert_eq!(
+            expand_chars("${nope?}", &mut session),
+            Err(Error::Parameter(
+                "nope".to_string(),
+                "parameter null or not set".to_string()
+            ))
+        );
+        assert_eq!(session.exit, None);
+
+        assert_eq!(
+            expand_chars("${x;}", &mut session),
+            Err(Error::BadSubstitution("${x;}".to_string()))
+        );
+        assert_eq!(
+            expand_chars("${1:=x}", &mut session),
+            Err(Error::BadAssignment("1".to_string()))
+        );
+    }
+}
@@ -0,0 +1,614 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use crate::{expand::Char, session::Options};
+
+/// A compiled shell pattern, as used for pathname expansion and `case`-style
+/// matching. Quoted characters in the source always match literally.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    tokens: Vec<PatternToken>,
+    nocase: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum PatternToken {
+    Literal(char),
+    /// `?`
+    Any,
+    /// `*`
+    Star,
+    /// `[...]`
+    Class {
+        negated: bool,
+        items: Vec<ClassItem>,
+    },
+    /// `?(...)`, `*(...)`, `+(...)`, `@(...)` or `!(...)` with `extglob`.
+    Extended {
+        kind: char,
+        alternatives: Vec<Vec<PatternToken>>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ClassItem {
+    Char(char),
+    Range(char, char),
+    Named(String),
+}
+
+impl Pattern {
+    pub fn new(chars: &[Char], options: &Options) -> Pattern {
+        Pattern {
+            tokens: parse_tokens(chars, options.extglob),
+            nocase: options.nocaseglob,
+        }
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        let text = text.chars().collect::<Vec<_>>();
+        let mut matcher = Matcher {
+            text: &text,
+            nocase: self.nocase,
+            tokens: HashMap::new(),
+            repeated: HashMap::new(),
+        };
+        matcher.tokens(&self.tokens, 0, text.len())
+    }
+
+    /// Whether the pattern starts with a literal `.`, which is needed for it
+    /// to match hidden files.
+    fn matches_hidden(&self) -> bool {
+        self.tokens.first() == Some(&PatternToken::Literal('.'))
+    }
+
+    fn literal(&self) -> Option<String> {
+        self.tokens
+            .iter()
+            .map(|token| match token {
+                PatternToken::Literal(c) => Some(*c),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+fn parse_tokens(chars: &[Char], extglob: bool) -> Vec<PatternToken> {
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let Char { c, quoted } = chars[i];
+        i += 1;
+
+        if quoted {
+            tokens.push(PatternToken::Literal(c));
+            continue;
+        }
+
+        if extglob && matches!(c, '?' | '*' | '+' | '@' | '!') {
+            if let Some((alternatives, len)) = parse_extended(&chars[i..], extglob) {
+                tokens.push(PatternToken::Extended {
+                    kind: c,
+                    alternatives,
+                });
+                i += len;
+                continue;
+            }
+        }
+
+        match c {
+            '?' => tokens.push(PatternToken::Any),
+            '*' => {
+                if tokens.last() != Some(&PatternToken::Star) {
+                    tokens.push(PatternToken::Star);
+                }
+            }
+            '[' => match parse_class(&chars[i..]) {
+                Some((class, len)) => {
+                    tokens.push(class);
+                    i += len;
+                }
+                None => tokens.push(PatternToken::Literal('[')),
+            },
+            c => tokens.push(PatternToken::Literal(c)),
+        }
+    }
+
+    tokens
+}
+
+/// Parses `(a|b)` following an extended glob operator, returning the
+/// alternatives and the number of characters consumed.
+fn parse_extended(chars: &[Char], extglob: bool) -> Option<(Vec<Vec<PatternToken>>, usize)> {
+    if chars.first()
+        != Some(&Char {
+            c: '(',
+            quoted: false,
+        })
+    {
+        return None;
+    }
+
+    let mut depth = 0;
+    let mut start = 1;
+    let mut alternatives = vec![];
+
+    for (i, Char { c, quoted }) in chars.iter().enumerate() {
+        if *quoted {
+            continue;
+        }
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    alternatives.push(parse_tokens(&chars[start..i], extglob));
+                    return Some((alternatives, i + 1));
+                }
+            }
+            '|' if depth == 1 => {
+                alternatives.push(parse_tokens(&chars[start..i], extglob));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Parses the inside of a bracket expression, returning the class and the
+/// number of characters consumed including the closing `]`.
+fn parse_class(chars: &[Char]) -> Option<(PatternToken, usize)> {
+    let mut i = 0;
+    let mut negated = false;
+    let mut items = vec![];
+
+    if let Some(Char {
+        c: '!' | '^',
+        quoted: false,
+    }) = chars.first()
+    {
+        negated = true;
+        i += 1;
+    }
+
+    let start = i;
+    loop {
+        let Char { c, quoted } = *chars.get(i)?;
+        i += 1;
+
+        if c == ']' && !quoted && i - 1 > start {
+            return Some((PatternToken::Class { negated, items }, i));
+        }
+
+        if c == '[' && !quoted && chars.get(i).map(|c| c.c) == Some(':') {
+            let rest = &chars[i + 1..];
+            if let Some(end) = rest.windows(2).position(|w| w[0].c == ':' && w[1].c == ']') {
+                items.push(ClassItem::Named(rest[..end].iter().map(|c| c.c).collect()));
+                i += end + 3;
+                continue;
+            }
+        }
+
+        match (chars.get(i), chars.get(i + 1)) {
+            (Some(Char { c: '-', .. }), Some(end)) if end.c != ']' || end.quoted => {
+                items.push(ClassItem::Range(c, end.c));
+                i += 2;
+            }
+            _ => items.push(ClassItem::Char(c)),
+        }
+    }
+}
+
+fn match_class(negated: bool, items: &[ClassItem], c: char, nocase: bool) -> bool {
+    let found = items.iter().any(|item| match item {
+        ClassItem::Char(x) => chars_eq(*x, c, nocase),
+        ClassItem::Range(start, end) => {
+            let range = *start..=*end;
+            range.contains(&c)
+                || nocase
+                    && (range.contains(&c.to_ascii_lowercase())
+                        || range.contains(&c.to_ascii_uppercase()))
+        }
+        ClassItem::Named(name) => match name.as_str() {
+            "alnum" => c.is_alphanumeric(),
+            "alpha" => c.is_alphabetic(),
+            "blank" => c == ' ' || c == '\t',
+            "cntrl" => c.is_control(),
+            "digit" => c.is_ascii_digit(),
+            "graph" => c.is_ascii_graphic(),
+            "lower" => c.is_lowercase(),
+            "print" => c.is_ascii_graphic() || c == ' ',
+            "punct" => c.is_ascii_punctuation(),
+            "space" => c.is_whitespace(),
+            "upper" => c.is_uppercase(),
+            "xdigit" => c.is_ascii_hexdigit(),
+            _ => false,
+        },
+    });
+    found != negated
+}
+
+fn chars_eq(a: char, b: char, nocase: bool) -> bool {
+    a == b || nocase && a.to_lowercase().eq(b.to_lowercase())
+}
+
+/// Matches a pattern against a text, remembering which parts of the pattern
+/// match which parts of the text, so that backtracking over `*` and extended
+/// globs takes polynomial rather than exponential time. The parts of the
+/// pattern are slices of its tokens, told apart by their address and length.
+struct Matcher<'a> {
+    text: &'a [char],
+    nocase: bool,
+    tokens: HashMap<(*const PatternToken, usize, usize, usize), bool>,
+    repeated: HashMap<(*const Vec<PatternToken>, usize, usize), bool>,
+}
+
+impl Matcher<'_> {
+    /// Whether `tokens` match all of `text[start..end]`.
+    fn tokens(&mut self, tokens: &[PatternToken], start: usize, end: usize) -> bool {
+        let key = (tokens.as_ptr(), tokens.len(), start, end);
+        if let Some(&matched) = self.tokens.get(&key) {
+            return matched;
+        }
+
+        let matched = match tokens.split_first() {
+            None => start == end,
+            Some((PatternToken::Star, rest)) => (start..=end).any(|i| self.tokens(rest, i, end)),
+            Some((PatternToken::Extended { kind, alternatives }, rest)) => (start..=end)
+                .any(|i| self.extended(*kind, alternatives, start, i) && self.tokens(rest, i, end)),
+            Some((token, rest)) => {
+                let Some(&c) = self.text[start..end].first() else {
+                    return false;
+                };
+                let matched = match token {
+                    PatternToken::Literal(x) => chars_eq(*x, c, self.nocase),
+                    PatternToken::Any => true,
+                    PatternToken::Class { negated, items } => {
+                        match_class(*negated, items, c, self.nocase)
+                    }
+                    PatternToken::Star | PatternToken::Extended { .. } => unreachable!(),
+                };
+                matched && self.tokens(rest, start + 1, end)
+            }
+        };
+
+        self.tokens.insert(key, matched);
+        matched
+    }
+
+    fn extended(
+        &mut self,
+        kind: char,
+        alternatives: &[Vec<PatternToken>],
+        start: usize,
+        end: usize,
+    ) -> bool {
+        match kind {
+            '?' => start == end || self.any(alternatives, start, end),
+            '@' => self.any(alternatives, start, end),
+            '!' => !self.any(alternatives, start, end),
+            '*' => start == end || self.repeated(alternatives, start, end),
+            '+' => self.repeated(alternatives, start, end),
+            _ => unreachable!(),
+        }
+    }
+
+    fn any(&mut self, alternatives: &[Vec<PatternToken>], start: usize, end: usize) -> bool {
+        alternatives
+            .iter()
+            .any(|alternative| self.tokens(alternative, start, end))
+    }
+
+    /// Whether `text[start..end]` is one or more non-empty matches of the
+    /// alternatives in a row.
+    fn repeated(&mut self, alternatives: &[Vec<PatternToken>], start: usize, end: usize) -> bool {
+        let key = (alternatives.as_ptr(), start, end);
+        if let Some(&matched) = self.repeated.get(&key) {
+            return matched;
+        }
+        let matched = (start + 1..=end).any(|i| {
+            self.any(alternatives, start, i) && (i == end || self.repeated(alternatives, i, end))
+        });
+        self.repeated.insert(key, matched);
+        matched
+    }
+}
+
+/// Whether `chars` contains an unquoted glob metacharacter.
+pub fn has_magic(chars: &[Char], options: &Options) -> bool {
+    chars.iter().enumerate().any(|(i, c)| {
+        !c.quoted
+            && match c.c {
+                '*' | '?' | '[' => true,
+                '+' | '@' | '!' => {
+                    options.extglob
+                        && chars.get(i + 1)
+                            == Some(&Char {
+                                c: '(',
+                                quoted: false,
+                            })
+                }
+                _ => false,
+            }
+    })
+}
+
+/// Expands `chars` against the filesystem, relative to `cwd`. The matches are
+/// sorted, and empty if nothing matched.
+pub fn glob(chars: &[Char], cwd: &Path, options: &Options) -> Vec<String> {
+    let components = chars.split(|c| c.c == '/').collect::<Vec<_>>();
+
+    let (mut paths, components) = match components.split_first() {
+        Some((first, rest)) if first.is_empty() && !rest.is_empty() => {
+            (vec![String::from("/")], rest)
+        }
+        _ => (vec![String::new()], &components[..]),
+    };
+
+    for (i, component) in components.iter().enumerate() {
+        let last = i == components.len() - 1;
+        let mut next = vec![];
+
+        if options.globstar && is_globstar(component) {
+            for path in &paths {
+                if !last {
+                    next.push(path.clone());
+                }
+                walk(cwd, path, last, options, &mut next);
+            }
+            paths = next;
+            continue;
+        }
+
+        let component = Pattern::new(component, options);
+
+        for path in &paths {
+            let dir = cwd.join(if path.is_empty() { "." } else { path });
+
+            if let Some(name) = component.literal() {
+                let candidate = format!("{}{}", path, name);
+                // An empty last component comes from a trailing slash, which
+                // only matches directories.
+                let exists = if name.is_empty() {
+                    dir.is_dir()
+                } else {
+                    fs::symlink_metadata(cwd.join(&candidate)).is_ok()
+                };
+                if exists {
+                    next.push(if last { candidate } else { candidate + "/" });
+                }
+                continue;
+            }
+
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            let names = entries
+                .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                .filter(|name| {
+                    !name.starts_with('.') || options.dotglob || component.matches_hidden()
+                })
+                .filter(|name| component.matches(name))
+                .filter(|name| last || cwd.join(format!("{}{}", path, name)).is_dir());
+
+            for name in names {
+                let candidate = format!("{}{}", path, name);
+                next.push(if last { candidate } else { candidate + "/" });
+            }
+        }
+
+        paths = next;
+    }
+
+    // `**/` can match the starting directory itself, which has no name.
+    paths.retain(|path| !path.is_empty());
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+fn is_globstar(component: &[Char]) -> bool {
+    component.len() == 2 && component.iter().all(|c| c.c == '*' && !c.quoted)
+}
+
+/// Collects everything below `path` for `**`: only directories, with a
+/// trailing slash, unless `files` is set. Symbolic links are not followed,
+/// so links that point back up the tree can't cause a loop.
+fn walk(cwd: &Path, path: &str, files: bool, options: &Options, paths: &mut Vec<String>) {
+    let dir = cwd.join(if path.is_empty() { "." } else { path });
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.filter_map(Result::ok) {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if name.starts_with('.') && !options.dotglob {
+            continue;
+        }
+        let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+
+        if is_dir {
+            let dir = format!("{}{}/", path, name);
+            if files {
+                paths.push(format!("{}{}", path, name));
+            } else {
+                paths.push(dir.clone());
+            }
+            walk(cwd, &dir, files, options, paths);
+        } else if files {
+            paths.push(format!("{}{}", path, name));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn chars(s: &str) -> Vec<Char> {
+        s.chars().map(|c| Char { c, quoted: false }).collect()
+    }
+
+    fn pattern(s: &str) -> Pattern {
+        Pattern::new(&chars(s), &Options::default())
+    }
+
+    fn extglob(s: &str) -> Pattern {
+        let options = Options {
+            extglob: true,
+            ..Default::default()
+        };
+        Pattern::new(&chars(s), &options)
+    }
+
+    #[test]
+    fn test_matches() {
+        assert!(pattern("*.rs").matches("main.rs"));
+        assert!(pattern("*.rs").matches(".rs"));
+        assert!(!pattern("*.rs").matches("main.rc"));
+        assert!(pattern("ma?n.*").matches("main.rs"));
+        assert!(!pattern("ma?n").matches("man"));
+        assert!(pattern("[a-c]x").matches("bx"));
+        assert!(!pattern("[!a-c]x").matches("bx"));
+        assert!(pattern("[]]").matches("]"));
+        assert!(pattern("[[:digit:]]*").matches("1abc"));
+        assert!(pattern("[abc").matches("[abc"));
+        assert!(pattern("a*b*c").matches("aXbYbZc"));
+
+        let quoted = vec![
+            Char {
+                c: '*',
+                quoted: true,
+            },
+            Char {
+                c: '*',
+                quoted: false,
+            },
+        ];
+        assert!(Pattern::new(&quoted, &Options::default()).matches("*foo"));
+        assert!(!Pattern::new(&quoted, &Options::default()).matches("foo"));
+    }
+
+    #[test]
+    fn test_glob() {
+        let dir = TempDir::new("").unwrap();
+        for path in ["b.rs", "a.rs", "c.txt", ".hidden.rs", "src/main.rs"] {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap();
+        }
+        let options = Options::default();
+
+        assert_eq!(
+            glob(&chars("*.rs"), dir.path(), &options),
+            vec!["a.rs", "b.rs"]
+        );
+        assert_eq!(
+            glob(&chars(".*.rs"), dir.path(), &options),
+            vec![".hidden.rs"]
+        );
+        assert_eq!(
+            glob(&chars("*/*.rs"), dir.path(), &options),
+            vec!["src/main.rs"]
+        );
+        assert_eq!(glob(&chars("*/"), dir.path(), &options), vec!["src/"]);
+        assert_eq!(
+            glob(&chars("*.md"), dir.path(), &options),
+            Vec::<String>::new()
+        );
+
+        let absolute = format!("{}/?.txt", dir.path().to_str().unwrap());
+        assert_eq!(
+            glob(&chars(&absolute), Path::new("/"), &Options::default()),
+            vec![format!("{}/c.txt", dir.path().to_str().unwrap())]
+        );
+    }
+
+    #[test]
+    fn test_matches_extended() {
+        assert!(extglob("@(foo|bar).rs").matches("bar.rs"));
+        assert!(!extglob("@(foo|bar).rs").matches("baz.rs"));
+        assert!(extglob("?(foo).rs").matches(".rs"));
+        assert!(extglob("*(ab)c").matches("ababc"));
+        assert!(extglob("*(ab)c").matches("c"));
+        assert!(!extglob("+(ab)c").matches("c"));
+        assert!(extglob("+(a|b)c").matches("abbac"));
+        assert!(extglob("!(*.rs)").matches("main.c"));
+        assert!(!extglob("!(*.rs)").matches("main.rs"));
+        assert!(extglob("@(a|@(b|c))").matches("c"));
+        assert!(pattern("@(a|b)").matches("@(a|b)"));
+    }
+
+    #[test]
+    fn test_matches_backtracking() {
+        let text = "a".repeat(40);
+        assert!(!pattern("*a*a*a*a*a*a*a*a*b").matches(&text));
+        assert!(pattern("*a*a*a*a*a*a*a*a*").matches(&text));
+        assert!(!extglob("*(a|aa)*(a|aa)*(a|aa)b").matches(&text));
+        assert!(extglob("+(a|aa)+(a|aa)").matches(&text));
+        assert!(!extglob("+(*a*a|a*a*)b").matches(&text));
+    }
+
+    #[test]
+    fn test_matches_nocase() {
+        let options = Options {
+            nocaseglob: true,
+            ..Default::default()
+        };
+        assert!(Pattern::new(&chars("*.RS"), &options).matches("main.rs"));
+        assert!(Pattern::new(&chars("[a-c]x"), &options).matches("BX"));
+    }
+
+    #[test]
+    fn test_glob_options() {
+        let dir = TempDir::new("").unwrap();
+        for path in ["a.rs", ".b.rs", "src/c.rs", "src/d/e.rs", ".git/f.rs"] {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap();
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("src/loop")).unwrap();
+
+        let mut options = Options {
+            globstar: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            glob(&chars("**/*.rs"), dir.path(), &options),
+            vec!["a.rs", "src/c.rs", "src/d/e.rs"]
+        );
+        assert_eq!(
+            glob(&chars("**/"), dir.path(), &options),
+            vec!["src/", "src/d/"]
+        );
+        assert_eq!(
+            glob(&chars("src/**"), dir.path(), &options),
+            vec!["src/c.rs", "src/d", "src/d/e.rs", "src/loop"]
+        );
+
+        options.dotglob = true;
+        assert_eq!(
+            glob(&chars("**/*.rs"), dir.path(), &options),
+            vec![".b.rs", ".git/f.rs", "a.rs", "src/c.rs", "src/d/e.rs"]
+        );
+        assert_eq!(
+            glob(&chars("*.rs"), dir.path(), &options),
+            vec![".b.rs", "a.rs"]
+        );
+
+        options.globstar = false;
+        assert_eq!(
+            glob(&chars("**/*.rs"), dir.path(), &options),
+            vec![".git/f.rs", "src/c.rs"]
+        );
+    }
+}
@@ -4,15 +4,27 @@ use std::ffi::OsStr;
 pub enum Token {
     Input(String),
     Pipe,
+    /// `|&`, which pipes stderr as well as stdout.
+    PipeAll,
     RedirectOut,
     RedirectAppend,
     RedirectIn,
+    /// `<<` or `<<-`, followed by the body of the here-document, which is
+    /// expanded unless the delimiter was quoted.
+    HereDoc {
+        expand: bool,
+    },
+    HereString,
     Background,
     And,
     Or,
     Semicolon,
     OpenParenthesis,
     CloseParenthesis,
+    OpenBrace,
+    CloseBrace,
+    /// The expression inside an `(( ... ))` command.
+    Arithmetic(String),
     // TODO:
     // - Variable
 }
@@ -26,17 +38,22 @@ impl std::fmt::Display for Token {
 impl AsRef<OsStr> for Token {
     fn as_ref(&self) -> &OsStr {
         match self {
-            Token::Input(s) => s.as_ref(),
+            Token::Input(s) | Token::Arithmetic(s) => s.as_ref(),
             Token::Pipe => "|".as_ref(),
+            Token::PipeAll => "|&".as_ref(),
             Token::RedirectOut => ">".as_ref(),
             Token::RedirectAppend => ">>".as_ref(),
             Token::RedirectIn => "<".as_ref(),
+            Token::HereDoc { .. } => "<<".as_ref(),
+            Token::HereString => "<<<".as_ref(),
             Token::And => "&&".as_ref(),
             Token::Or => "||".as_ref(),
             Token::Background => "&".as_ref(),
             Token::Semicolon => ";".as_ref(),
             Token::OpenParenthesis => "(".as_ref(),
             Token::CloseParenthesis => ")".as_ref(),
+            Token::OpenBrace => "{".as_ref(),
+            Token::CloseBrace => "}".as_ref(),
         }
     }
 }
@@ -49,14 +66,20 @@ mod tests {
     fn test_token_display() {
         assert_eq!(Token::Input("foo".to_string()).to_string(), "foo");
         assert_eq!(Token::Pipe.to_string(), "|");
+        assert_eq!(Token::PipeAll.to_string(), "|&");
         assert_eq!(Token::RedirectOut.to_string(), ">");
         assert_eq!(Token::RedirectAppend.to_string(), ">>");
         assert_eq!(Token::RedirectIn.to_string(), "<");
+        assert_eq!(Token::HereDoc { expand: true }.to_string(), "<<");
+        assert_eq!(Token::HereString.to_string(), "<<<");
         assert_eq!(Token::And.to_string(), "&&");
         assert_eq!(Token::Or.to_string(), "||");
         assert_eq!(Token::Background.to_string(), "&");
         assert_eq!(Token::Semicolon.to_string(), ";");
         assert_eq!(Token::OpenParenthesis.to_string(), "(");
         assert_eq!(Token::CloseParenthesis.to_string(), ")");
+        assert_eq!(Token::OpenBrace.to_string(), "{");
+        assert_eq!(Token::CloseBrace.to_string(), "}");
+        assert_eq!(Token::Arithmetic("1 + 2".to_string()).to_string(), "1 + 2");
     }
 }
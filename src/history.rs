@@ -0,0 +1,642 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{error::Error, session::Session};
+
+/// The default `HISTSIZE`.
+pub const DEFAULT_SIZE: usize = 500;
+
+/// The lines entered at the prompt, oldest first.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct History {
+    entries: Vec<Entry>,
+    /// The number of the first entry, which grows as old entries are dropped.
+    first: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub line: String,
+    /// When the line was entered, in seconds since the Unix epoch.
+    pub time: u64,
+}
+
+impl History {
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The line at `index`, counting from the oldest entry.
+    pub fn line(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(|entry| entry.line.as_str())
+    }
+
+    /// The index of the most recent entry before `before` that contains
+    /// `text`.
+    pub fn search(&self, text: &str, before: usize) -> Option<usize> {
+        self.entries[..before.min(self.len())]
+            .iter()
+            .rposition(|entry| entry.line.contains(text))
+    }
+
+    /// The number `history` shows for the first entry.
+    pub fn first(&self) -> usize {
+        self.first.max(1)
+    }
+
+    /// Adds an entry, as `HISTCONTROL` allows, keeping at most `size`
+    /// entries. Returns whether it was added.
+    pub fn push(&mut self, entry: Entry, control: &str, size: Option<usize>) -> bool {
+        let control = control.split(':').collect::<Vec<_>>();
+        let ignore = |name| control.contains(&name) || control.contains(&"ignoreboth");
+        if entry.line.trim().is_empty()
+            || ignore("ignorespace") && entry.line.starts_with([' ', '\t'])
+            || ignore("ignoredups") && self.entries.last().map(|e| &e.line) == Some(&entry.line)
+        {
+            return false;
+        }
+        if control.contains(&"erasedups") {
+            self.entries.retain(|e| e.line != entry.line);
+        }
+        self.entries.push(entry);
+        if let Some(size) = size {
+            self.truncate(size);
+        }
+        true
+    }
+
+    /// Drops the oldest entries, so that there are at most `size`.
+    pub fn truncate(&mut self, size: usize) {
+        let excess = self.entries.len().saturating_sub(size);
+        self.entries.drain(..excess);
+        self.first = self.first() + excess;
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.first = 1;
+    }
+
+    /// Deletes the entry numbered `number`, as `history` shows it.
+    pub fn delete(&mut self, number: usize) -> bool {
+        match number.checked_sub(self.first()) {
+            Some(index) if index < self.entries.len() => {
+                self.entries.remove(index);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Applies csh-style history expansion to `line`: `!!`, `!n`, `!-n`,
+    /// `!prefix` and `!?text?` refer to earlier lines, optionally followed
+    /// by a word designator such as `:2`, and `!$`, `!^` and `!*` are words
+    /// of the previous line. A line starting `^old^new` repeats the previous
+    /// line with `old` replaced. Returns `None` if nothing was expanded.
+    pub fn expand(&self, line: &str) -> Result<Option<String>, Error> {
+        if let Some(rest) = line.strip_prefix('^') {
+            let mut parts = rest.splitn(3, '^');
+            let old = parts.next().unwrap_or_default();
+            let new = parts.next().unwrap_or_default();
+            let tail = parts.next().unwrap_or_default();
+            let previous = self.event("!!", self.len().checked_sub(1))?;
+            if old.is_empty() || !previous.contains(old) {
+                return Err(Error::History(format!(
+                    "^{}^{}: substitution failed",
+                    old, new
+                )));
+            }
+            return Ok(Some(previous.replacen(old, new, 1) + tail));
+        }
+
+        let chars = line.chars().collect::<Vec<_>>();
+        let mut expanded = String::new();
+        let mut changed = false;
+        let (mut single, mut double) = (false, false);
+        let mut i = 0;
+
+        while i < chars.len() {
+            let c = chars[i];
+            match c {
+                '\\' if !single => {
+                    expanded.extend(&chars[i..(i + 2).min(chars.len())]);
+                    i += 2;
+                    continue;
+                }
+                '\'' if !double => single = !single,
+                '"' if !single => double = !double,
+                '!' if !single => {
+                    if let Some(words) = self.reference(&chars, &mut i, double)? {
+                        expanded.push_str(&words);
+                        changed = true;
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            expanded.push(c);
+            i += 1;
+        }
+
+        Ok(changed.then_some(expanded))
+    }
+
+    /// Expands the history reference starting with the `!` at `chars[*i]`,
+    /// and moves past it. Returns `None` if the `!` doesn't start one.
+    fn reference(
+        &self,
+        chars: &[char],
+        i: &mut usize,
+        double: bool,
+    ) -> Result<Option<String>, Error> {
+        let start = *i;
+        let previous = self.len().checked_sub(1);
+        let mut j = start + 1;
+
+        let (index, designator) = match chars.get(j) {
+            None => return Ok(None),
+            Some(c) if c.is_whitespace() || matches!(c, '=' | '(') => return Ok(None),
+            Some('"') if double => return Ok(None),
+            Some('!') => {
+                j += 1;
+                (previous, None)
+            }
+            Some(&c @ ('$' | '^' | '*')) => {
+                j += 1;
+                (previous, Some(c.to_string()))
+            }
+            Some('?') => {
+                let end = chars[j + 1..]
+                    .iter()
+                    .position(|&c| c == '?')
+                    .map_or(chars.len(), |n| j + 1 + n);
+                let text = chars[j + 1..end].iter().collect::<String>();
+                j = (end + 1).min(chars.len());
+                (self.search(&text, self.len()), None)
+            }
+            Some(c) if c.is_ascii_digit() || *c == '-' => {
+                let digits = chars[j + 1..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_digit())
+                    .count();
+                let number = chars[j..j + 1 + digits].iter().collect::<String>();
+                j += 1 + digits;
+                let index = match number.parse::<i64>() {
+                    Ok(n) if n < 0 => self.len().checked_sub(n.unsigned_abs() as usize),
+                    Ok(n) => (n as usize).checked_sub(self.first()),
+                    Err(_) => None,
+                };
+                (index, None)
+            }
+            Some(_) => {
+                let len = chars[j..]
+                    .iter()
+                    .take_while(|&&c| !c.is_whitespace() && !"!:;&|<>()\"'".contains(c))
+                    .count();
+                let prefix = chars[j..j + len].iter().collect::<String>();
+                j += len;
+                let index = self
+                    .entries
+                    .iter()
+                    .rposition(|entry| entry.line.starts_with(&prefix));
+                (index, None)
+            }
+        };
+
+        // A word designator, such as `:1` or `:$`, may follow the event.
+        let mut designator = designator;
+        if designator.is_none() && chars.get(j) == Some(&':') {
+            let len = chars[j + 1..]
+                .iter()
+                .take_while(|&&c| c.is_ascii_digit() || matches!(c, '$' | '^' | '*' | '-'))
+                .count();
+            if len > 0 {
+                designator = Some(chars[j + 1..j + 1 + len].iter().collect());
+                j += 1 + len;
+            }
+        }
+
+        let spec = chars[start..j].iter().collect::<String>();
+        let line = self.event(&spec, index.filter(|&index| index < self.len()))?;
+        *i = j;
+        match designator {
+            None => Ok(Some(line.to_string())),
+            Some(designator) => select(&words(line), &designator)
+                .map(Some)
+                .ok_or_else(|| Error::History(format!("{}: bad word specifier", spec))),
+        }
+    }
+
+    fn event(&self, spec: &str, index: Option<usize>) -> Result<&str, Error> {
+        index
+            .and_then(|index| self.line(index))
+            .ok_or_else(|| Error::History(format!("{}: event not found", spec)))
+    }
+}
+
+/// The history settings, taken from the shell's variables.
+struct Settings {
+    file: Option<PathBuf>,
+    control: String,
+    size: Option<usize>,
+    file_size: Option<usize>,
+}
+
+impl Settings {
+    fn new(session: &Session) -> Settings {
+        // A negative or invalid size means there's no limit.
+        let limit = |name, default| match session.var(name) {
+            None => default,
+            Some(value) => value.parse::<usize>().ok(),
+        };
+        let size = limit("HISTSIZE", Some(DEFAULT_SIZE));
+        Settings {
+            file: session
+                .var("HISTFILE")
+                .filter(|file| !file.is_empty())
+                .map(|file| session.cwd.join(file)),
+            control: session.var("HISTCONTROL").unwrap_or_default().to_string(),
+            size,
+            file_size: limit("HISTFILESIZE", size),
+        }
+    }
+}
+
+/// Reads the history from `HISTFILE`, keeping the last `HISTSIZE` entries.
+pub fn load(session: &mut Session) -> io::Result<()> {
+    let settings = Settings::new(session);
+    let Some(file) = settings.file else {
+        return Ok(());
+    };
+    let text = match std::fs::read_to_string(file) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    session.history = History {
+        entries: parse(&text),
+        first: 1,
+    };
+    if let Some(size) = settings.size {
+        session.history.truncate(size);
+    }
+    Ok(())
+}
+
+/// Adds a line entered at the prompt to the history, and if `save`, appends
+/// it to `HISTFILE` straight away, so that shells in several terminals don't
+/// overwrite each other's history. Errors name the file.
+pub fn add(session: &mut Session, line: &str, save: bool) -> io::Result<()> {
+    let settings = Settings::new(session);
+    let entry = Entry {
+        line: line.to_string(),
+        time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs()),
+    };
+    if !session
+        .history
+        .push(entry.clone(), &settings.control, settings.size)
+    {
+        return Ok(());
+    }
+
+    match settings.file {
+        Some(file) if save => append(&file, &entry, settings.file_size)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", file.display(), e))),
+        _ => Ok(()),
+    }
+}
+
+/// Writes the whole history to `file`, or `HISTFILE`, replacing it.
+pub fn write(session: &Session, file: Option<&Path>) -> io::Result<()> {
+    let settings = Settings::new(session);
+    let Some(file) = file.map(|file| session.cwd.join(file)).or(settings.file) else {
+        return Ok(());
+    };
+    let mut file = lock(&file)?;
+    file.set_len(0)?;
+    file.write_all(format(session.history.entries()).as_bytes())
+}
+
+/// Formats a timestamp in local time with a `strftime` format, for
+/// `HISTTIMEFORMAT`.
+pub fn format_time(format: &str, time: u64) -> String {
+    let Ok(format) = std::ffi::CString::new(format) else {
+        return String::new();
+    };
+    let time = time as libc::time_t;
+    let mut buf = [0u8; 256];
+    // SAFETY: `tm` is filled in by `localtime_r`, and `strftime` writes at
+    // most `buf.len()` bytes, returning how many it wrote.
+    let len = unsafe {
+        let mut tm = std::mem::zeroed::<libc::tm>();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return String::new();
+        }
+        libc::strftime(
+            buf.as_mut_ptr() as *mut libc::c_char,
+            buf.len(),
+            format.as_ptr(),
+            &tm,
+        )
+    };
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+/// Appends `entry` to `path` under a lock, then drops the oldest entries if
+/// there are more than `size`.
+fn append(path: &Path, entry: &Entry, size: Option<usize>) -> io::Result<()> {
+    let mut file = lock(path)?;
+    file.write_all(format(std::slice::from_ref(entry)).as_bytes())?;
+
+    let Some(size) = size else {
+        return Ok(());
+    };
+    let mut text = String::new();
+    file.rewind()?;
+    file.read_to_string(&mut text)?;
+    let entries = parse(&text);
+    if entries.len() > size {
+        file.set_len(0)?;
+        file.write_all(format(&entries[entries.len() - size..]).as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Opens a history file for appending, holding an exclusive lock on it until
+/// it's closed.
+fn lock(path: &Path) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)?;
+    // SAFETY: the descriptor is valid for as long as `file` is open.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(file)
+}
+
+/// Formats entries as bash does with `HISTTIMEFORMAT` set: each preceded by
+/// a `#` comment holding its timestamp.
+fn format(entries: &[Entry]) -> String {
+    entries
+        .iter()
+        .map(|entry| format!("#{}\n{}\n", entry.time, entry.line))
+        .collect()
+}
+
+/// Reads entries from a history file. Where there are timestamps, the lines
+/// up to the next one are a single entry, so multi-line commands survive.
+fn parse(text: &str) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = vec![];
+    let mut time = None;
+    let mut timestamped = false;
+
+    for line in text.lines() {
+        let stamp = line
+            .strip_prefix('#')
+            .filter(|digits| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|digits| digits.parse().ok());
+        if stamp.is_some() {
+            time = stamp;
+            continue;
+        }
+        match time.take() {
+            Some(time) => {
+                entries.push(Entry {
+                    line: line.to_string(),
+                    time,
+                });
+                timestamped = true;
+            }
+            None if timestamped => {
+                let entry = entries.last_mut().unwrap();
+                entry.line.push('\n');
+                entry.line.push_str(line);
+            }
+            None => entries.push(Entry {
+                line: line.to_string(),
+                time: 0,
+            }),
+        }
+    }
+
+    entries
+}
+
+/// Splits a line into words at unquoted whitespace, keeping the quotes.
+fn words(line: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut quote = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if quote != Some('\'') => {
+                word.push(c);
+                word.extend(chars.next());
+            }
+            '\'' | '"' if quote.is_none() => {
+                quote = Some(c);
+                word.push(c);
+            }
+            c if Some(c) == quote => {
+                quote = None;
+                word.push(c);
+            }
+            c if c.is_whitespace() && quote.is_none() => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// Selects words by a designator: a number, `^` for the first argument, `$`
+/// for the last word, `*` for all the arguments, or a range `x-y`.
+fn select(words: &[String], designator: &str) -> Option<String> {
+    let last = words.len().checked_sub(1)?;
+    let position = |s: &str| match s {
+        "^" => Some(1),
+        "$" => Some(last),
+        s => s.parse().ok(),
+    };
+    let (start, end) = match designator {
+        "*" if last == 0 => return Some(String::new()),
+        "*" => (1, last),
+        _ => match designator.split_once('-') {
+            Some((start, "")) => (position(start)?, last.saturating_sub(1)),
+            Some((start, end)) => (position(start)?, position(end)?),
+            None => (position(designator)?, position(designator)?),
+        },
+    };
+    (start <= end && end <= last).then(|| words[start..=end].join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn history(lines: &[&str]) -> History {
+        let mut history = History::default();
+        for line in lines {
+            let entry = Entry {
+                line: line.to_string(),
+                time: 0,
+            };
+            history.push(entry, "", None);
+        }
+        history
+    }
+
+    #[test]
+    fn test_push() {
+        let entry = |line: &str| Entry {
+            line: line.to_string(),
+            time: 0,
+        };
+        let mut history = History::default();
+        assert!(history.push(entry("ls"), "", None));
+        assert!(history.push(entry("ls"), "", None));
+        assert!(!history.push(entry("  "), "", None));
+        assert!(!history.push(entry("ls"), "ignoredups", None));
+        assert!(!history.push(entry(" secret"), "ignoreboth", None));
+        assert!(history.push(entry(" secret"), "ignoredups", None));
+        assert!(history.push(entry("pwd"), "", None));
+        assert!(history.push(entry("ls"), "erasedups", Some(2)));
+
+        let lines = history.entries().iter().map(|e| e.line.as_str());
+        assert_eq!(lines.collect::<Vec<_>>(), vec!["pwd", "ls"]);
+        assert_eq!(history.first(), 2);
+        assert!(history.delete(2));
+        assert!(!history.delete(1));
+        assert_eq!(history.line(0), Some("ls"));
+    }
+
+    #[test]
+    fn test_expand() {
+        let history = history(&[
+            "echo one two",
+            "ls -l /tmp",
+            "git commit -m 'a b'",
+            "cat foo.txt",
+        ]);
+        let cases = [
+            ("echo hi", None),
+            ("!!", Some("cat foo.txt")),
+            ("sudo !! | wc", Some("sudo cat foo.txt | wc")),
+            ("vi !$", Some("vi foo.txt")),
+            ("echo !^ !*", Some("echo foo.txt foo.txt")),
+            ("!1", Some("echo one two")),
+            ("!-2", Some("git commit -m 'a b'")),
+            ("!ls", Some("ls -l /tmp")),
+            ("!e:2", Some("two")),
+            ("echo !git:$", Some("echo 'a b'")),
+            ("echo !?commit?", Some("echo git commit -m 'a b'")),
+            ("!ls:1-2", Some("-l /tmp")),
+            ("echo '!!' \\!! ! x!= !(a)", None),
+            ("echo \"!!\"", Some("echo \"cat foo.txt\"")),
+            ("^foo^bar", Some("cat bar.txt")),
+            ("^cat^less^ -N", Some("less foo.txt -N")),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(
+                history.expand(line).unwrap().as_deref(),
+                expected,
+                "{}",
+                line
+            );
+        }
+
+        for line in ["!nope", "!9", "!ls:5", "^zz^y"] {
+            assert!(
+                matches!(history.expand(line), Err(Error::History(_))),
+                "{}",
+                line
+            );
+        }
+        assert_eq!(
+            History::default().expand("!!").unwrap_err(),
+            Error::History("!!: event not found".to_string())
+        );
+    }
+
+    #[test]
+    fn test_file() {
+        let dir = TempDir::new("").unwrap();
+        let mut session = Session::new();
+        session.cwd = dir.path().to_path_buf();
+        session.vars.insert("HISTFILE".into(), "history".into());
+        session.vars.insert("HISTFILESIZE".into(), "3".into());
+        session
+            .vars
+            .insert("HISTCONTROL".into(), "ignorespace".into());
+
+        for line in ["one", " two", "three", "cat <<EOF\nfour\nEOF", "five"] {
+            add(&mut session, line, true).unwrap();
+        }
+        assert_eq!(session.history.len(), 4);
+        add(&mut session, "unsaved", false).unwrap();
+        assert_eq!(session.history.len(), 5);
+
+        let mut other = Session::new();
+        other.cwd = session.cwd.clone();
+        other.vars = session.vars.clone();
+        load(&mut other).unwrap();
+        let lines = other.history.entries().iter().map(|e| e.line.as_str());
+        assert_eq!(
+            lines.collect::<Vec<_>>(),
+            vec!["three", "cat <<EOF\nfour\nEOF", "five"]
+        );
+        assert!(other.history.entries().iter().all(|e| e.time > 0));
+
+        other.history.clear();
+        write(&other, None).unwrap();
+        load(&mut session).unwrap();
+        assert!(session.history.is_empty());
+
+        assert_eq!(parse("a\nb\n").len(), 2);
+
+        session
+            .vars
+            .insert("HISTFILE".into(), "missing/history".into());
+        let e = add(&mut session, "six", true).unwrap_err();
+        assert!(e.to_string().starts_with(&format!(
+            "{}: ",
+            dir.path().join("missing/history").display()
+        )));
+        assert_eq!(session.history.len(), 1);
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time("%Y", 86400 * 366), "1971");
+        assert_eq!(format_time("", 0), "");
+    }
+}
@@ -1,59 +1,98 @@
-use crate::{error::Error, grammar::Token, input};
+use std::{iter::Peekable, str::Chars};
+
+use crate::{error::Error, expand::is_name, grammar::Token, input, session::Options};
 
 pub struct Lexer;
 
 impl Lexer {
+    /// Lexes `line` with the default options.
     pub fn lex(line: &str) -> Result<Vec<Token>, Error> {
+        Lexer::lex_with(line, &Options::default())
+    }
+
+    /// Lexes `line`. As in bash, a `(` after `?`, `*`, `+`, `@` or `!` only
+    /// continues the word as an extended glob with `extglob` on, so the
+    /// option has to be set before the line that uses it.
+    pub fn lex_with(line: &str, options: &Options) -> Result<Vec<Token>, Error> {
         let mut tokens = vec![];
         let mut token = String::new();
         let mut escape = false;
         let mut in_double_quotes = false;
         let mut in_single_quotes = false;
+        let mut here_docs: Vec<PendingHereDoc> = vec![];
 
         let mut iter = line.chars().peekable();
 
         while let Some(c) = iter.next() {
-            if escape {
+            // Quotes and backslashes are kept in the word, so that expansion
+            // knows which characters were quoted.
+            if escape && c == '\n' {
+                token.pop();
+                escape = false;
+                // The command continues on the next line.
+                if iter.peek().is_none() {
+                    return Err(Error::Parse(input!("")));
+                }
+            } else if escape {
                 token.push(c);
                 escape = false;
-            } else if c == '\\' {
+            } else if c == '\\' && !in_single_quotes {
+                token.push(c);
                 escape = true;
+            } else if c == '$' && !in_single_quotes && iter.peek() == Some(&'{') {
+                token.push(c);
+                take_nested(&mut iter, &mut token, '{', '}', 0);
+            } else if c == '$' && !in_single_quotes && iter.peek() == Some(&'(') {
+                token.push(c);
+                take_nested(&mut iter, &mut token, '(', ')', 0);
+            } else if c == '#' && token.is_empty() && !in_single_quotes && !in_double_quotes {
+                // A comment runs to the end of the line.
+                while iter.next_if(|c| *c != '\n').is_some() {}
             } else if c == '"' && !in_single_quotes {
+                token.push(c);
                 in_double_quotes = !in_double_quotes;
-                if !in_double_quotes {
-                    tokens.push(input!(std::mem::take(&mut token)));
-                }
             } else if c == '\'' && !in_double_quotes {
+                token.push(c);
                 in_single_quotes = !in_single_quotes;
-                if !in_single_quotes {
-                    tokens.push(input!(std::mem::take(&mut token)));
-                }
             } else if (c == ' ' || c == '\t') && !in_single_quotes && !in_double_quotes {
-                if !token.is_empty() {
-                    tokens.push(input!(std::mem::take(&mut token)));
+                push_word(&mut tokens, &mut token);
+            } else if c == '\n' && !in_single_quotes && !in_double_quotes {
+                push_word(&mut tokens, &mut token);
+                // The bodies of here-documents follow the line they're on.
+                for here_doc in here_docs.drain(..) {
+                    tokens[here_doc.index] = input!(take_here_doc(&mut iter, &here_doc)?);
                 }
-            } else if c == ';' && !in_single_quotes && !in_double_quotes {
-                if !token.is_empty() {
-                    tokens.push(input!(std::mem::take(&mut token)));
+                if tokens.last().is_some_and(ends_command) {
+                    tokens.push(Token::Semicolon);
                 }
+            } else if c == ';' && !in_single_quotes && !in_double_quotes {
+                push_word(&mut tokens, &mut token);
                 tokens.push(Token::Semicolon);
             } else if c == '|' && !in_single_quotes && !in_double_quotes {
-                if !token.is_empty() {
-                    tokens.push(input!(std::mem::take(&mut token)));
-                }
+                push_word(&mut tokens, &mut token);
                 if iter.peek() == Some(&'|') {
                     iter.next();
                     if iter.peek() == Some(&'|') {
                         Err(Error::Parse(Token::Pipe))?;
                     }
                     tokens.push(Token::Or);
+                } else if iter.next_if_eq(&'&').is_some() {
+                    tokens.push(Token::PipeAll);
                 } else {
                     tokens.push(Token::Pipe);
                 }
+            } else if (c == '<' || c == '>')
+                && !in_single_quotes
+                && !in_double_quotes
+                && iter.peek() == Some(&'(')
+            {
+                // A process substitution is a word of its own.
+                push_word(&mut tokens, &mut token);
+                token.push(c);
+                take_nested(&mut iter, &mut token, '(', ')', 0);
+                push_word(&mut tokens, &mut token);
             } else if c == '>' && !in_single_quotes && !in_double_quotes {
-                if !token.is_empty() {
-                    tokens.push(input!(std::mem::take(&mut token)));
-                }
+                push_word(&mut tokens, &mut token);
                 if iter.peek() == Some(&'>') {
                     iter.next();
                     if iter.peek() == Some(&'>') {
@@ -64,9 +103,7 @@ impl Lexer {
                     tokens.push(Token::RedirectOut);
                 }
             } else if c == '&' && !in_single_quotes && !in_double_quotes {
-                if !token.is_empty() {
-                    tokens.push(input!(std::mem::take(&mut token)));
-                }
+                push_word(&mut tokens, &mut token);
                 if iter.peek() == Some(&'&') {
                     iter.next();
                     if iter.peek() == Some(&'&') {
@@ -77,37 +114,260 @@ impl Lexer {
                     tokens.push(Token::Background);
                 }
             } else if c == '<' && !in_single_quotes && !in_double_quotes {
-                if !token.is_empty() {
-                    tokens.push(input!(std::mem::take(&mut token)));
+                push_word(&mut tokens, &mut token);
+                if iter.next_if_eq(&'<').is_none() {
+                    tokens.push(Token::RedirectIn);
+                } else if iter.next_if_eq(&'<').is_some() {
+                    tokens.push(Token::HereString);
+                } else {
+                    let strip_tabs = iter.next_if_eq(&'-').is_some();
+                    let (delimiter, quoted) = take_delimiter(&mut iter)
+                        .ok_or(Error::Parse(Token::HereDoc { expand: true }))?;
+                    tokens.push(Token::HereDoc { expand: !quoted });
+                    // The body is filled in once the rest of the line is lexed.
+                    here_docs.push(PendingHereDoc {
+                        index: tokens.len(),
+                        delimiter,
+                        strip_tabs,
+                    });
+                    tokens.push(input!(""));
+                }
+            } else if c == '('
+                && !in_single_quotes
+                && !in_double_quotes
+                && token.strip_suffix('=').is_some_and(is_name)
+            {
+                // The elements of `name=(...)` are part of the assignment.
+                token.push(c);
+                if !take_nested(&mut iter, &mut token, '(', ')', 1) {
+                    return Err(Error::Parse(input!("")));
                 }
-                tokens.push(Token::RedirectIn);
             } else if c == '(' && !in_single_quotes && !in_double_quotes {
-                if !token.is_empty() {
-                    tokens.push(input!(std::mem::take(&mut token)));
+                // An extended glob such as `@(a|b)` is part of the word.
+                if options.extglob && token.ends_with(['?', '*', '+', '@', '!']) {
+                    let mut depth = 0;
+                    token.push(c);
+                    for c in iter.by_ref() {
+                        token.push(c);
+                        match c {
+                            '(' => depth += 1,
+                            ')' if depth == 0 => break,
+                            ')' => depth -= 1,
+                            _ => {}
+                        }
+                    }
+                    continue;
+                }
+                push_word(&mut tokens, &mut token);
+                if at_command_start(&tokens) {
+                    if let Some(expression) = take_arithmetic(&mut iter) {
+                        tokens.push(Token::Arithmetic(expression));
+                        continue;
+                    }
                 }
                 tokens.push(Token::OpenParenthesis);
             } else if c == ')' && !in_single_quotes && !in_double_quotes {
-                if !token.is_empty() {
-                    tokens.push(input!(std::mem::take(&mut token)));
-                }
+                push_word(&mut tokens, &mut token);
                 tokens.push(Token::CloseParenthesis);
             } else {
                 token.push(c);
             }
         }
 
-        if !token.is_empty() {
-            tokens.push(input!(token));
+        if in_single_quotes {
+            return Err(Error::UnterminatedQuote('\''));
+        }
+        if in_double_quotes {
+            return Err(Error::UnterminatedQuote('"'));
+        }
+        push_word(&mut tokens, &mut token);
+
+        if let Some(here_doc) = here_docs.first() {
+            return Err(Error::UnterminatedHereDoc(here_doc.delimiter.clone()));
         }
 
         Ok(tokens)
     }
 }
 
+struct PendingHereDoc {
+    index: usize,
+    delimiter: String,
+    strip_tabs: bool,
+}
+
+/// Takes the delimiter of a here-document with its quotes removed, and
+/// whether any of it was quoted.
+fn take_delimiter(iter: &mut Peekable<Chars>) -> Option<(String, bool)> {
+    while iter.next_if(|c| *c == ' ' || *c == '\t').is_some() {}
+
+    let mut delimiter = String::new();
+    let mut quoted = false;
+    let mut quote = None;
+
+    while let Some(&c) = iter.peek() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => delimiter.push(c),
+            None if c == '\'' || c == '"' => {
+                quote = Some(c);
+                quoted = true;
+            }
+            None if c == '\\' => {
+                quoted = true;
+                iter.next();
+                match iter.peek() {
+                    Some(&c) => delimiter.push(c),
+                    None => break,
+                }
+            }
+            None if " \t\n;|&<>()".contains(c) => break,
+            None => delimiter.push(c),
+        }
+        iter.next();
+    }
+
+    (!delimiter.is_empty() || quoted).then_some((delimiter, quoted))
+}
+
+/// Takes the lines of a here-document up to its delimiter, stripping leading
+/// tabs for `<<-`.
+fn take_here_doc(iter: &mut Peekable<Chars>, here_doc: &PendingHereDoc) -> Result<String, Error> {
+    let mut body = String::new();
+
+    while iter.peek().is_some() {
+        let line = iter.by_ref().take_while(|c| *c != '\n').collect::<String>();
+        let line = if here_doc.strip_tabs {
+            line.trim_start_matches('\t')
+        } else {
+            &line
+        };
+        if line == here_doc.delimiter {
+            return Ok(body);
+        }
+        body.push_str(line);
+        body.push('\n');
+    }
+
+    Err(Error::UnterminatedHereDoc(here_doc.delimiter.clone()))
+}
+
+/// Whether a newline after `token` ends a command, rather than being a blank
+/// line or continuing an incomplete one.
+fn ends_command(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Input(_) | Token::CloseParenthesis | Token::CloseBrace | Token::Arithmetic(_)
+    )
+}
+
 pub fn is_operator(token: &Token) -> bool {
     !matches!(token, Token::Input(_))
 }
 
+/// Copies a `${...}`, `$(...)`, `<(...)` or `>(...)` into `token`, so that
+/// blanks and operators inside it don't end the word, starting `depth`
+/// brackets in. Returns whether the brackets were closed.
+fn take_nested(
+    iter: &mut Peekable<Chars>,
+    token: &mut String,
+    open: char,
+    close: char,
+    mut depth: usize,
+) -> bool {
+    let mut escape = false;
+    let mut in_double_quotes = false;
+    let mut in_single_quotes = false;
+
+    for c in iter.by_ref() {
+        token.push(c);
+        if escape {
+            escape = false;
+        } else if c == '\\' && !in_single_quotes {
+            escape = true;
+        } else if c == '\'' && !in_double_quotes {
+            in_single_quotes = !in_single_quotes;
+        } else if c == '"' && !in_single_quotes {
+            in_double_quotes = !in_double_quotes;
+        } else if in_single_quotes || in_double_quotes {
+            continue;
+        } else if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Takes the expression of an `(( ... ))` command, having seen the first `(`.
+/// Nothing is consumed unless the parentheses close with `))`, as otherwise
+/// they are nested subshells.
+fn take_arithmetic(iter: &mut Peekable<Chars>) -> Option<String> {
+    let mut lookahead = iter.clone();
+    if lookahead.next() != Some('(') {
+        return None;
+    }
+
+    let mut expression = String::new();
+    let mut depth = 0;
+    while let Some(c) = lookahead.next() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            ')' if lookahead.next() == Some(')') => {
+                *iter = lookahead;
+                return Some(expression);
+            }
+            ')' => return None,
+            _ => {}
+        }
+        expression.push(c);
+    }
+
+    None
+}
+
+/// Whether the next word would start a command.
+pub fn at_command_start(tokens: &[Token]) -> bool {
+    match tokens.last() {
+        None => true,
+        Some(
+            Token::RedirectOut
+            | Token::RedirectAppend
+            | Token::RedirectIn
+            | Token::HereDoc { .. }
+            | Token::HereString,
+        ) => false,
+        Some(t) => is_operator(t),
+    }
+}
+
+fn push_word(tokens: &mut Vec<Token>, token: &mut String) {
+    if token.is_empty() {
+        return;
+    }
+
+    // `{` and `}` are reserved words, so they only delimit a group when they
+    // appear where a command could start.
+    let at_command_start = at_command_start(tokens);
+
+    match token.as_str() {
+        "{" if at_command_start => {
+            token.clear();
+            tokens.push(Token::OpenBrace);
+        }
+        "}" if at_command_start => {
+            token.clear();
+            tokens.push(Token::CloseBrace);
+        }
+        _ => tokens.push(input!(std::mem::take(token))),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -118,6 +378,10 @@ mod tests {
         let tokens = Lexer::lex(line).unwrap();
         assert_eq!(tokens, vec![Token::Pipe]);
 
+        let line = "|&";
+        let tokens = Lexer::lex(line).unwrap();
+        assert_eq!(tokens, vec![Token::PipeAll]);
+
         let line = ">";
         let tokens = Lexer::lex(line).unwrap();
         assert_eq!(tokens, vec![Token::RedirectOut]);
@@ -170,15 +434,26 @@ mod tests {
 
         let line = "echo \"Hello, World!\"";
         let tokens = Lexer::lex(line).unwrap();
-        assert_eq!(tokens, vec![input!("echo"), input!("Hello, World!")]);
+        assert_eq!(tokens, vec![input!("echo"), input!("\"Hello, World!\"")]);
 
         let line = "echo \'Hello, World!\'";
         let tokens = Lexer::lex(line).unwrap();
-        assert_eq!(tokens, vec![input!("echo"), input!("Hello, World!")]);
+        assert_eq!(tokens, vec![input!("echo"), input!("\'Hello, World!\'")]);
 
         let line = "echo \'Hello,\\ World!\'";
         let tokens = Lexer::lex(line).unwrap();
-        assert_eq!(tokens, vec![input!("echo"), input!("Hello, World!")]);
+        assert_eq!(tokens, vec![input!("echo"), input!("\'Hello,\\ World!\'")]);
+
+        let line = "echo Hello,\\ \"World\"'!' '|'";
+        let tokens = Lexer::lex(line).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                input!("echo"),
+                input!("Hello,\\ \"World\"'!'"),
+                input!("'|'")
+            ]
+        );
 
         let line = "echo Hello, World! > output.txt";
         let tokens = Lexer::lex(line).unwrap();
@@ -207,4 +482,182 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_lex_braces() {
+        let line = "{ echo foo; }";
+        let tokens = Lexer::lex(line).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::OpenBrace,
+                input!("echo"),
+                input!("foo"),
+                Token::Semicolon,
+                Token::CloseBrace
+            ]
+        );
+
+        let line = "echo { }";
+        let tokens = Lexer::lex(line).unwrap();
+        assert_eq!(tokens, vec![input!("echo"), input!("{"), input!("}")]);
+    }
+
+    #[test]
+    fn test_lex_parameter() {
+        let line = "echo ${x:-a b;c} \"${y#'}'}\"";
+        let tokens = Lexer::lex(line).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                input!("echo"),
+                input!("${x:-a b;c}"),
+                input!("\"${y#'}'}\"")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lex_arithmetic() {
+        let line = "echo $((1 + (2 * 3))) && ((x > 1)) | ((echo) )";
+        let tokens = Lexer::lex(line).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                input!("echo"),
+                input!("$((1 + (2 * 3)))"),
+                Token::And,
+                Token::Arithmetic("x > 1".to_string()),
+                Token::Pipe,
+                Token::OpenParenthesis,
+                Token::OpenParenthesis,
+                input!("echo"),
+                Token::CloseParenthesis,
+                Token::CloseParenthesis
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lex_process_substitution() {
+        let line = "diff <(sort a | uniq) >(cat>out)";
+        let tokens = Lexer::lex(line).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                input!("diff"),
+                input!("<(sort a | uniq)"),
+                input!(">(cat>out)")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lex_comments_and_unterminated_input() {
+        let tokens = Lexer::lex("# comment\necho a#b '#' # c;d\n").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                input!("echo"),
+                input!("a#b"),
+                input!("'#'"),
+                Token::Semicolon
+            ]
+        );
+        assert_eq!(Lexer::lex("echo $# ${#x}").unwrap().len(), 3);
+
+        assert_eq!(Lexer::lex("echo 'a\n"), Err(Error::UnterminatedQuote('\'')));
+        assert_eq!(Lexer::lex("echo \"a"), Err(Error::UnterminatedQuote('"')));
+        assert!(Lexer::lex("echo a \\\n").unwrap_err().is_incomplete());
+        assert_eq!(Lexer::lex("echo a \\\nb").unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_lex_extglob() {
+        let line = "echo @(a|b) x*";
+        assert_eq!(
+            Lexer::lex(line).unwrap(),
+            vec![
+                input!("echo"),
+                input!("@"),
+                Token::OpenParenthesis,
+                input!("a"),
+                Token::Pipe,
+                input!("b"),
+                Token::CloseParenthesis,
+                input!("x*")
+            ]
+        );
+
+        let options = Options {
+            extglob: true,
+            ..Default::default()
+        };
+        let line = "ls !(*.rs|*.md) +(a) (b)";
+        assert_eq!(
+            Lexer::lex_with(line, &options).unwrap(),
+            vec![
+                input!("ls"),
+                input!("!(*.rs|*.md)"),
+                input!("+(a)"),
+                Token::OpenParenthesis,
+                input!("b"),
+                Token::CloseParenthesis
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lex_arrays() {
+        assert_eq!(
+            Lexer::lex("a=(x \"y )\" (z)) b=( ) c (d)").unwrap(),
+            vec![
+                input!("a=(x \"y )\" (z))"),
+                input!("b=( )"),
+                input!("c"),
+                Token::OpenParenthesis,
+                input!("d"),
+                Token::CloseParenthesis
+            ]
+        );
+        assert!(Lexer::lex("a=(x\ny").unwrap_err().is_incomplete());
+        assert_eq!(Lexer::lex("a=(x\ny)").unwrap(), vec![input!("a=(x\ny)")]);
+    }
+
+    #[test]
+    fn test_lex_here_docs() {
+        let line = "cat <<EOF | cat <<-'E O'; cat <<<$x\n$y\nEOF\n\t\tb\n\tE O\necho \\\n1";
+        let tokens = Lexer::lex(line).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                input!("cat"),
+                Token::HereDoc { expand: true },
+                input!("$y\n"),
+                Token::Pipe,
+                input!("cat"),
+                Token::HereDoc { expand: false },
+                input!("b\n"),
+                Token::Semicolon,
+                input!("cat"),
+                Token::HereString,
+                input!("$x"),
+                Token::Semicolon,
+                input!("echo"),
+                input!("1")
+            ]
+        );
+
+        let line = "cat <<EOF\nfoo";
+        assert_eq!(
+            Lexer::lex(line),
+            Err(Error::UnterminatedHereDoc("EOF".to_string()))
+        );
+
+        let line = "cat <<";
+        assert_eq!(
+            Lexer::lex(line),
+            Err(Error::Parse(Token::HereDoc { expand: true }))
+        );
+    }
 }
@@ -1,10 +1,22 @@
+pub mod alias;
+pub mod arith;
 pub mod ast;
+pub mod builtin;
+pub mod complete;
+pub mod editor;
 pub mod error;
 pub mod exec;
+pub mod expand;
+pub mod glob;
 pub mod grammar;
+pub mod history;
 pub mod lex;
 pub mod parse;
 pub mod pipeline;
+pub mod prompt;
+pub mod session;
+pub mod signal;
+pub mod time;
 
 #[macro_export]
 macro_rules! input {
@@ -1,165 +1,336 @@
-use crate::{ast::Ast, error::Error, grammar::Token, lex::is_operator};
+use crate::{ast::Ast, error::Error, expand, grammar::Token};
 
 pub struct Parser;
 
 impl Parser {
     pub fn parse(tokens: &[Token]) -> Result<Ast, Error> {
-        let mut i = 0;
-        let mut nodes = vec![];
-
-        while i < tokens.len() {
-            let token = &tokens[i];
-
-            match token {
-                Token::Pipe => {
-                    let left = nodes.pop().ok_or(Error::Parse(Token::Pipe))?;
-                    let command = parse_command(&tokens[i + 1..]);
-                    i += command.args.len() + 1;
-                    nodes.push(Ast::Pipe {
-                        left: Box::new(left),
-                        right: Box::new(command.into()),
-                    });
-                }
-                Token::RedirectOut => {
-                    let left = nodes.pop().ok_or(Error::Parse(Token::RedirectOut))?;
-                    let right = tokens[i + 1].clone();
-                    i += 1;
-                    nodes.push(Ast::RedirectOut {
-                        left: Box::new(left),
-                        right,
-                    });
-                }
-                Token::RedirectAppend => {
-                    let left = nodes.pop().ok_or(Error::Parse(Token::RedirectAppend))?;
-                    let right = tokens[i + 1].clone();
-                    i += 1;
-                    nodes.push(Ast::RedirectAppend {
-                        left: Box::new(left),
-                        right,
-                    });
-                }
-                Token::And => {
-                    let left = nodes.pop().ok_or(Error::Parse(Token::And))?;
-                    let command = parse_command(&tokens[i + 1..]);
-                    i += command.args.len() + 1;
-                    nodes.push(Ast::And {
-                        left: Box::new(left),
-                        right: Box::new(command.into()),
-                    });
-                }
-                Token::Or => {
-                    let left = nodes.pop().ok_or(Error::Parse(Token::Or))?;
-                    let command = parse_command(&tokens[i + 1..]);
-                    i += command.args.len() + 1;
-                    nodes.push(Ast::Or {
-                        left: Box::new(left),
-                        right: Box::new(command.into()),
-                    });
-                }
-                Token::Semicolon => {
-                    let right_tokens = &tokens[i + 1..];
-                    if !right_tokens.is_empty() {
-                        let left = nodes.pop().ok_or(Error::Parse(Token::Semicolon))?;
-                        let command = parse_command(right_tokens);
-                        i += command.args.len() + 1;
-                        nodes.push(Ast::Sequence {
-                            left: Box::new(left),
-                            right: Box::new(command.into()),
-                        });
-                    }
-                }
-                Token::OpenParenthesis => {
-                    let (subshell, l) = Self::parse_subshell(&tokens[i + 1..]);
-                    let subshell = subshell?;
-                    i += l + 1;
-                    nodes.push(Ast::Subshell {
-                        inner: Box::new(subshell),
-                    });
-                }
-                Token::CloseParenthesis => {
-                    Err(Error::Parse(Token::CloseParenthesis))?;
-                }
-                Token::Background => {
-                    let left = nodes.pop().ok_or(Error::Parse(Token::Background))?;
-                    nodes.push(Ast::Background {
-                        inner: Box::new(left),
-                    });
-                }
-                t if !is_operator(t) => {
-                    let command = parse_command(&tokens[i..]);
-                    i += command.args.len();
-                    nodes.push(command.into());
-                }
-                _ => {
-                    panic!("Unsupported token: {:?}", token)
-                }
-            }
+        let mut tokens = Tokens { tokens, i: 0 };
+        let ast = parse_list(&mut tokens)?;
 
-            i += 1;
+        match tokens.peek() {
+            Some(token) => Err(Error::Parse(token.clone())),
+            None => Ok(ast),
         }
+    }
+}
 
-        nodes.pop().ok_or(Error::Parse(Token::Input(String::new())))
+struct Tokens<'a> {
+    tokens: &'a [Token],
+    i: usize,
+}
+
+impl Tokens<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.i)
     }
 
-    fn parse_subshell(tokens: &[Token]) -> (Result<Ast, Error>, usize) {
-        let mut inner_tokens = vec![];
-        let mut paren_level = 1;
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.i);
+        self.i += 1;
+        token
+    }
 
-        for token in tokens {
-            match token {
-                Token::OpenParenthesis => {
-                    paren_level += 1;
-                    inner_tokens.push(token.clone());
-                }
-                Token::CloseParenthesis => {
-                    paren_level -= 1;
-                    if paren_level == 0 {
-                        break;
-                    }
-                    inner_tokens.push(token.clone());
-                }
-                _ => {
-                    inner_tokens.push(token.clone());
-                }
+    fn expect(&mut self, expected: Token) -> Result<(), Error> {
+        match self.peek() {
+            Some(token) if *token == expected => {
+                self.i += 1;
+                Ok(())
             }
+            Some(token) => Err(Error::Parse(token.clone())),
+            None => Err(unexpected_end()),
         }
+    }
+}
 
-        let l = inner_tokens.len();
+fn unexpected_end() -> Error {
+    Error::Parse(Token::Input(String::new()))
+}
 
-        (Self::parse(&inner_tokens), l)
-    }
+fn starts_command(token: Option<&Token>) -> bool {
+    matches!(
+        token,
+        Some(Token::Input(_) | Token::OpenParenthesis | Token::OpenBrace | Token::Arithmetic(_))
+    )
 }
 
-fn parse_command(tokens: &[Token]) -> Command {
-    let command = tokens[0].clone();
-    let mut args = vec![];
+// list := and_or ((';' | '&') and_or)* (';' | '&')?
+fn parse_list(tokens: &mut Tokens) -> Result<Ast, Error> {
+    let mut items = vec![];
 
-    for token in &tokens[1..] {
-        if is_operator(token) {
+    loop {
+        let mut item = parse_and_or(tokens)?;
+
+        match tokens.peek() {
+            Some(Token::Semicolon) => {
+                tokens.next();
+            }
+            Some(Token::Background) => {
+                tokens.next();
+                item = Ast::Background {
+                    inner: Box::new(item),
+                };
+            }
+            _ => {
+                items.push(item);
+                break;
+            }
+        }
+
+        items.push(item);
+
+        if !starts_command(tokens.peek()) {
             break;
         }
+    }
 
-        args.push(token.clone());
+    let mut items = items.into_iter();
+    let first = items.next().ok_or_else(unexpected_end)?;
+
+    Ok(items.fold(first, |left, right| Ast::Sequence {
+        left: Box::new(left),
+        right: Box::new(right),
+    }))
+}
+
+// and_or := pipeline (('&&' | '||') pipeline)*
+fn parse_and_or(tokens: &mut Tokens) -> Result<Ast, Error> {
+    let mut left = parse_pipeline(tokens)?;
+
+    loop {
+        match tokens.peek() {
+            Some(Token::And) => {
+                tokens.next();
+                let right = parse_pipeline(tokens)?;
+                left = Ast::And {
+                    left: Box::new(left),
+                    right: Box::new(right),
+                };
+            }
+            Some(Token::Or) => {
+                tokens.next();
+                let right = parse_pipeline(tokens)?;
+                left = Ast::Or {
+                    left: Box::new(left),
+                    right: Box::new(right),
+                };
+            }
+            _ => return Ok(left),
+        }
+    }
+}
+
+fn is_word(token: Option<&Token>, word: &str) -> bool {
+    matches!(token, Some(Token::Input(s)) if s == word)
+}
+
+// pipeline := 'time' ['-p'] pipeline | '!' pipeline | command (('|' | '|&') command)*
+fn parse_pipeline(tokens: &mut Tokens) -> Result<Ast, Error> {
+    if is_word(tokens.peek(), "time") {
+        tokens.next();
+        let posix = is_word(tokens.peek(), "-p");
+        if posix {
+            tokens.next();
+        }
+        let inner = parse_pipeline(tokens)?;
+        return Ok(Ast::Time {
+            inner: Box::new(inner),
+            posix,
+        });
+    }
+
+    if is_word(tokens.peek(), "!") {
+        tokens.next();
+        let inner = parse_pipeline(tokens)?;
+        return Ok(Ast::Not {
+            inner: Box::new(inner),
+        });
+    }
+
+    let mut left = parse_command(tokens)?;
+
+    loop {
+        let all = match tokens.peek() {
+            Some(Token::Pipe) => false,
+            Some(Token::PipeAll) => true,
+            _ => break,
+        };
+        tokens.next();
+        let right = Box::new(parse_command(tokens)?);
+        left = if all {
+            Ast::PipeAll {
+                left: Box::new(left),
+                right,
+            }
+        } else {
+            Ast::Pipe {
+                left: Box::new(left),
+                right,
+            }
+        };
     }
 
-    Command { command, args }
+    Ok(left)
 }
 
-#[derive(Debug)]
-struct Command {
-    command: Token,
-    args: Vec<Token>,
+// command := simple_command | '(' list ')' redirect* | '{' list '}' redirect*
+//          | '((' expression '))' redirect*
+fn parse_command(tokens: &mut Tokens) -> Result<Ast, Error> {
+    match tokens.peek() {
+        Some(Token::OpenParenthesis) => {
+            tokens.next();
+            let inner = parse_list(tokens)?;
+            tokens.expect(Token::CloseParenthesis)?;
+            let subshell = Ast::Subshell {
+                inner: Box::new(inner),
+            };
+            let redirects = parse_redirects(tokens, None)?;
+            Ok(wrap_redirects(subshell, redirects))
+        }
+        Some(Token::OpenBrace) => {
+            tokens.next();
+            let inner = parse_list(tokens)?;
+            tokens.expect(Token::CloseBrace)?;
+            let group = Ast::Group {
+                inner: Box::new(inner),
+            };
+            let redirects = parse_redirects(tokens, None)?;
+            Ok(wrap_redirects(group, redirects))
+        }
+        Some(Token::Arithmetic(expression)) => {
+            let arithmetic = Ast::Arithmetic {
+                expression: expression.clone(),
+            };
+            tokens.next();
+            let redirects = parse_redirects(tokens, None)?;
+            Ok(wrap_redirects(arithmetic, redirects))
+        }
+        Some(Token::Input(_)) => parse_simple_command(tokens),
+        Some(token) => Err(Error::Parse(token.clone())),
+        None => Err(unexpected_end()),
+    }
 }
 
-impl From<Command> for Ast {
-    fn from(command: Command) -> Ast {
+// simple_command := assignment* word (word | redirect)* | assignment+ redirect*
+fn parse_simple_command(tokens: &mut Tokens) -> Result<Ast, Error> {
+    let mut assignments = vec![];
+    while let Some(token) = tokens.peek().filter(|token| is_assignment(token)) {
+        assignments.push(token.clone());
+        tokens.next();
+    }
+
+    let Some(command @ Token::Input(_)) = tokens.peek().cloned() else {
+        let redirects = parse_redirects(tokens, None)?;
+        return Ok(wrap_redirects(Ast::Assignment { assignments }, redirects));
+    };
+    tokens.next();
+
+    // function := name '(' ')' command
+    if let (true, Token::Input(name)) = (assignments.is_empty(), &command) {
+        if tokens.peek() == Some(&Token::OpenParenthesis) {
+            tokens.next();
+            tokens.expect(Token::CloseParenthesis)?;
+            let body = parse_command(tokens)?;
+            return Ok(Ast::Function {
+                name: name.clone(),
+                body: Box::new(body),
+            });
+        }
+    }
+
+    let mut args = vec![];
+    let redirects = parse_redirects(tokens, Some(&mut args))?;
+
+    Ok(wrap_redirects(
         Ast::Command {
-            command: command.command,
-            args: command.args,
+            assignments,
+            command,
+            args,
+        },
+        redirects,
+    ))
+}
+
+/// Whether `token` is a `NAME=value` word.
+fn is_assignment(token: &Token) -> bool {
+    match token {
+        Token::Input(word) => word
+            .split_once('=')
+            .is_some_and(|(name, _)| expand::is_name(name)),
+        _ => false,
+    }
+}
+
+/// Collects redirections, and any words interleaved with them into `words`.
+fn parse_redirects(
+    tokens: &mut Tokens,
+    mut words: Option<&mut Vec<Token>>,
+) -> Result<Vec<Redirect>, Error> {
+    let mut redirects = vec![];
+
+    loop {
+        match (tokens.peek(), words.as_deref_mut()) {
+            (Some(word @ Token::Input(_)), Some(words)) => {
+                words.push(word.clone());
+                tokens.next();
+            }
+            (
+                Some(
+                    kind @ (Token::RedirectOut
+                    | Token::RedirectAppend
+                    | Token::HereDoc { .. }
+                    | Token::HereString),
+                ),
+                _,
+            ) => {
+                let kind = kind.clone();
+                tokens.next();
+                match tokens.next() {
+                    Some(target @ Token::Input(_)) => redirects.push(Redirect {
+                        kind,
+                        target: target.clone(),
+                    }),
+                    Some(token) => Err(Error::Parse(token.clone()))?,
+                    None => Err(unexpected_end())?,
+                }
+            }
+            (Some(Token::RedirectIn), _) => Err(Error::Parse(Token::RedirectIn))?,
+            _ => return Ok(redirects),
         }
     }
 }
 
+struct Redirect {
+    kind: Token,
+    target: Token,
+}
+
+/// Wraps `ast` in its redirections. The innermost redirection wins, so they
+/// are applied in reverse to let the last one on the line take effect.
+fn wrap_redirects(ast: Ast, redirects: Vec<Redirect>) -> Ast {
+    redirects
+        .into_iter()
+        .rev()
+        .fold(ast, |left, Redirect { kind, target }| match kind {
+            Token::RedirectAppend => Ast::RedirectAppend {
+                left: Box::new(left),
+                right: target,
+            },
+            Token::HereDoc { expand } => Ast::HereDoc {
+                left: Box::new(left),
+                body: target.to_string(),
+                expand,
+            },
+            Token::HereString => Ast::HereString {
+                left: Box::new(left),
+                right: target,
+            },
+            _ => Ast::RedirectOut {
+                left: Box::new(left),
+                right: target,
+            },
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -181,10 +352,12 @@ mod tests {
             ast,
             Ast::Pipe {
                 left: Box::new(Ast::Command {
+                    assignments: vec![],
                     command: input!("ls"),
                     args: vec![input!("-l")],
                 }),
                 right: Box::new(Ast::Command {
+                    assignments: vec![],
                     command: input!("grep"),
                     args: vec![input!("main")],
                 }),
@@ -206,6 +379,7 @@ mod tests {
             ast,
             Ast::Subshell {
                 inner: Box::new(Ast::Command {
+                    assignments: vec![],
                     command: input!("echo"),
                     args: vec![input!("foo")],
                 }),
@@ -225,6 +399,7 @@ mod tests {
             ast,
             Ast::Subshell {
                 inner: Box::new(Ast::Command {
+                    assignments: vec![],
                     command: input!("echo"),
                     args: vec![input!("foo")],
                 }),
@@ -249,6 +424,7 @@ mod tests {
                 inner: Box::new(Ast::Subshell {
                     inner: Box::new(Ast::Subshell {
                         inner: Box::new(Ast::Command {
+                            assignments: vec![],
                             command: input!("echo"),
                             args: vec![input!("foo")],
                         }),
@@ -272,10 +448,12 @@ mod tests {
             Ast::Subshell {
                 inner: Box::new(Ast::Pipe {
                     left: Box::new(Ast::Command {
+                        assignments: vec![],
                         command: input!("echo"),
                         args: vec![input!("foo")],
                     }),
                     right: Box::new(Ast::Command {
+                        assignments: vec![],
                         command: input!("cat"),
                         args: vec![],
                     }),
@@ -301,16 +479,19 @@ mod tests {
                 left: Box::new(Ast::Subshell {
                     inner: Box::new(Ast::Pipe {
                         left: Box::new(Ast::Command {
+                            assignments: vec![],
                             command: input!("echo"),
                             args: vec![input!("foo")],
                         }),
                         right: Box::new(Ast::Command {
+                            assignments: vec![],
                             command: input!("cat"),
                             args: vec![],
                         }),
                     }),
                 }),
                 right: Box::new(Ast::Command {
+                    assignments: vec![],
                     command: input!("cat"),
                     args: vec![],
                 }),
@@ -338,16 +519,19 @@ mod tests {
                     left: Box::new(Ast::Subshell {
                         inner: Box::new(Ast::Pipe {
                             left: Box::new(Ast::Command {
+                                assignments: vec![],
                                 command: input!("echo"),
                                 args: vec![input!("foo")],
                             }),
                             right: Box::new(Ast::Command {
+                                assignments: vec![],
                                 command: input!("cat"),
                                 args: vec![],
                             }),
                         }),
                     }),
                     right: Box::new(Ast::Command {
+                        assignments: vec![],
                         command: input!("cat"),
                         args: vec![],
                     }),
@@ -378,6 +562,7 @@ mod tests {
         assert_eq!(
             ast,
             Ast::Command {
+                assignments: vec![],
                 command: input!("echo"),
                 args: vec![input!("foo")],
             }
@@ -396,10 +581,12 @@ mod tests {
             ast,
             Ast::Sequence {
                 left: Box::new(Ast::Command {
+                    assignments: vec![],
                     command: input!("echo"),
                     args: vec![input!("foo")],
                 }),
                 right: Box::new(Ast::Command {
+                    assignments: vec![],
                     command: input!("echo"),
                     args: vec![input!("bar")],
                 }),
@@ -420,14 +607,334 @@ mod tests {
             ast,
             Ast::Sequence {
                 left: Box::new(Ast::Command {
+                    assignments: vec![],
                     command: input!("echo"),
                     args: vec![input!("foo")],
                 }),
                 right: Box::new(Ast::Command {
+                    assignments: vec![],
+                    command: input!("echo"),
+                    args: vec![input!("bar")],
+                }),
+            }
+        );
+    }
+
+    #[test]
+    fn test_group() {
+        // { echo foo; echo bar; } > out
+        let tokens = vec![
+            Token::OpenBrace,
+            input!("echo"),
+            input!("foo"),
+            Token::Semicolon,
+            input!("echo"),
+            input!("bar"),
+            Token::Semicolon,
+            Token::CloseBrace,
+            Token::RedirectOut,
+            input!("out"),
+        ];
+        let ast = Parser::parse(&tokens).unwrap();
+        assert_eq!(
+            ast,
+            Ast::RedirectOut {
+                left: Box::new(Ast::Group {
+                    inner: Box::new(Ast::Sequence {
+                        left: Box::new(Ast::Command {
+                            assignments: vec![],
+                            command: input!("echo"),
+                            args: vec![input!("foo")],
+                        }),
+                        right: Box::new(Ast::Command {
+                            assignments: vec![],
+                            command: input!("echo"),
+                            args: vec![input!("bar")],
+                        }),
+                    }),
+                }),
+                right: input!("out"),
+            }
+        );
+
+        // { echo foo }
+        let tokens = vec![Token::OpenBrace, input!("echo"), input!("foo"), input!("}")];
+        let ast = Parser::parse(&tokens);
+        assert_eq!(ast, Err(Error::Parse(input!(""))));
+    }
+
+    #[test]
+    fn test_background() {
+        // echo foo & echo bar
+        let tokens = vec![
+            input!("echo"),
+            input!("foo"),
+            Token::Background,
+            input!("echo"),
+            input!("bar"),
+        ];
+        let ast = Parser::parse(&tokens).unwrap();
+        assert_eq!(
+            ast,
+            Ast::Sequence {
+                left: Box::new(Ast::Background {
+                    inner: Box::new(Ast::Command {
+                        assignments: vec![],
+                        command: input!("echo"),
+                        args: vec![input!("foo")],
+                    }),
+                }),
+                right: Box::new(Ast::Command {
+                    assignments: vec![],
                     command: input!("echo"),
                     args: vec![input!("bar")],
                 }),
             }
         );
     }
+
+    #[test]
+    fn test_not_and_time() {
+        // ! echo foo | grep bar
+        let tokens = vec![
+            input!("!"),
+            input!("echo"),
+            input!("foo"),
+            Token::Pipe,
+            input!("grep"),
+            input!("bar"),
+        ];
+        let ast = Parser::parse(&tokens).unwrap();
+        assert_eq!(
+            ast,
+            Ast::Not {
+                inner: Box::new(Ast::Pipe {
+                    left: Box::new(Ast::Command {
+                        assignments: vec![],
+                        command: input!("echo"),
+                        args: vec![input!("foo")],
+                    }),
+                    right: Box::new(Ast::Command {
+                        assignments: vec![],
+                        command: input!("grep"),
+                        args: vec![input!("bar")],
+                    }),
+                }),
+            }
+        );
+
+        // time -p ! true && echo !
+        let tokens = vec![
+            input!("time"),
+            input!("-p"),
+            input!("!"),
+            input!("true"),
+            Token::And,
+            input!("echo"),
+            input!("!"),
+        ];
+        let ast = Parser::parse(&tokens).unwrap();
+        assert_eq!(
+            ast,
+            Ast::And {
+                left: Box::new(Ast::Time {
+                    inner: Box::new(Ast::Not {
+                        inner: Box::new(Ast::Command {
+                            assignments: vec![],
+                            command: input!("true"),
+                            args: vec![],
+                        }),
+                    }),
+                    posix: true,
+                }),
+                right: Box::new(Ast::Command {
+                    assignments: vec![],
+                    command: input!("echo"),
+                    args: vec![input!("!")],
+                }),
+            }
+        );
+
+        // !
+        let tokens = vec![input!("!")];
+        let ast = Parser::parse(&tokens);
+        assert_eq!(ast, Err(Error::Parse(input!(""))));
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        // (( i++ )) && echo
+        let tokens = vec![
+            Token::Arithmetic(" i++ ".to_string()),
+            Token::And,
+            input!("echo"),
+        ];
+        let ast = Parser::parse(&tokens).unwrap();
+        assert_eq!(
+            ast,
+            Ast::And {
+                left: Box::new(Ast::Arithmetic {
+                    expression: " i++ ".to_string(),
+                }),
+                right: Box::new(Ast::Command {
+                    assignments: vec![],
+                    command: input!("echo"),
+                    args: vec![],
+                }),
+            }
+        );
+    }
+
+    #[test]
+    fn test_here_docs() {
+        // cat <<EOF <<<$x (with the body "$y\n")
+        let tokens = vec![
+            input!("cat"),
+            Token::HereDoc { expand: true },
+            input!("$y\n"),
+            Token::HereString,
+            input!("$x"),
+        ];
+        let ast = Parser::parse(&tokens).unwrap();
+        assert_eq!(
+            ast,
+            Ast::HereDoc {
+                left: Box::new(Ast::HereString {
+                    left: Box::new(Ast::Command {
+                        assignments: vec![],
+                        command: input!("cat"),
+                        args: vec![],
+                    }),
+                    right: input!("$x"),
+                }),
+                body: "$y\n".to_string(),
+                expand: true,
+            }
+        );
+
+        // cat <<<
+        let tokens = vec![input!("cat"), Token::HereString];
+        let ast = Parser::parse(&tokens);
+        assert_eq!(ast, Err(Error::Parse(input!(""))));
+    }
+
+    #[test]
+    fn test_pipe_all() {
+        // a |& b | c
+        let tokens = vec![
+            input!("a"),
+            Token::PipeAll,
+            input!("b"),
+            Token::Pipe,
+            input!("c"),
+        ];
+        let ast = Parser::parse(&tokens).unwrap();
+        assert_eq!(
+            ast,
+            Ast::Pipe {
+                left: Box::new(Ast::PipeAll {
+                    left: Box::new(Ast::Command {
+                        assignments: vec![],
+                        command: input!("a"),
+                        args: vec![],
+                    }),
+                    right: Box::new(Ast::Command {
+                        assignments: vec![],
+                        command: input!("b"),
+                        args: vec![],
+                    }),
+                }),
+                right: Box::new(Ast::Command {
+                    assignments: vec![],
+                    command: input!("c"),
+                    args: vec![],
+                }),
+            }
+        );
+    }
+
+    #[test]
+    fn test_assignments() {
+        // A=1 B= env C=2 > out
+        let tokens = vec![
+            input!("A=1"),
+            input!("B="),
+            input!("env"),
+            input!("C=2"),
+            Token::RedirectOut,
+            input!("out"),
+        ];
+        let ast = Parser::parse(&tokens).unwrap();
+        assert_eq!(
+            ast,
+            Ast::RedirectOut {
+                left: Box::new(Ast::Command {
+                    assignments: vec![input!("A=1"), input!("B=")],
+                    command: input!("env"),
+                    args: vec![input!("C=2")],
+                }),
+                right: input!("out"),
+            }
+        );
+
+        // A=1 && '=x' 1=y
+        let tokens = vec![input!("A=1"), Token::And, input!("'=x'"), input!("1=y")];
+        let ast = Parser::parse(&tokens).unwrap();
+        assert_eq!(
+            ast,
+            Ast::And {
+                left: Box::new(Ast::Assignment {
+                    assignments: vec![input!("A=1")],
+                }),
+                right: Box::new(Ast::Command {
+                    assignments: vec![],
+                    command: input!("'=x'"),
+                    args: vec![input!("1=y")],
+                }),
+            }
+        );
+    }
+
+    #[test]
+    fn test_functions() {
+        // f() { echo $1; }; f a
+        let tokens = vec![
+            input!("f"),
+            Token::OpenParenthesis,
+            Token::CloseParenthesis,
+            Token::OpenBrace,
+            input!("echo"),
+            input!("$1"),
+            Token::Semicolon,
+            Token::CloseBrace,
+            Token::Semicolon,
+            input!("f"),
+            input!("a"),
+        ];
+        let ast = Parser::parse(&tokens).unwrap();
+        assert_eq!(
+            ast,
+            Ast::Sequence {
+                left: Box::new(Ast::Function {
+                    name: "f".to_string(),
+                    body: Box::new(Ast::Group {
+                        inner: Box::new(Ast::Command {
+                            assignments: vec![],
+                            command: input!("echo"),
+                            args: vec![input!("$1")],
+                        }),
+                    }),
+                }),
+                right: Box::new(Ast::Command {
+                    assignments: vec![],
+                    command: input!("f"),
+                    args: vec![input!("a")],
+                }),
+            }
+        );
+
+        // f ( x
+        let tokens = vec![input!("f"), Token::OpenParenthesis, input!("x")];
+        assert_eq!(Parser::parse(&tokens), Err(Error::Parse(input!("x"))));
+    }
 }
@@ -1,18 +1,20 @@
 use std::io;
 
 use crate::{
+    alias,
     exec::{execute, RunningProcess},
     lex::Lexer,
     parse::Parser,
+    session::Session,
 };
 
 pub struct Pipeline;
 
 impl Pipeline {
-    pub fn run(input: &str) -> io::Result<RunningProcess> {
-        let tokens = Lexer::lex(input)?;
+    pub fn run(input: &str, session: &mut Session) -> io::Result<RunningProcess> {
+        let tokens = alias::expand(Lexer::lex_with(input, &session.options)?, session)?;
         let ast = Parser::parse(&tokens)?;
-        execute(&ast)
+        execute(&ast, session)
     }
 }
 
@@ -28,11 +30,11 @@ mod tests {
     #[test]
     fn test_pipeline_pipe() {
         let input = "echo 'foo\nbar\nbaz' | grep bar";
-        let status = Pipeline::run(input).unwrap();
+        let status = Pipeline::run(input, &mut Session::new()).unwrap();
         assert!(status.success());
 
         let input = "echo 'foo\nbar\nbaz' | grep qux";
-        let status = Pipeline::run(input).unwrap();
+        let status = Pipeline::run(input, &mut Session::new()).unwrap();
         assert!(!status.success());
     }
 
@@ -45,7 +47,7 @@ mod tests {
             .write_all("foo\n".as_bytes())
             .unwrap();
         let input = format!("echo bar >> {}", path.to_str().unwrap());
-        let status = Pipeline::run(&input).unwrap();
+        let status = Pipeline::run(&input, &mut Session::new()).unwrap();
         assert!(status.success());
         let mut result = String::new();
         File::open(&path)
@@ -63,7 +65,7 @@ mod tests {
             "echo foo | cat | cat|cat  |  cat > {}",
             path.to_str().unwrap()
         );
-        let status = Pipeline::run(&input).unwrap();
+        let status = Pipeline::run(&input, &mut Session::new()).unwrap();
         assert!(status.success());
         let mut result = String::new();
         File::open(&path)
@@ -81,7 +83,7 @@ mod tests {
             "((( echo foo | cat ) | cat ) | cat ) | cat > {}",
             path.to_str().unwrap()
         );
-        let status = Pipeline::run(&input).unwrap();
+        let status = Pipeline::run(&input, &mut Session::new()).unwrap();
         assert!(status.success());
         let mut result = String::new();
         File::open(&path)
@@ -90,4 +92,387 @@ mod tests {
             .unwrap();
         assert_eq!(&result, "foo\n");
     }
+
+    #[test]
+    fn test_group_redirect() {
+        let dir = TempDir::new("").unwrap();
+        let path = dir.path().join("output.txt");
+        let input = format!(
+            "{{ echo foo; echo bar | cat; }} > {}",
+            path.to_str().unwrap()
+        );
+        let status = Pipeline::run(&input, &mut Session::new()).unwrap();
+        assert!(status.success());
+        let mut result = String::new();
+        File::open(&path)
+            .unwrap()
+            .read_to_string(&mut result)
+            .unwrap();
+        assert_eq!(&result, "foo\nbar\n");
+    }
+
+    #[test]
+    fn test_group_runs_in_current_shell() {
+        let dir = TempDir::new("").unwrap();
+        let path = dir.path().canonicalize().unwrap();
+        let mut session = Session::new();
+        let input = format!("{{ cd {}; }}", path.to_str().unwrap());
+        let status = Pipeline::run(&input, &mut session).unwrap();
+        assert!(status.success());
+        assert_eq!(session.cwd, path);
+    }
+
+    #[test]
+    fn test_subshell_isolation() {
+        let dir = TempDir::new("").unwrap();
+        let mut session = Session::new();
+        let cwd = session.cwd.clone();
+        let input = format!("(cd {}; exit 3)", dir.path().to_str().unwrap());
+        let status = Pipeline::run(&input, &mut session).unwrap();
+        match status {
+            RunningProcess::Foreground(status) => assert_eq!(status.code(), Some(3)),
+            RunningProcess::Background => panic!("expected a foreground process"),
+        }
+        assert_eq!(session.cwd, cwd);
+        assert_eq!(session.exit, None);
+
+        let status = Pipeline::run("(exit 1); echo foo", &mut session).unwrap();
+        assert!(status.success());
+        assert_eq!(session.exit, None);
+    }
+
+    #[test]
+    fn test_exit() {
+        let mut session = Session::new();
+        let status = Pipeline::run("exit 2 && echo foo", &mut session).unwrap();
+        assert!(!status.success());
+        assert_eq!(session.exit, Some(2));
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let mut session = Session::new();
+        let status = Pipeline::run("(( i = 5, i > 3 )) && (( i -= 5 ))", &mut session).unwrap();
+        assert!(!status.success());
+        assert_eq!(session.var("i"), Some("0"));
+
+        let result = Pipeline::run("(( 1 / i ))", &mut session);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_here_docs() {
+        let dir = TempDir::new("").unwrap();
+        let path = dir.path().join("output.txt");
+        let mut session = Session::new();
+        session.vars.insert("x".to_string(), "a  b".to_string());
+
+        let input = format!(
+            "cat <<EOF >{0}; cat <<-'EOF' >>{0}; tr a-z A-Z <<<$x >>{0}\n$x\nEOF\n\t$x\n\tEOF",
+            path.display()
+        );
+        Pipeline::run(&input, &mut session).unwrap();
+
+        let mut contents = String::new();
+        File::open(&path)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "a  b\n$x\nA  B\n");
+    }
+
+    #[test]
+    fn test_large_here_doc() {
+        let dir = TempDir::new("").unwrap();
+        let path = dir.path().join("output.txt");
+        let body = "x".repeat(1 << 20);
+        let input = format!("cat <<EOF >{}\n{}\nEOF", path.display(), body);
+        Pipeline::run(&input, &mut Session::new()).unwrap();
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().len(),
+            body.len() as u64 + 1
+        );
+    }
+
+    #[test]
+    fn test_process_substitution() {
+        let dir = TempDir::new("").unwrap();
+        let mut session = Session::new();
+        session.cwd = dir.path().to_path_buf();
+
+        Pipeline::run(
+            "paste -d, <(echo a; echo b) <(printf '1\\n2\\n') >joined.txt",
+            &mut session,
+        )
+        .unwrap();
+        let status = Pipeline::run(
+            "echo foo | tee >(tr a-z A-Z >upper.txt) >/dev/null",
+            &mut session,
+        )
+        .unwrap();
+        assert!(status.success());
+
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("joined.txt"), "a,1\nb,2\n");
+        assert_eq!(read("upper.txt"), "FOO\n");
+    }
+
+    #[test]
+    fn test_assignments() {
+        let dir = TempDir::new("").unwrap();
+        let path = dir.path().join("output.txt");
+        let mut session = Session::new();
+
+        let input = format!(
+            "A=1; B=2 sh -c 'echo $A$B' >{0}; export A; C='x y' sh -c 'echo $A $C' >>{0}",
+            path.display()
+        );
+        Pipeline::run(&input, &mut session).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "2\n1 x y\n");
+        assert_eq!(session.var("A"), Some("1"));
+        assert_eq!(session.var("B"), None);
+        assert_eq!(session.var("C"), None);
+
+        Pipeline::run("D=4 export E=5; unset A", &mut session).unwrap();
+        assert_eq!(session.var("D"), Some("4"));
+        assert_eq!(session.var("A"), None);
+
+        Pipeline::run("readonly D", &mut session).unwrap();
+        assert!(Pipeline::run("D=6", &mut session).is_err());
+        assert_eq!(session.var("D"), Some("4"));
+    }
+
+    #[test]
+    fn test_errexit() {
+        let dir = TempDir::new("").unwrap();
+        let mut session = Session::new();
+        session.cwd = dir.path().to_path_buf();
+
+        let input = "set -e; false && true; ! true; false || true; { false && true; }; echo a >out.txt; false; echo b >>out.txt";
+        Pipeline::run(input, &mut session).unwrap();
+        assert_eq!(session.exit, Some(1));
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("out.txt"), "a\n");
+
+        let mut session = Session::new();
+        Pipeline::run("set -e; true && (exit 3); echo b", &mut session).unwrap();
+        assert_eq!(session.exit, Some(3));
+    }
+
+    #[test]
+    fn test_set_options() {
+        let dir = TempDir::new("").unwrap();
+        File::create(dir.path().join("a.txt")).unwrap();
+        let mut session = Session::new();
+        session.cwd = dir.path().to_path_buf();
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+
+        assert!(Pipeline::run("false | true", &mut session)
+            .unwrap()
+            .success());
+        Pipeline::run("set -o pipefail", &mut session).unwrap();
+        assert!(!Pipeline::run("false | true", &mut session)
+            .unwrap()
+            .success());
+
+        Pipeline::run("set -f; echo *.txt >glob.txt", &mut session).unwrap();
+        assert_eq!(read("glob.txt"), "*.txt\n");
+
+        Pipeline::run("set -C", &mut session).unwrap();
+        assert!(Pipeline::run("echo x >glob.txt", &mut session).is_err());
+        assert!(Pipeline::run("echo x >/dev/null", &mut session).is_ok());
+
+        Pipeline::run("set -u", &mut session).unwrap();
+        assert!(Pipeline::run("echo $nope", &mut session).is_err());
+        assert_eq!(session.exit, Some(1));
+        session.exit = None;
+        assert!(Pipeline::run("echo ${nope:-x} >/dev/null", &mut session).is_ok());
+
+        Pipeline::run(
+            "{ set -x; A=1; echo \"a b\" c; } |& cat >>trace.txt",
+            &mut session,
+        )
+        .unwrap();
+        assert_eq!(read("trace.txt"), "+ A=1\n+ echo 'a b' c\na b c\n");
+    }
+
+    #[test]
+    fn test_traps() {
+        let dir = TempDir::new("").unwrap();
+        let mut session = Session::new();
+        session.cwd = dir.path().to_path_buf();
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+
+        // The signal may reach another of the test's threads, so it's only
+        // seen by the shell a moment after `kill` exits.
+        Pipeline::run(
+            "trap 'echo usr1 $? >>out.txt' USR1; kill -USR1 $$",
+            &mut session,
+        )
+        .unwrap();
+        for _ in 0..100 {
+            Pipeline::run("true", &mut session).unwrap();
+            if dir.path().join("out.txt").exists() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(read("out.txt"), "usr1 0\n");
+
+        let input =
+            "trap 'echo err $? >>err.txt' ERR; false; false && true; ! true; sh -c 'exit 4'";
+        Pipeline::run(input, &mut session).unwrap();
+        assert_eq!(read("err.txt"), "err 1\nerr 4\n");
+        assert_eq!(session.status, 4);
+
+        let input = "trap - ERR; trap 'echo debug >>debug.txt' DEBUG; true; x=1";
+        Pipeline::run(input, &mut session).unwrap();
+        assert_eq!(read("debug.txt"), "debug\ndebug\n");
+        Pipeline::run("trap - DEBUG", &mut session).unwrap();
+
+        let input =
+            "(trap 'echo exit >>exit.txt' EXIT; echo sub >>exit.txt); echo parent >>exit.txt";
+        Pipeline::run(input, &mut session).unwrap();
+        assert_eq!(read("exit.txt"), "sub\nexit\nparent\n");
+        assert!(!session.traps.contains_key("EXIT"));
+    }
+
+    #[test]
+    fn test_functions() {
+        let dir = TempDir::new("").unwrap();
+        let mut session = Session::new();
+        session.cwd = dir.path().to_path_buf();
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+
+        let input = "greet() { echo \"hi $1 $#\" >>out.txt; }; greet a b; x=1 greet c";
+        Pipeline::run(input, &mut session).unwrap();
+        assert_eq!(read("out.txt"), "hi a 2\nhi c 1\n");
+        assert_eq!(session.var("x"), None);
+
+        let input = "args() { printf '[%s]\\n' \"$@\" >args.txt; }; args a 'b c'";
+        Pipeline::run(input, &mut session).unwrap();
+        assert_eq!(read("args.txt"), "[a]\n[b c]\n");
+
+        let input = "f() { trap 'echo ret $? >>ret.txt' RETURN; return 3; echo no >>ret.txt; }; f";
+        Pipeline::run(input, &mut session).unwrap();
+        assert_eq!(session.status, 3);
+        assert_eq!(read("ret.txt"), "ret 3\n");
+
+        Pipeline::run("trap - RETURN; unset -f f greet args", &mut session).unwrap();
+        assert!(session.functions.is_empty());
+        assert!(Pipeline::run("f", &mut session).is_err());
+
+        Pipeline::run("return 1", &mut session).unwrap();
+        assert_eq!(session.status, 1);
+        assert!(session.returning.is_none());
+
+        // More than a pipe's buffer goes through a function on the left.
+        let input = "big() { seq 100000; }; big | cat >big.txt";
+        Pipeline::run(input, &mut session).unwrap();
+        assert!(read("big.txt").len() > 64 * 1024);
+        assert!(read("big.txt").ends_with("\n100000\n"));
+    }
+
+    #[test]
+    fn test_source() {
+        let dir = TempDir::new("").unwrap();
+        let lib = dir.path().join("lib");
+        std::fs::create_dir(&lib).unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        let write = |path: &str, text: &str| std::fs::write(dir.path().join(path), text).unwrap();
+        write(
+            "lib/helpers.sh",
+            "greet() {\n  echo \"hi $1\" >>out.txt\n}\nloaded=$#\n",
+        );
+        write(
+            "args.sh",
+            "echo \"$# $1\" >>out.txt\nprintf '[%s]' \"$@\" >>out.txt\nreturn 4\necho no >>out.txt\n",
+        );
+        write("cd.sh", "cd sub\n");
+
+        let mut session = Session::new();
+        session.cwd = dir.path().to_path_buf();
+        let path = format!("{}:{}", lib.display(), session.var("PATH").unwrap());
+        session.vars.insert("PATH".into(), path);
+        session.args = vec!["x".into(), "y".into()];
+
+        Pipeline::run("source helpers.sh; greet you", &mut session).unwrap();
+        assert_eq!(session.var("loaded"), Some("2"));
+
+        Pipeline::run(". ./args.sh a 'b c'", &mut session).unwrap();
+        assert_eq!(session.status, 4);
+        assert_eq!(session.args, vec!["x", "y"]);
+        assert!(session.returning.is_none());
+
+        let read = || std::fs::read_to_string(dir.path().join("out.txt")).unwrap();
+        assert_eq!(read(), "hi you\n2 a\n[a][b c]");
+
+        Pipeline::run(". cd.sh", &mut session).unwrap();
+        assert_eq!(session.cwd, dir.path().join("sub").canonicalize().unwrap());
+
+        Pipeline::run("source missing.sh", &mut session).unwrap();
+        assert_eq!(session.status, 1);
+    }
+
+    #[test]
+    fn test_aliases() {
+        let dir = TempDir::new("").unwrap();
+        let mut session = Session::new();
+        session.cwd = dir.path().to_path_buf();
+        let read = || std::fs::read_to_string(dir.path().join("out.txt")).unwrap();
+
+        let input = "alias say='echo said' e='echo ' w=word";
+        Pipeline::run(input, &mut session).unwrap();
+        Pipeline::run("say hi >>out.txt; echo say >>out.txt", &mut session).unwrap();
+        Pipeline::run("e w >>out.txt; e 'w' >>out.txt", &mut session).unwrap();
+        assert!(Pipeline::run("\\say x 2>/dev/null", &mut session).is_err());
+        assert_eq!(read(), "said hi\nsay\nword\nw\n");
+
+        Pipeline::run("unalias say; alias", &mut session).unwrap();
+        assert!(!session.aliases.contains_key("say"));
+    }
+
+    #[test]
+    fn test_extglob() {
+        let dir = TempDir::new("").unwrap();
+        for name in ["a.rs", "b.rs", "c.txt"] {
+            File::create(dir.path().join(name)).unwrap();
+        }
+        let mut session = Session::new();
+        session.cwd = dir.path().to_path_buf();
+
+        assert!(Pipeline::run("echo !(*.txt) >out", &mut session).is_err());
+        Pipeline::run("shopt -s extglob", &mut session).unwrap();
+        Pipeline::run("echo !(*.txt) >out", &mut session).unwrap();
+        let out = std::fs::read_to_string(dir.path().join("out")).unwrap();
+        assert_eq!(out, "a.rs b.rs out\n");
+    }
+
+    #[test]
+    fn test_arrays() {
+        let dir = TempDir::new("").unwrap();
+        let mut session = Session::new();
+        session.cwd = dir.path().to_path_buf();
+        let read = || std::fs::read_to_string(dir.path().join("out.txt")).unwrap();
+
+        let input = "a=(1 \"2 3\"\n  $(echo 4 5)); printf '[%s]' \"${a[@]}\" ${#a[@]} >out.txt";
+        Pipeline::run(input, &mut session).unwrap();
+        assert_eq!(read(), "[1][2 3][4][5][4]");
+
+        Pipeline::run(
+            "a=x; unset b; b=(); printf '<%s>' \"${a[@]}\" \"${b[@]}\" >out.txt",
+            &mut session,
+        )
+        .unwrap();
+        assert_eq!(read(), "<x><2 3><4><5>");
+        assert_eq!(session.elements("b"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_killed_by_signal() {
+        let mut session = Session::new();
+        let status = Pipeline::run("sh -c 'kill -TERM $$'", &mut session).unwrap();
+        assert!(!status.success());
+        assert_eq!(session.status, 128 + 15);
+    }
 }
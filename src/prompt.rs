@@ -0,0 +1,296 @@
+use std::{
+    ffi::CStr,
+    path::Path,
+    sync::atomic::Ordering,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    exec::capture,
+    expand::{expand_here_doc, find_closing_paren},
+    history::format_time,
+    session::Session,
+};
+
+/// Marks the start of a part of a prompt that takes no space on the screen,
+/// from `\[`, as readline does.
+pub const START_IGNORE: char = '\x01';
+
+/// Marks the end of a part of a prompt that takes no space, from `\]`.
+pub const END_IGNORE: char = '\x02';
+
+/// Expands a prompt string such as `PS1`. Its backslash escapes are decoded
+/// as bash does, and in the rest parameters, arithmetic and command
+/// substitutions are expanded. The text of escapes and substitutions isn't
+/// expanded again.
+pub fn expand(ps: &str, session: &mut Session) -> String {
+    let chars = ps.chars().collect::<Vec<_>>();
+    let mut prompt = String::new();
+    // Text waiting to be expanded.
+    let mut text = String::new();
+
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+
+        if c == '$' && chars.get(i) == Some(&'(') && chars.get(i + 1) != Some(&'(') {
+            if let Some(end) = find_closing_paren(&chars, i) {
+                flush(&mut text, &mut prompt, session);
+                let command = chars[i + 1..end].iter().collect::<String>();
+                let output = capture(&command, session).unwrap_or_default();
+                prompt.push_str(output.trim_end_matches('\n'));
+                i = end + 1;
+                continue;
+            }
+        }
+
+        if c != '\\' || i == chars.len() {
+            text.push(c);
+            continue;
+        }
+
+        let (escape, len) = decode(&chars[i..], session);
+        match escape {
+            Some(escape) => {
+                flush(&mut text, &mut prompt, session);
+                prompt.push_str(&escape);
+            }
+            None => {
+                text.push('\\');
+                text.extend(&chars[i..i + len]);
+            }
+        }
+        i += len;
+    }
+
+    flush(&mut text, &mut prompt, session);
+    prompt
+}
+
+/// A prompt as it's written, without the markers around its non-printing
+/// parts.
+pub fn printed(prompt: &str) -> String {
+    prompt.replace([START_IGNORE, END_IGNORE], "")
+}
+
+/// Expands `text` onto the end of `prompt`, leaving it as it is if it can't
+/// be expanded.
+fn flush(text: &mut String, prompt: &mut String, session: &mut Session) {
+    match expand_here_doc(text, session) {
+        Ok(expanded) => prompt.push_str(&expanded),
+        Err(_) => prompt.push_str(text),
+    }
+    text.clear();
+}
+
+/// Decodes the escape at the start of `chars`, just after a backslash.
+/// Returns its text, or `None` if it isn't one, and how many characters it
+/// takes.
+fn decode(chars: &[char], session: &Session) -> (Option<String>, usize) {
+    let now = || {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs())
+    };
+    let time = |format: &str| Some(format_time(format, now()));
+
+    let escape = match chars[0] {
+        'a' => Some("\x07".to_string()),
+        'e' => Some("\x1b".to_string()),
+        'n' => Some("\n".to_string()),
+        'r' => Some("\r".to_string()),
+        '\\' => Some("\\".to_string()),
+        '[' => Some(START_IGNORE.to_string()),
+        ']' => Some(END_IGNORE.to_string()),
+        // SAFETY: `geteuid` always succeeds.
+        '$' => Some(
+            if unsafe { libc::geteuid() } == 0 {
+                "#"
+            } else {
+                "$"
+            }
+            .to_string(),
+        ),
+        '?' => Some(session.status.to_string()),
+        '!' => Some((session.history.first() + session.history.len()).to_string()),
+        'j' => Some(session.jobs.load(Ordering::Relaxed).to_string()),
+        's' => Some(
+            session
+                .name
+                .rsplit('/')
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+        ),
+        'v' => Some(
+            env!("CARGO_PKG_VERSION")
+                .rsplitn(2, '.')
+                .last()
+                .unwrap_or_default()
+                .to_string(),
+        ),
+        'V' => Some(env!("CARGO_PKG_VERSION").to_string()),
+        'u' => Some(
+            user_name()
+                .or_else(|| session.var("USER").map(String::from))
+                .unwrap_or_default(),
+        ),
+        'h' => Some(
+            host_name()
+                .split('.')
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+        ),
+        'H' => Some(host_name()),
+        'l' => Some(tty_name()),
+        'w' => Some(tilde(&session.cwd, session)),
+        'W' => Some(match tilde(&session.cwd, session).as_str() {
+            "/" => "/".to_string(),
+            dir => dir.rsplit('/').next().unwrap_or_default().to_string(),
+        }),
+        'd' => time("%a %b %d"),
+        't' => time("%H:%M:%S"),
+        'T' => time("%I:%M:%S"),
+        '@' => time("%I:%M %p"),
+        'A' => time("%H:%M"),
+        'D' if chars.get(1) == Some(&'{') => {
+            if let Some(end) = chars.iter().position(|&c| c == '}') {
+                let format = chars[2..end].iter().collect::<String>();
+                let format = if format.is_empty() { "%X" } else { &format };
+                return (time(format), end + 1);
+            }
+            None
+        }
+        '0'..='7' => {
+            let digits = chars
+                .iter()
+                .take(3)
+                .take_while(|c| ('0'..='7').contains(c))
+                .collect::<String>();
+            let c = u32::from_str_radix(&digits, 8)
+                .ok()
+                .and_then(char::from_u32);
+            return (c.map(String::from), digits.len());
+        }
+        _ => None,
+    };
+    (escape, 1)
+}
+
+/// A directory, with the home directory replaced by `~`.
+fn tilde(dir: &Path, session: &Session) -> String {
+    let dir = dir.to_string_lossy();
+    match session
+        .var("HOME")
+        .filter(|home| !home.is_empty() && *home != "/")
+    {
+        Some(home) if dir == home => "~".to_string(),
+        Some(home) => match dir.strip_prefix(home) {
+            Some(rest) if rest.starts_with('/') => format!("~{}", rest),
+            _ => dir.into_owned(),
+        },
+        None => dir.into_owned(),
+    }
+}
+
+/// The name of the current user, from the passwd database.
+fn user_name() -> Option<String> {
+    let mut passwd = std::mem::MaybeUninit::<libc::passwd>::uninit();
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut result = std::ptr::null_mut();
+
+    // SAFETY: all pointers are valid for the duration of the call, and `buf`
+    // outlives the strings in `passwd` that point into it.
+    let name = unsafe {
+        let status = libc::getpwuid_r(
+            libc::geteuid(),
+            passwd.as_mut_ptr(),
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        );
+        if status != 0 || result.is_null() {
+            return None;
+        }
+        CStr::from_ptr((*result).pw_name)
+    };
+
+    Some(name.to_string_lossy().into_owned())
+}
+
+fn host_name() -> String {
+    let mut buf = [0 as libc::c_char; 256];
+    // SAFETY: `gethostname` writes at most `buf.len()` bytes, and the last
+    // byte stays zero so the name is terminated.
+    unsafe {
+        if libc::gethostname(buf.as_mut_ptr(), buf.len() - 1) != 0 {
+            return String::new();
+        }
+        CStr::from_ptr(buf.as_ptr()).to_string_lossy().into_owned()
+    }
+}
+
+/// The base name of the terminal on stdin, such as `0` for `/dev/pts/0`.
+fn tty_name() -> String {
+    // SAFETY: `ttyname` returns null or a string that stays valid until the
+    // next call, which is copied straight away.
+    let name = unsafe {
+        let name = libc::ttyname(libc::STDIN_FILENO);
+        if name.is_null() {
+            return "tty".to_string();
+        }
+        CStr::from_ptr(name).to_string_lossy().into_owned()
+    };
+    name.rsplit('/').next().unwrap_or_default().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let mut session = Session::new();
+        session.vars.clear();
+        session.vars.insert("HOME".into(), "/home/me".into());
+        session.vars.insert("NAME".into(), "box".into());
+        session.cwd = PathBuf::from("/home/me/src/shell");
+        session.status = 3;
+        // SAFETY: `geteuid` always succeeds.
+        let root = if unsafe { libc::geteuid() } == 0 {
+            "#"
+        } else {
+            "$"
+        };
+        let mut expand = |ps: &str| expand(ps, &mut session);
+
+        assert_eq!(expand("\\w \\W> "), "~/src/shell shell> ");
+        assert_eq!(expand("[\\?] $NAME $((1 + 1))\\n"), "[3] box 2\n");
+        assert_eq!(
+            expand("\\[\\e[1;32m\\]ok\\[\\e[0m\\]"),
+            "\x01\x1b[1;32m\x02ok\x01\x1b[0m\x02"
+        );
+        assert_eq!(expand("\\\\$NAME \\$"), format!("\\box {}", root));
+        assert_eq!(expand("\\D{%Y}").len(), 4);
+        assert_eq!(expand("\\j \\s"), "0 shell");
+        assert_eq!(expand("trailing\\"), "trailing\\");
+
+        let output = super::expand("$(echo a; echo b) \\101\\z", &mut Session::new());
+        assert_eq!(output, "a\nb A\\z");
+    }
+
+    #[test]
+    fn test_tilde() {
+        let mut session = Session::new();
+        session.vars.insert("HOME".into(), "/home/me".into());
+        let tilde = |dir: &str| tilde(Path::new(dir), &session);
+        assert_eq!(tilde("/home/me"), "~");
+        assert_eq!(tilde("/home/me/a"), "~/a");
+        assert_eq!(tilde("/home/metoo"), "/home/metoo");
+        assert_eq!(tilde("/"), "/");
+    }
+}
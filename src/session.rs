@@ -0,0 +1,304 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    path::PathBuf,
+    sync::{atomic::AtomicUsize, Arc},
+};
+
+use crate::{ast::Ast, complete::Spec, error::Error, history::History, time::Usage};
+
+/// The state of a running shell. Subshells run against a clone, so nothing
+/// they change is seen by the parent.
+#[derive(Debug, Clone)]
+pub struct Session {
+    /// `$0`.
+    pub name: String,
+    /// The positional parameters, `$1` onwards.
+    pub args: Vec<String>,
+    pub cwd: PathBuf,
+    pub vars: HashMap<String, String>,
+    /// The indexed arrays assigned with `name=(...)`, by name. A name is
+    /// either a variable or an array, never both.
+    pub arrays: HashMap<String, Vec<String>>,
+    /// The names of variables passed to the environment of commands.
+    pub exported: HashSet<String>,
+    /// The names of variables that can't be assigned or unset.
+    pub readonly: HashSet<String>,
+    pub options: Options,
+    pub interactive: bool,
+    /// The exit status of the last command, `$?`.
+    pub status: i32,
+    /// Set by `exit`, stops execution of the rest of the current shell.
+    pub exit: Option<i32>,
+    /// Set while running a condition, such as the left of `&&`, where
+    /// `set -e` doesn't apply.
+    pub in_condition: bool,
+    /// The commands set by `trap`, by signal name without `SIG`, or `EXIT`,
+    /// `ERR`, `DEBUG` or `RETURN`. An empty command ignores the signal.
+    pub traps: HashMap<String, String>,
+    /// Set while a trap runs, so that no other trap fires inside it.
+    pub in_trap: bool,
+    pub history: History,
+    /// The aliases defined with `alias`, by name.
+    pub aliases: HashMap<String, String>,
+    /// The functions defined with `name() body`, by name.
+    pub functions: HashMap<String, Ast>,
+    /// How many function calls are running.
+    pub function_depth: usize,
+    /// How many files being run with `source` are running.
+    pub source_depth: usize,
+    /// Set by `return`, stops execution of the rest of the current function
+    /// or sourced file.
+    pub returning: Option<i32>,
+    /// The completions registered with `complete`, by command name.
+    pub completions: HashMap<String, Spec>,
+    /// How many background jobs are running, counted across copies of the
+    /// session.
+    pub jobs: Arc<AtomicUsize>,
+    /// Set while `time` runs, to add up the CPU time of the commands it
+    /// waits for.
+    pub usage: Option<Usage>,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session {
+            name: String::from("shell"),
+            args: vec![],
+            cwd: env::current_dir().unwrap_or_else(|_| PathBuf::from("/")),
+            vars: env::vars().collect(),
+            arrays: HashMap::new(),
+            exported: env::vars().map(|(name, _)| name).collect(),
+            readonly: HashSet::new(),
+            options: Options::default(),
+            interactive: false,
+            status: 0,
+            exit: None,
+            in_condition: false,
+            traps: HashMap::new(),
+            in_trap: false,
+            history: History::default(),
+            aliases: HashMap::new(),
+            functions: HashMap::new(),
+            function_depth: 0,
+            source_depth: 0,
+            returning: None,
+            completions: HashMap::new(),
+            jobs: Arc::default(),
+            usage: None,
+        }
+    }
+
+    /// The value of a variable, or the first element of an array.
+    pub fn var(&self, name: &str) -> Option<&str> {
+        match self.arrays.get(name) {
+            Some(elements) => elements.first().map(String::as_str),
+            None => self.vars.get(name).map(String::as_str),
+        }
+    }
+
+    /// Sets a variable, or the first element of an array.
+    pub fn set_var(&mut self, name: &str, value: String) -> Result<(), Error> {
+        if self.readonly.contains(name) {
+            return Err(Error::Readonly(name.to_string()));
+        }
+        match self.arrays.get_mut(name) {
+            Some(elements) if elements.is_empty() => elements.push(value),
+            Some(elements) => elements[0] = value,
+            None => {
+                self.vars.insert(name.to_string(), value);
+            }
+        }
+        Ok(())
+    }
+
+    /// The elements of an array, with a variable treated as an array of one.
+    pub fn elements(&self, name: &str) -> Vec<String> {
+        match self.arrays.get(name) {
+            Some(elements) => elements.clone(),
+            None => self.vars.get(name).cloned().into_iter().collect(),
+        }
+    }
+
+    /// Makes `name` an array of `elements`, replacing any variable of that
+    /// name. Arrays can't be exported.
+    pub fn set_array(&mut self, name: &str, elements: Vec<String>) -> Result<(), Error> {
+        if self.readonly.contains(name) {
+            return Err(Error::Readonly(name.to_string()));
+        }
+        self.vars.remove(name);
+        self.exported.remove(name);
+        self.arrays.insert(name.to_string(), elements);
+        Ok(())
+    }
+
+    pub fn unset_var(&mut self, name: &str) -> Result<(), Error> {
+        if self.readonly.contains(name) {
+            return Err(Error::Readonly(name.to_string()));
+        }
+        self.vars.remove(name);
+        self.arrays.remove(name);
+        self.exported.remove(name);
+        Ok(())
+    }
+
+    /// The exported variables that are set, which commands are run with.
+    pub fn env(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.vars
+            .iter()
+            .filter(|(name, _)| self.exported.contains(*name))
+    }
+}
+
+impl Default for Session {
+    fn default() -> Session {
+        Session::new()
+    }
+}
+
+/// Options toggled with `set` and `shopt`.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Emacs-style line editing.
+    pub emacs: bool,
+    /// Exit when a command fails, `-e`.
+    pub errexit: bool,
+    /// `>` doesn't overwrite existing files, `-C`.
+    pub noclobber: bool,
+    /// Disables pathname expansion, `-f`.
+    pub noglob: bool,
+    /// Expanding an unset parameter is an error, `-u`.
+    pub nounset: bool,
+    /// A pipeline fails if any command in it does.
+    pub pipefail: bool,
+    /// Vi-style line editing.
+    pub vi: bool,
+    /// Print commands to stderr before running them, `-x`.
+    pub xtrace: bool,
+
+    /// Wildcards match files starting with a `.`.
+    pub dotglob: bool,
+    /// Enables `?(...)`, `*(...)`, `+(...)`, `@(...)` and `!(...)`.
+    pub extglob: bool,
+    /// Patterns that match nothing are an error.
+    pub failglob: bool,
+    /// `**` matches files and directories recursively.
+    pub globstar: bool,
+    /// Pathname expansion ignores case.
+    pub nocaseglob: bool,
+    /// Patterns that match nothing expand to nothing.
+    pub nullglob: bool,
+}
+
+impl Options {
+    /// The options for `set -o`, with their single-letter flags.
+    pub const SET: &'static [(&'static str, Option<char>)] = &[
+        ("emacs", None),
+        ("errexit", Some('e')),
+        ("noclobber", Some('C')),
+        ("noglob", Some('f')),
+        ("nounset", Some('u')),
+        ("pipefail", None),
+        ("vi", None),
+        ("xtrace", Some('x')),
+    ];
+
+    pub const SHOPT: &'static [&'static str] = &[
+        "dotglob",
+        "extglob",
+        "failglob",
+        "globstar",
+        "nocaseglob",
+        "nullglob",
+    ];
+
+    pub fn set(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "emacs" => Some(&mut self.emacs),
+            "errexit" => Some(&mut self.errexit),
+            "noclobber" => Some(&mut self.noclobber),
+            "noglob" => Some(&mut self.noglob),
+            "nounset" => Some(&mut self.nounset),
+            "pipefail" => Some(&mut self.pipefail),
+            "vi" => Some(&mut self.vi),
+            "xtrace" => Some(&mut self.xtrace),
+            _ => None,
+        }
+    }
+
+    /// The name of the option set by a single-letter flag.
+    pub fn flag(flag: char) -> Option<&'static str> {
+        Options::SET
+            .iter()
+            .find(|(_, f)| *f == Some(flag))
+            .map(|(name, _)| *name)
+    }
+
+    pub fn shopt(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "dotglob" => Some(&mut self.dotglob),
+            "extglob" => Some(&mut self.extglob),
+            "failglob" => Some(&mut self.failglob),
+            "globstar" => Some(&mut self.globstar),
+            "nocaseglob" => Some(&mut self.nocaseglob),
+            "nullglob" => Some(&mut self.nullglob),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vars() {
+        let mut session = Session::new();
+        session.vars.clear();
+        session.exported.clear();
+
+        session.set_var("a", "1".to_string()).unwrap();
+        session.set_var("b", "2".to_string()).unwrap();
+        session.exported.insert("b".to_string());
+        session.exported.insert("c".to_string());
+        assert_eq!(
+            session.env().collect::<Vec<_>>(),
+            vec![(&"b".to_string(), &"2".to_string())]
+        );
+
+        session.readonly.insert("a".to_string());
+        assert_eq!(
+            session.set_var("a", "3".to_string()),
+            Err(Error::Readonly("a".to_string()))
+        );
+        assert_eq!(
+            session.unset_var("a"),
+            Err(Error::Readonly("a".to_string()))
+        );
+        assert_eq!(session.var("a"), Some("1"));
+
+        session.unset_var("b").unwrap();
+        assert_eq!(session.var("b"), None);
+        assert!(!session.exported.contains("b"));
+    }
+
+    #[test]
+    fn test_arrays() {
+        let mut session = Session::new();
+        session.set_var("a", "x".to_string()).unwrap();
+        assert_eq!(session.elements("a"), vec!["x"]);
+        assert!(session.elements("nope").is_empty());
+
+        session
+            .set_array("a", vec!["1".to_string(), "2".to_string()])
+            .unwrap();
+        assert_eq!(session.vars.get("a"), None);
+        assert_eq!(session.var("a"), Some("1"));
+        session.set_var("a", "3".to_string()).unwrap();
+        assert_eq!(session.elements("a"), vec!["3", "2"]);
+
+        session.unset_var("a").unwrap();
+        assert_eq!(session.var("a"), None);
+        assert!(session.arrays.is_empty());
+    }
+}
@@ -0,0 +1,194 @@
+use std::{
+    io,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// The signals the interactive shell handles itself, which its children get
+/// back with their default dispositions.
+const SIGNALS: [libc::c_int; 4] = [libc::SIGINT, libc::SIGQUIT, libc::SIGTSTP, libc::SIGTTOU];
+
+/// Signal names without the `SIG` prefix, as `trap` and `kill` take them.
+const NAMES: &[(&str, libc::c_int)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF),
+    ("WINCH", libc::SIGWINCH),
+    ("SYS", libc::SIGSYS),
+];
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// The caught signals that have arrived, by number. The handler only sets
+/// these, so that traps run between commands rather than in the handler.
+static PENDING: [AtomicBool; 65] = [const { AtomicBool::new(false) }; 65];
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    if signal == libc::SIGINT {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+    }
+    if let Some(pending) = PENDING.get(signal as usize) {
+        pending.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Keeps the terminal's signals from stopping or killing an interactive
+/// shell. SIGINT is caught, so that Ctrl-C only interrupts the line being
+/// read or the foreground command, and SIGQUIT, SIGTSTP and SIGTTOU are
+/// ignored.
+pub fn init_interactive() -> io::Result<()> {
+    for signal in SIGNALS {
+        reset(signal, true)?;
+    }
+    Ok(())
+}
+
+/// Restores the default dispositions of the signals the shell handles,
+/// other than those in `ignored`. This is async-signal-safe, for use in a
+/// child between fork and exec.
+pub fn restore_defaults(ignored: &[libc::c_int]) -> io::Result<()> {
+    for signal in SIGNALS {
+        if !ignored.contains(&signal) {
+            set_handler(signal, libc::SIG_DFL)?;
+        }
+    }
+    Ok(())
+}
+
+/// Catches `signal`, so that its arrival is seen by [`take_pending`].
+pub fn catch(signal: libc::c_int) -> io::Result<()> {
+    set_handler(signal, on_signal as *const () as libc::sighandler_t)
+}
+
+pub fn ignore(signal: libc::c_int) -> io::Result<()> {
+    set_handler(signal, libc::SIG_IGN)
+}
+
+/// Gives `signal` back the disposition the shell starts with.
+pub fn reset(signal: libc::c_int, interactive: bool) -> io::Result<()> {
+    let handler = match signal {
+        libc::SIGINT if interactive => on_signal as *const () as libc::sighandler_t,
+        libc::SIGQUIT | libc::SIGTSTP | libc::SIGTTOU if interactive => libc::SIG_IGN,
+        _ => libc::SIG_DFL,
+    };
+    set_handler(signal, handler)
+}
+
+/// Whether `signal` has arrived since this was last called for it.
+pub fn take_pending(signal: libc::c_int) -> bool {
+    PENDING
+        .get(signal as usize)
+        .is_some_and(|pending| pending.swap(false, Ordering::SeqCst))
+}
+
+/// Whether SIGINT has arrived since the flag was last cleared.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+pub fn clear_interrupted() {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+}
+
+/// The number of a signal named with or without `SIG`, in any case.
+pub fn number(name: &str) -> Option<libc::c_int> {
+    let name = name.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    NAMES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, signal)| *signal)
+}
+
+/// The name of a signal without `SIG`.
+pub fn name(signal: libc::c_int) -> Option<&'static str> {
+    NAMES
+        .iter()
+        .find(|(_, s)| *s == signal)
+        .map(|(name, _)| *name)
+}
+
+/// All the signals by name, in numerical order.
+pub fn names() -> Vec<(&'static str, libc::c_int)> {
+    let mut names = NAMES.to_vec();
+    names.sort_by_key(|(_, signal)| *signal);
+    names
+}
+
+fn set_handler(signal: libc::c_int, handler: libc::sighandler_t) -> io::Result<()> {
+    // SAFETY: the action is fully initialised, and the handler only stores
+    // to atomics, which is async-signal-safe. Without `SA_RESTART`, a
+    // blocking read is interrupted so that Ctrl-C can cancel the line being
+    // read.
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(signal, &action, std::ptr::null_mut()) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interrupted() {
+        clear_interrupted();
+        reset(libc::SIGINT, true).unwrap();
+        assert!(!interrupted());
+
+        unsafe { libc::raise(libc::SIGINT) };
+        assert!(interrupted());
+
+        clear_interrupted();
+        reset(libc::SIGINT, false).unwrap();
+        assert!(!interrupted());
+    }
+
+    #[test]
+    fn test_pending() {
+        catch(libc::SIGWINCH).unwrap();
+        assert!(!take_pending(libc::SIGWINCH));
+
+        unsafe { libc::raise(libc::SIGWINCH) };
+        unsafe { libc::raise(libc::SIGWINCH) };
+        assert!(take_pending(libc::SIGWINCH));
+        assert!(!take_pending(libc::SIGWINCH));
+        reset(libc::SIGWINCH, false).unwrap();
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(number("INT"), Some(libc::SIGINT));
+        assert_eq!(number("sigterm"), Some(libc::SIGTERM));
+        assert_eq!(number("NOPE"), None);
+        assert_eq!(name(libc::SIGHUP), Some("HUP"));
+        assert_eq!(names()[0], ("HUP", 1));
+    }
+}
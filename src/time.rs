@@ -0,0 +1,192 @@
+use std::{
+    io,
+    os::unix::process::ExitStatusExt,
+    process::{Child, ExitStatus},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// bash's default `TIMEFORMAT`.
+pub const DEFAULT_FORMAT: &str = "\nreal\t%3lR\nuser\t%3lU\nsys\t%3lS";
+
+/// The format used by `time -p`.
+pub const POSIX_FORMAT: &str = "real %2R\nuser %2U\nsys %2S";
+
+/// Elapsed real time, and CPU time used by child processes, over some span.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Times {
+    pub real: Duration,
+    pub user: Duration,
+    pub sys: Duration,
+}
+
+/// The user and sys CPU time used by the processes of a command being timed,
+/// added to as each one is waited for. Clones share the totals, so processes
+/// waited for on other threads, such as the left of a pipe, count too.
+#[derive(Debug, Clone, Default)]
+pub struct Usage(Arc<Mutex<(Duration, Duration)>>);
+
+impl Usage {
+    pub fn add(&self, user: Duration, sys: Duration) {
+        let mut usage = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        usage.0 += user;
+        usage.1 += sys;
+    }
+
+    /// The user and sys time so far.
+    pub fn get(&self) -> (Duration, Duration) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Waits for `child` to exit with `wait4`, adding the CPU time it used to
+/// `usage`.
+pub fn wait(child: &mut Child, usage: &Usage) -> io::Result<ExitStatus> {
+    // The child only sees end-of-file on a piped stdin once it's closed.
+    drop(child.stdin.take());
+
+    let pid = child.id() as libc::pid_t;
+    let mut status = 0;
+    let mut rusage = std::mem::MaybeUninit::<libc::rusage>::zeroed();
+    loop {
+        // SAFETY: `status` and `rusage` are valid and writable, and `pid` is
+        // a child that hasn't been reaped.
+        let result = unsafe { libc::wait4(pid, &mut status, 0, rusage.as_mut_ptr()) };
+        if result != -1 {
+            break;
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+
+    // SAFETY: `wait4` filled in `rusage` when it succeeded.
+    let rusage = unsafe { rusage.assume_init() };
+    usage.add(timeval(rusage.ru_utime), timeval(rusage.ru_stime));
+    Ok(ExitStatus::from_raw(status))
+}
+
+fn timeval(tv: libc::timeval) -> Duration {
+    Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000)
+}
+
+impl Times {
+    /// Formats the times like bash's `TIMEFORMAT`: `%[p][l]R`, `%[p][l]U` and
+    /// `%[p][l]S` for real, user and sys time, `%P` for the CPU percentage and
+    /// `%%` for a literal `%`. `p` is the number of decimal places, up to 3.
+    pub fn format(&self, format: &str) -> String {
+        let mut output = String::new();
+        let mut chars = format.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                output.push(c);
+                continue;
+            }
+
+            let mut precision = 3;
+            if let Some(p) = chars.peek().and_then(|c| c.to_digit(10)) {
+                precision = p.min(3) as usize;
+                chars.next();
+            }
+            let long = chars.next_if_eq(&'l').is_some();
+
+            let duration = match chars.next() {
+                Some('R') => self.real,
+                Some('U') => self.user,
+                Some('S') => self.sys,
+                Some('P') => {
+                    let cpu = (self.user + self.sys).as_secs_f64();
+                    let real = self.real.as_secs_f64();
+                    let percent = if real > 0.0 { cpu / real * 100.0 } else { 0.0 };
+                    output.push_str(&format!("{:.2}", percent));
+                    continue;
+                }
+                Some('%') => {
+                    output.push('%');
+                    continue;
+                }
+                Some(c) => {
+                    output.push('%');
+                    output.push(c);
+                    continue;
+                }
+                None => {
+                    output.push('%');
+                    break;
+                }
+            };
+
+            // Like bash, the time is truncated to the precision, not rounded.
+            let scale = 10u128.pow(precision as u32);
+            let units = duration.as_nanos() * scale / 1_000_000_000;
+            let secs = units / scale;
+            let fraction = match precision {
+                0 => String::new(),
+                _ => format!(".{:01$}", units % scale, precision),
+            };
+            if long {
+                output.push_str(&format!("{}m{}{}s", secs / 60, secs % 60, fraction));
+            } else {
+                output.push_str(&format!("{}{}", secs, fraction));
+            }
+        }
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+
+    const BUSY: &str = "i=0; while [ $i -lt 100000 ]; do i=$((i + 1)); done";
+
+    #[test]
+    fn test_wait() {
+        let sh = |script: &str| Command::new("sh").args(["-c", script]).spawn().unwrap();
+
+        let total = |usage: &Usage| {
+            let (user, sys) = usage.get();
+            user + sys
+        };
+
+        // The usage of another child, reaped separately, isn't counted.
+        let usage = Usage::default();
+        let busy_usage = Usage::default();
+        let mut busy = sh(BUSY);
+        let mut short = sh("true; exit 3");
+        wait(&mut busy, &busy_usage).unwrap();
+        assert_eq!(wait(&mut short, &usage).unwrap().code(), Some(3));
+        assert!(total(&busy_usage) > Duration::ZERO);
+        assert!(total(&usage) < total(&busy_usage));
+    }
+
+    #[test]
+    fn test_format() {
+        let times = Times {
+            real: Duration::from_millis(61_500),
+            user: Duration::from_millis(250),
+            sys: Duration::from_millis(5),
+        };
+        assert_eq!(
+            times.format(DEFAULT_FORMAT),
+            "\nreal\t1m1.500s\nuser\t0m0.250s\nsys\t0m0.005s"
+        );
+        assert_eq!(
+            times.format(POSIX_FORMAT),
+            "real 61.50\nuser 0.25\nsys 0.00"
+        );
+        assert_eq!(times.format("%0R %% %x"), "61 % %x");
+        assert_eq!(times.format(""), "");
+
+        let times = Times {
+            real: Duration::from_micros(119_999_900),
+            ..times
+        };
+        assert_eq!(times.format("%3lR %1R"), "1m59.999s 119.9");
+    }
+}